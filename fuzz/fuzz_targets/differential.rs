@@ -0,0 +1,31 @@
+//! Differential fuzz target comparing the library lexer with the hand-made lexer.
+//!
+//! The first byte selects how the remaining bytes become source code: even values build the
+//! input from the Oat token alphabet, odd values fold the bytes into ASCII characters.
+
+#![no_main]
+
+use lex4oat::fuzz::{alphabet_input, ascii_input, check_backends};
+use lex4oat::lex4oat::CompiledLexer;
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+/// The hand-made lexer compiled from `src/oat.l`, built once per fuzzing process.
+static COMPILED: OnceLock<CompiledLexer> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&mode, data)) = data.split_first() else {
+        return;
+    };
+    let input = if mode % 2 == 0 {
+        alphabet_input(data)
+    } else {
+        ascii_input(data)
+    };
+
+    let compiled =
+        COMPILED.get_or_init(|| CompiledLexer::from_spec(include_str!("../../src/oat.l")).unwrap());
+    if let Err(report) = check_backends(&input, compiled) {
+        panic!("Lexers disagree on {:?}\n{}", input, report);
+    }
+});
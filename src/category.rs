@@ -0,0 +1,90 @@
+//! Module for sorting token kinds into the categories editors color them by.
+//! Both lexers name their tokens after the rules of `oat.l`, so the categories are keyed by
+//! those kinds. Kinds of other specifications have no category and are left uncolored.
+
+use crate::channel::TRIVIA_KINDS;
+use crate::escape::STRING_KIND;
+use crate::literal::INT_KIND;
+
+/// The kind of the tokens holding identifiers in `oat.l`.
+pub const IDENTIFIER_KIND: &str = "ID";
+
+/// The kind of the tokens holding documentation comments in `oat.l`.
+pub const DOC_COMMENT_KIND: &str = "DOC_COMMENT";
+
+/// What a token is, as far as coloring it goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// A keyword, including the literals `true`, `false` and `null`.
+    Keyword,
+    /// A built-in type name such as `int`.
+    Type,
+    /// A string literal.
+    String,
+    /// An integer literal.
+    Number,
+    /// A line, block or documentation comment.
+    Comment,
+    /// An operator or a delimiter.
+    Operator,
+    /// An identifier.
+    Identifier,
+}
+
+impl Category {
+    /// Every category, in the order of their indices.
+    pub const ALL: [Category; 7] = [
+        Category::Keyword,
+        Category::Type,
+        Category::String,
+        Category::Number,
+        Category::Comment,
+        Category::Operator,
+        Category::Identifier,
+    ];
+
+    /// Returns the category of a token kind.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The kind of a token lexed with `oat.l`.
+    ///
+    /// # Returns
+    ///
+    /// The category of the kind, or `None` if `oat.l` has no such kind.
+    pub fn of_kind(kind: &str) -> Option<Category> {
+        let category = match kind {
+            IDENTIFIER_KIND => Category::Identifier,
+            STRING_KIND => Category::String,
+            INT_KIND => Category::Number,
+            "GLOBAL" | "VAR" | "NULL" | "TRUE" | "FALSE" | "NEW" | "RETURN" | "FOR" | "WHILE"
+            | "IF" | "IFQ" | "ELSE" | "STRUCT" | "LENGTH" => Category::Keyword,
+            "VOID" | "INT" | "BOOL" | "STRING" => Category::Type,
+            "LPAREN" | "RPAREN" | "LBRACE" | "RBRACE" | "COMMA" | "LBRACKET" | "RBRACKET"
+            | "SEMICOLON" | "ASSIGN" | "DOT" | "QUESTION" | "ARROW" | "TIMES" | "PLUS"
+            | "MINUS" | "MOD" | "LSHIFT" | "RSHIFT" | "ARSHIFT" | "LT" | "LE" | "GT" | "GE"
+            | "EQ" | "NE" | "AND" | "OR" | "BAND" | "BOR" | "NOT" | "BANG" => Category::Operator,
+            _ if TRIVIA_KINDS.contains(&kind) => Category::Comment,
+            _ => return None,
+        };
+        Some(category)
+    }
+
+    /// Returns the position of the category in `Category::ALL`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Returns the lowercase name of the category.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Keyword => "keyword",
+            Category::Type => "type",
+            Category::String => "string",
+            Category::Number => "number",
+            Category::Comment => "comment",
+            Category::Operator => "operator",
+            Category::Identifier => "identifier",
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Module for splitting token streams into channels.
+//! Comments and the text between tokens are trivia: a parser only wants the tokens of the
+//! main channel, while formatters and documentation tools also need the trivia. Every main
+//! token carries the trivia in front of it, and the trivia after the last token belongs to
+//! the end of the input. The trivia and the main tokens are lossless: their texts concatenate
+//! to the input byte for byte.
+
+use crate::token::{LineIndex, Span, Token};
+use clap::ValueEnum;
+
+/// The token kinds of `oat.l` that are trivia.
+pub const TRIVIA_KINDS: &[&str] = &["COMMENT", "DOC_COMMENT"];
+
+/// The kind of the trivia covering whitespace between two tokens.
+pub const WHITESPACE_KIND: &str = "WHITESPACE";
+
+/// The kind of the trivia covering other text between two tokens, which no rule matched or
+/// a skipped rule matched.
+pub const UNKNOWN_KIND: &str = "UNKNOWN";
+
+/// The channels of a token stream that can be printed.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// Every token as lexed, comments included.
+    All,
+    /// Only the tokens a parser sees.
+    Main,
+    /// Only the comments, whitespace and unmatched text.
+    Trivia,
+    /// Every token and all trivia, whose texts concatenate to the input.
+    Lossless,
+}
+
+impl Channel {
+    /// Selects the tokens of this channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The lexed input, used to recover the whitespace between tokens.
+    /// * `tokens` - The tokens lexed from `input`.
+    ///
+    /// # Returns
+    ///
+    /// The tokens of the channel, in input order.
+    pub fn select(self, input: &str, tokens: Vec<Token>) -> Vec<Token> {
+        match self {
+            Channel::All => tokens,
+            Channel::Main => tokens
+                .into_iter()
+                .filter(|token| !is_trivia(&token.kind))
+                .collect(),
+            Channel::Trivia => Channels::split(input, tokens).trivia().cloned().collect(),
+            Channel::Lossless => Channels::split(input, tokens).all().cloned().collect(),
+        }
+    }
+}
+
+/// A token of the main channel with the trivia in front of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenWithTrivia {
+    /// The comments and whitespace between the previous main token and this one.
+    pub leading: Vec<Token>,
+    /// The token itself.
+    pub token: Token,
+}
+
+/// A token stream split into the main channel and its trivia.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Channels {
+    /// The tokens of the main channel, each with its leading trivia.
+    pub tokens: Vec<TokenWithTrivia>,
+    /// The trivia after the last main token.
+    pub trailing: Vec<Token>,
+}
+
+impl Channels {
+    /// Splits a token stream into channels.
+    ///
+    /// Comments become trivia, and the text between two tokens becomes `WHITESPACE` and
+    /// `UNKNOWN` trivia tokens, so the trivia and the main tokens together cover the input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The lexed input.
+    /// * `tokens` - The tokens lexed from `input`.
+    ///
+    /// # Returns
+    ///
+    /// The main tokens with their leading trivia, and the trailing trivia.
+    pub fn split(input: &str, tokens: Vec<Token>) -> Channels {
+        let lines = LineIndex::new(input);
+        let mut channels = Channels {
+            tokens: Vec::new(),
+            trailing: Vec::new(),
+        };
+        let mut end = 0;
+        for token in tokens {
+            if token.span.start > end {
+                let gap = Span::new(end, token.span.start);
+                channels.trailing.extend(gap_trivia(input, gap, &lines));
+            }
+            end = end.max(token.span.end);
+            if is_trivia(&token.kind) {
+                channels.trailing.push(token);
+            } else {
+                let leading = std::mem::take(&mut channels.trailing);
+                channels.tokens.push(TokenWithTrivia { leading, token });
+            }
+        }
+        if end < input.len() {
+            let gap = Span::new(end, input.len());
+            channels.trailing.extend(gap_trivia(input, gap, &lines));
+        }
+        channels
+    }
+
+    /// Returns the tokens a parser sees, in input order.
+    pub fn main(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().map(|token| &token.token)
+    }
+
+    /// Returns the comments, whitespace and unmatched text, in input order.
+    pub fn trivia(&self) -> impl Iterator<Item = &Token> {
+        self.tokens
+            .iter()
+            .flat_map(|token| &token.leading)
+            .chain(&self.trailing)
+    }
+
+    /// Returns every main token and all trivia, in input order.
+    pub fn all(&self) -> impl Iterator<Item = &Token> {
+        self.tokens
+            .iter()
+            .flat_map(|token| token.leading.iter().chain([&token.token]))
+            .chain(&self.trailing)
+    }
+
+    /// Rebuilds the input from the texts of all tokens.
+    ///
+    /// # Returns
+    ///
+    /// The input the channels were split from, byte for byte.
+    pub fn source(&self) -> String {
+        self.all().map(|token| token.lexeme.as_str()).collect()
+    }
+}
+
+/// Covers the text between two tokens with trivia tokens.
+///
+/// # Arguments
+///
+/// * `input` - The lexed input.
+/// * `gap` - The part of the input between the tokens.
+/// * `lines` - The line index of the input.
+///
+/// # Returns
+///
+/// One `WHITESPACE` or `UNKNOWN` token for every run of whitespace or other text.
+fn gap_trivia(input: &str, gap: Span, lines: &LineIndex) -> Vec<Token> {
+    let mut trivia = Vec::new();
+    let mut run_start = gap.start;
+    let mut chars = input[gap.start..gap.end].char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        let is_whitespace = c.is_whitespace();
+        let ends_run = chars
+            .peek()
+            .is_none_or(|&(_, next)| next.is_whitespace() != is_whitespace);
+        if ends_run {
+            let end = chars.peek().map_or(gap.end, |&(next, _)| gap.start + next);
+            let kind = if is_whitespace {
+                WHITESPACE_KIND
+            } else {
+                UNKNOWN_KIND
+            };
+            let span = Span::new(run_start, end);
+            trivia.push(Token::new(
+                kind.to_string(),
+                input[span.start..span.end].to_string(),
+                span,
+                lines,
+            ));
+            run_start = end;
+        }
+    }
+    trivia
+}
+
+/// Checks whether tokens of a kind are trivia.
+///
+/// # Arguments
+///
+/// * `kind` - The token kind.
+///
+/// # Returns
+///
+/// `true` for comments, whitespace and unmatched text.
+pub fn is_trivia(kind: &str) -> bool {
+    kind == WHITESPACE_KIND || kind == UNKNOWN_KIND || TRIVIA_KINDS.contains(&kind)
+}
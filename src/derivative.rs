@@ -0,0 +1,653 @@
+//! Module for building DFAs from regular expression derivatives.
+//! Every rule of a specification is parsed into a `Regex`, and the DFA states are tuples of
+//! rule derivatives: the state reached after reading a string holds, for every rule, the
+//! expression matching the rest of the rule. Smart constructors keep the expressions in a
+//! normal form so that only finitely many states arise. This gives a second DFA builder,
+//! independent of the Thompson construction in `nfa.rs` and the subset construction in
+//! `dfa.rs`, to check them against.
+
+use crate::dfa::{Dfa, ModeStart};
+use crate::nfa::{
+    INITIAL, NestedComment, Rule, RuleRegex, SetExpr, parse_spec, split_top_level, unescape,
+};
+use crate::node::Node;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::rc::Rc;
+
+/// A regular expression over characters, kept in normal form by its smart constructors.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Regex {
+    /// Matches no string at all.
+    Empty,
+    /// Matches only the empty string.
+    Epsilon,
+    /// Matches a single character from a non-empty set.
+    Class(BTreeSet<char>),
+    /// Matches the first expression followed by the second.
+    Concat(Rc<Regex>, Rc<Regex>),
+    /// Matches what any of at least two expressions matches.
+    Alt(BTreeSet<Rc<Regex>>),
+    /// Matches what all of at least two expressions match.
+    And(BTreeSet<Rc<Regex>>),
+    /// Matches zero or more repetitions of the expression.
+    Star(Rc<Regex>),
+    /// Matches every string the expression does not match.
+    Not(Rc<Regex>),
+}
+
+impl Regex {
+    /// Returns the expression matching no string.
+    pub fn empty() -> Rc<Regex> {
+        Rc::new(Regex::Empty)
+    }
+
+    /// Returns the expression matching only the empty string.
+    pub fn epsilon() -> Rc<Regex> {
+        Rc::new(Regex::Epsilon)
+    }
+
+    /// Returns the expression matching every string.
+    pub fn universal() -> Rc<Regex> {
+        Regex::complement(Regex::empty())
+    }
+
+    /// Returns the expression matching a single character from a set.
+    pub fn class(chars: BTreeSet<char>) -> Rc<Regex> {
+        if chars.is_empty() {
+            Regex::empty()
+        } else {
+            Rc::new(Regex::Class(chars))
+        }
+    }
+
+    /// Returns the concatenation of two expressions.
+    ///
+    /// Concatenations are kept right-nested, and concatenating with `Empty` or `Epsilon` is
+    /// simplified away.
+    pub fn concat(first: Rc<Regex>, second: Rc<Regex>) -> Rc<Regex> {
+        match (&*first, &*second) {
+            (Regex::Empty, _) | (_, Regex::Empty) => Regex::empty(),
+            (Regex::Epsilon, _) => second,
+            (_, Regex::Epsilon) => first,
+            (Regex::Concat(head, tail), _) => {
+                Regex::concat(head.clone(), Regex::concat(tail.clone(), second))
+            }
+            _ => Rc::new(Regex::Concat(first, second)),
+        }
+    }
+
+    /// Returns the alternation of two expressions.
+    ///
+    /// Nested alternations are flattened into one set of alternatives, `Empty` alternatives are
+    /// dropped, and an alternative matching every string absorbs the others.
+    pub fn alt(first: Rc<Regex>, second: Rc<Regex>) -> Rc<Regex> {
+        let mut members = BTreeSet::new();
+        for regex in [first, second] {
+            match &*regex {
+                Regex::Alt(inner) => members.extend(inner.iter().cloned()),
+                Regex::Empty => {}
+                _ => {
+                    members.insert(regex);
+                }
+            }
+        }
+        if members.contains(&Regex::universal()) {
+            return Regex::universal();
+        }
+        Regex::from_members(members, Regex::empty(), Regex::Alt)
+    }
+
+    /// Returns the intersection of two expressions.
+    ///
+    /// Nested intersections are flattened into one set of members, members matching every
+    /// string are dropped, and an `Empty` member makes the whole intersection `Empty`.
+    pub fn and(first: Rc<Regex>, second: Rc<Regex>) -> Rc<Regex> {
+        let universal = Regex::universal();
+        let mut members = BTreeSet::new();
+        for regex in [first, second] {
+            match &*regex {
+                Regex::And(inner) => members.extend(inner.iter().cloned()),
+                Regex::Empty => return Regex::empty(),
+                _ if regex == universal => {}
+                _ => {
+                    members.insert(regex);
+                }
+            }
+        }
+        Regex::from_members(members, universal, Regex::And)
+    }
+
+    /// Returns zero or more repetitions of an expression.
+    pub fn star(regex: Rc<Regex>) -> Rc<Regex> {
+        match &*regex {
+            Regex::Empty | Regex::Epsilon => Regex::epsilon(),
+            Regex::Star(_) => regex,
+            _ => Rc::new(Regex::Star(regex)),
+        }
+    }
+
+    /// Returns one or more repetitions of an expression.
+    pub fn plus(regex: Rc<Regex>) -> Rc<Regex> {
+        Regex::concat(regex.clone(), Regex::star(regex))
+    }
+
+    /// Returns an expression matching an expression or the empty string.
+    pub fn opt(regex: Rc<Regex>) -> Rc<Regex> {
+        Regex::alt(regex, Regex::epsilon())
+    }
+
+    /// Returns the complement of an expression.
+    pub fn complement(regex: Rc<Regex>) -> Rc<Regex> {
+        match &*regex {
+            Regex::Not(inner) => inner.clone(),
+            _ => Rc::new(Regex::Not(regex)),
+        }
+    }
+
+    /// Builds an alternation or intersection from its normalized members.
+    ///
+    /// # Arguments
+    ///
+    /// * `members` - The members, without nested expressions of the same kind.
+    /// * `neutral` - The expression to return if there are no members.
+    /// * `combine` - The variant to build if there are several members.
+    ///
+    /// # Returns
+    ///
+    /// The combined expression.
+    fn from_members(
+        mut members: BTreeSet<Rc<Regex>>,
+        neutral: Rc<Regex>,
+        combine: fn(BTreeSet<Rc<Regex>>) -> Regex,
+    ) -> Rc<Regex> {
+        match members.len() {
+            0 => neutral,
+            1 => members.pop_first().unwrap(),
+            _ => Rc::new(combine(members)),
+        }
+    }
+
+    /// Checks whether the expression matches the empty string.
+    pub fn nullable(&self) -> bool {
+        match self {
+            Regex::Empty | Regex::Class(_) => false,
+            Regex::Epsilon | Regex::Star(_) => true,
+            Regex::Concat(first, second) => first.nullable() && second.nullable(),
+            Regex::Alt(members) => members.iter().any(|regex| regex.nullable()),
+            Regex::And(members) => members.iter().all(|regex| regex.nullable()),
+            Regex::Not(regex) => !regex.nullable(),
+        }
+    }
+
+    /// Computes the Brzozowski derivative of an expression with respect to a character.
+    ///
+    /// # Arguments
+    ///
+    /// * `regex` - The expression.
+    /// * `ch` - The character read.
+    ///
+    /// # Returns
+    ///
+    /// An expression matching every string `s` for which `regex` matches `ch` followed by `s`.
+    pub fn derivative(regex: &Rc<Regex>, ch: char) -> Rc<Regex> {
+        match &**regex {
+            Regex::Empty | Regex::Epsilon => Regex::empty(),
+            Regex::Class(chars) => {
+                if chars.contains(&ch) {
+                    Regex::epsilon()
+                } else {
+                    Regex::empty()
+                }
+            }
+            Regex::Concat(first, second) => {
+                let derived = Regex::concat(Regex::derivative(first, ch), second.clone());
+                if first.nullable() {
+                    Regex::alt(derived, Regex::derivative(second, ch))
+                } else {
+                    derived
+                }
+            }
+            Regex::Alt(members) => members
+                .iter()
+                .map(|member| Regex::derivative(member, ch))
+                .fold(Regex::empty(), Regex::alt),
+            Regex::And(members) => members
+                .iter()
+                .map(|member| Regex::derivative(member, ch))
+                .fold(Regex::universal(), Regex::and),
+            Regex::Star(inner) => Regex::concat(Regex::derivative(inner, ch), regex.clone()),
+            Regex::Not(inner) => Regex::complement(Regex::derivative(inner, ch)),
+        }
+    }
+
+    /// Collects every character mentioned in a class of the expression.
+    ///
+    /// All characters that are not mentioned have the same derivative.
+    fn collect_chars(&self, chars: &mut BTreeSet<char>) {
+        match self {
+            Regex::Empty | Regex::Epsilon => {}
+            Regex::Class(set) => chars.extend(set),
+            Regex::Concat(first, second) => {
+                first.collect_chars(chars);
+                second.collect_chars(chars);
+            }
+            Regex::Alt(members) | Regex::And(members) => {
+                for member in members {
+                    member.collect_chars(chars);
+                }
+            }
+            Regex::Star(inner) | Regex::Not(inner) => inner.collect_chars(chars),
+        }
+    }
+
+    /// Parses a rule regex in the syntax of the hand-made lexer.
+    ///
+    /// The syntax and its corner cases follow `Nfa::parse_regex`: groups end at the first `)`
+    /// and sets at the first `]`, a repetition operator applies to the last atom and is
+    /// ignored without one, `\s` stands for whitespace and any other escaped character for
+    /// itself, and negated sets cover the ASCII characters. Where set operators are declared,
+    /// branches using them are parsed into a `SetExpr` first, whose operations map onto `and`
+    /// and `complement`.
+    ///
+    /// # Arguments
+    ///
+    /// * `regex` - The regex of a rule.
+    /// * `set_operators` - Whether `&` and `~` are set operators rather than characters.
+    ///
+    /// # Returns
+    ///
+    /// The parsed expression.
+    pub fn parse(regex: &str, set_operators: bool) -> Rc<Regex> {
+        let (branches, _) = split_top_level(regex, &['|']);
+        if set_operators
+            && branches
+                .iter()
+                .any(|branch| SetExpr::parse(branch).is_some())
+        {
+            return branches
+                .iter()
+                .map(|branch| match SetExpr::parse(branch) {
+                    Some(expr) => Regex::from_set_expr(&expr),
+                    None => Regex::parse(branch, true),
+                })
+                .fold(Regex::empty(), Regex::alt);
+        }
+
+        let mut chars = regex.chars();
+        let mut alternatives = Vec::new();
+        // The atoms of the current branch before its last atom, and the last atom itself.
+        let mut branch = Regex::epsilon();
+        let mut atom: Option<Rc<Regex>> = None;
+
+        while let Some(c) = chars.next() {
+            let next = match c {
+                '|' => {
+                    let last = atom.take().unwrap_or_else(Regex::epsilon);
+                    alternatives.push(Regex::concat(branch, last));
+                    branch = Regex::epsilon();
+                    continue;
+                }
+                '*' | '+' | '?' => {
+                    atom = atom.map(|atom| match c {
+                        '*' => Regex::star(atom),
+                        '+' => Regex::plus(atom),
+                        _ => Regex::opt(atom),
+                    });
+                    continue;
+                }
+                '\\' => match chars.next() {
+                    Some('s') => Regex::class(WHITESPACE.iter().copied().collect()),
+                    Some(escaped) => Regex::class(BTreeSet::from([unescape(escaped)])),
+                    None => break,
+                },
+                // As in `lrlex`, '.' matches any character, line breaks included.
+                '.' => parse_set("^"),
+                '[' => parse_set(&chars.by_ref().take_while(|&c| c != ']').collect::<String>()),
+                '(' => Regex::parse(
+                    &chars.by_ref().take_while(|&c| c != ')').collect::<String>(),
+                    set_operators,
+                ),
+                _ => Regex::class(BTreeSet::from([c])),
+            };
+            if let Some(previous) = atom.replace(next) {
+                branch = Regex::concat(branch, previous);
+            }
+        }
+
+        let last = atom.unwrap_or_else(Regex::epsilon);
+        alternatives.push(Regex::concat(branch, last));
+        alternatives.into_iter().fold(Regex::empty(), Regex::alt)
+    }
+
+    /// Parses the regex of a rule, with its anchors and trailing context.
+    ///
+    /// The DFA accepts a rule's token together with its trailing context, so `/` becomes a
+    /// concatenation and `$` a trailing newline. A rule anchored with `^` starts with the
+    /// `LINE_START` marker, which `construct_dfa` only lets it skip from the line start state.
+    ///
+    /// # Arguments
+    ///
+    /// * `regex` - The regex of a rule.
+    /// * `set_operators` - Whether `&` and `~` are set operators rather than characters.
+    ///
+    /// # Returns
+    ///
+    /// The parsed expression.
+    pub fn parse_rule(regex: &str, set_operators: bool) -> Rc<Regex> {
+        let rule = RuleRegex::parse(regex);
+        let mut parsed = Regex::parse(&rule.token, set_operators);
+        if let Some(context) = &rule.context {
+            parsed = Regex::concat(parsed, Regex::parse(context, set_operators));
+        }
+        if rule.line_end {
+            parsed = Regex::concat(parsed, Regex::class(BTreeSet::from(['\n'])));
+        }
+        if rule.line_start {
+            parsed = Regex::concat(Regex::class(BTreeSet::from([LINE_START])), parsed);
+        }
+        parsed
+    }
+
+    /// Returns the rest of a rule anchored with `^`, or `None` if the rule is not anchored.
+    fn strip_line_start(regex: &Rc<Regex>) -> Option<Rc<Regex>> {
+        let marker = BTreeSet::from([LINE_START]);
+        match &**regex {
+            Regex::Class(set) if *set == marker => Some(Regex::epsilon()),
+            Regex::Concat(first, rest) if **first == Regex::Class(marker) => Some(rest.clone()),
+            _ => None,
+        }
+    }
+
+    /// Converts a set expression into a regular expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `expr` - The set expression of a regex branch.
+    ///
+    /// # Returns
+    ///
+    /// The equivalent expression.
+    fn from_set_expr(expr: &SetExpr) -> Rc<Regex> {
+        match expr {
+            SetExpr::Regex(regex) => Regex::parse(regex, true),
+            SetExpr::Complement(expr) => {
+                // Complements are taken over the characters a DFA complement is completed with.
+                let inner = Regex::from_set_expr(expr);
+                let mut universe = (0u8..=127).map(char::from).collect();
+                inner.collect_chars(&mut universe);
+                Regex::and(
+                    Regex::complement(inner),
+                    Regex::star(Regex::class(universe)),
+                )
+            }
+            SetExpr::Intersection(left, right) => {
+                Regex::and(Regex::from_set_expr(left), Regex::from_set_expr(right))
+            }
+            SetExpr::Difference(left, right) => Regex::and(
+                Regex::from_set_expr(left),
+                Regex::complement(Regex::from_set_expr(right)),
+            ),
+        }
+    }
+}
+
+/// The characters matched by `\s`.
+const WHITESPACE: &[char] = &[' ', '\t', '\n', '\r'];
+
+/// A marker in front of rules anchored with `^`, a noncharacter that never occurs in input.
+pub const LINE_START: char = '\u{FDD0}';
+
+/// Parses the contents of a character set, following `Nfa::parse_regex_set`.
+///
+/// # Arguments
+///
+/// * `set` - The characters between `[` and `]`.
+///
+/// # Returns
+///
+/// A class matching the characters of the set.
+fn parse_set(set: &str) -> Rc<Regex> {
+    let mut chars = set.chars().peekable();
+    let negated = chars.next_if_eq(&'^').is_some();
+
+    let mut members = BTreeSet::new();
+    let mut previous: Option<char> = None;
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let Some(escaped) = chars.next() else {
+                break;
+            };
+            if escaped == 's' {
+                members.extend(WHITESPACE);
+            } else {
+                members.insert(unescape(escaped));
+            }
+            previous = Some(unescape(escaped));
+        } else if c == '-'
+            && let Some(start) = previous
+            && let Some(end) = chars.next()
+        {
+            // A range starts after the previous character, which is already in the set. As
+            // in `lrlex`, a '-' ending the set stands for itself.
+            members.extend((start as u32 + 1..=end as u32).filter_map(char::from_u32));
+            previous = Some(end);
+        } else {
+            members.insert(c);
+            previous = Some(c);
+        }
+    }
+
+    if negated {
+        Regex::class(
+            (0u8..=127)
+                .map(char::from)
+                .filter(|c| !members.contains(c))
+                .collect(),
+        )
+    } else {
+        Regex::class(members)
+    }
+}
+
+/// Builds a lexer DFA from rule expressions using derivatives.
+///
+/// A state accepts the first rule whose derivative matches the empty string, like the
+/// subset-construction DFA accepts the rule defined first. The alphabet is the ASCII
+/// characters plus every character mentioned by a rule, so complements range over the same
+/// characters as negated sets. Rules starting with the `LINE_START` marker only match from
+/// a second start state, used at the start of a line. Every mode gets its own start states,
+/// in which the rules inactive in the mode start out as `Empty`.
+///
+/// # Arguments
+///
+/// * `rules` - The token name and expression of every rule, in priority order.
+/// * `modes` - The name of every mode, starting with `INITIAL`, and whether each rule is
+///   active in it.
+///
+/// # Returns
+///
+/// The constructed `Dfa`.
+pub fn construct_dfa(rules: &[(String, Rc<Regex>)], modes: &[(String, Vec<bool>)]) -> Dfa {
+    let mut alphabet: BTreeSet<char> = (0u8..=127).map(char::from).collect();
+    for (_, regex) in rules {
+        regex.collect_chars(&mut alphabet);
+    }
+    alphabet.remove(&LINE_START);
+
+    let mut nodes: HashMap<usize, Node> = HashMap::new();
+    let mut states: HashMap<Vec<Rc<Regex>>, usize> = HashMap::new();
+    let mut unmarked: VecDeque<Vec<Rc<Regex>>> = VecDeque::new();
+
+    let mut mode_starts = Vec::new();
+    for (name, active) in modes {
+        let start: Vec<Rc<Regex>> = rules
+            .iter()
+            .zip(active)
+            .map(|((_, regex), &active)| {
+                if active {
+                    regex.clone()
+                } else {
+                    Regex::empty()
+                }
+            })
+            .collect();
+        // At the start of a line, anchored rules skip their marker.
+        let line_start: Vec<Rc<Regex>> = start
+            .iter()
+            .map(|regex| Regex::strip_line_start(regex).unwrap_or_else(|| regex.clone()))
+            .collect();
+        let [start_id, line_start_id] = [start, line_start].map(|state| match states.get(&state) {
+            Some(&id) => id,
+            None => {
+                let id = add_state(&mut nodes, rules, &state);
+                states.insert(state.clone(), id);
+                unmarked.push_back(state);
+                id
+            }
+        });
+        mode_starts.push(ModeStart {
+            name: name.clone(),
+            start_id,
+            line_start_id,
+        });
+    }
+
+    while let Some(state) = unmarked.pop_front() {
+        let state_id = states[&state];
+
+        // Characters no expression mentions all lead to the same state.
+        let mut mentioned = BTreeSet::new();
+        for regex in &state {
+            regex.collect_chars(&mut mentioned);
+        }
+        let mut unmentioned: Option<Vec<Rc<Regex>>> = None;
+
+        for &ch in &alphabet {
+            let next: Vec<Rc<Regex>> = if mentioned.contains(&ch) {
+                state
+                    .iter()
+                    .map(|regex| Regex::derivative(regex, ch))
+                    .collect()
+            } else {
+                unmentioned
+                    .get_or_insert_with(|| {
+                        state
+                            .iter()
+                            .map(|regex| Regex::derivative(regex, ch))
+                            .collect()
+                    })
+                    .clone()
+            };
+            if next.iter().all(|regex| **regex == Regex::Empty) {
+                continue;
+            }
+
+            let next_id = match states.get(&next) {
+                Some(&id) => id,
+                None => {
+                    let id = add_state(&mut nodes, rules, &next);
+                    states.insert(next.clone(), id);
+                    unmarked.push_back(next);
+                    id
+                }
+            };
+
+            // Add or update the outgoing edge of the current state.
+            let node = nodes.get_mut(&state_id).unwrap();
+            if let Some(edge) = node
+                .get_mut_outgoing_edges()
+                .iter_mut()
+                .find(|edge| edge.get_to() == next_id)
+            {
+                edge.push(ch);
+            } else {
+                node.add_outgoing_edge(next_id, ch.to_string());
+            }
+        }
+    }
+
+    Dfa::from_nodes(nodes, mode_starts)
+}
+
+/// Adds a DFA node for a tuple of rule derivatives.
+///
+/// # Returns
+///
+/// The ID of the new node.
+fn add_state(
+    nodes: &mut HashMap<usize, Node>,
+    rules: &[(String, Rc<Regex>)],
+    state: &[Rc<Regex>],
+) -> usize {
+    let accepted = rules
+        .iter()
+        .zip(state)
+        .find(|(_, regex)| regex.nullable())
+        .map(|((name, _), _)| name.clone());
+    let node = match accepted {
+        Some(name) => Node::new(name, true),
+        None => Node::new("<>".to_string(), false),
+    };
+    let id = node.get_id();
+    nodes.insert(id, node);
+    id
+}
+
+/// Builds a lexer DFA from the rules of a specification using derivatives.
+///
+/// Like the hand-made NFA, the skipped `;` rule is left out unless it changes the mode, and a
+/// rule is active in the start conditions of its prefix, or else in every inclusive one. The
+/// openers of nested comments come before all rules.
+///
+/// # Arguments
+///
+/// * `spec` - The contents of a `.l` specification file.
+///
+/// # Returns
+///
+/// The constructed `Dfa`.
+pub fn dfa_from_spec(spec: &str) -> Dfa {
+    let spec = parse_spec(spec);
+    let openers: Vec<Rule> = spec
+        .nested_comments
+        .iter()
+        .map(NestedComment::opener_rule)
+        .collect();
+    let lexed: Vec<&Rule> = openers
+        .iter()
+        .chain(spec.rules.iter().filter(|rule| rule.is_lexed()))
+        .collect();
+    let rules: Vec<(String, Rc<Regex>)> = lexed
+        .iter()
+        .map(|rule| {
+            let regex = Regex::parse_rule(&rule.regex, spec.set_operators);
+            (rule.name.clone(), regex)
+        })
+        .collect();
+
+    let mut conditions = vec![(INITIAL, false)];
+    for condition in &spec.start_conditions {
+        if conditions.iter().all(|(name, _)| *name != condition.name) {
+            conditions.push((&condition.name, condition.exclusive));
+        }
+    }
+    let modes: Vec<(String, Vec<bool>)> = conditions
+        .iter()
+        .map(|&(name, exclusive)| {
+            let active = lexed
+                .iter()
+                .map(|rule| {
+                    if rule.start_conditions.is_empty() {
+                        !exclusive
+                    } else {
+                        rule.start_conditions
+                            .iter()
+                            .any(|condition| condition == name)
+                    }
+                })
+                .collect();
+            (name.to_string(), active)
+        })
+        .collect();
+    construct_dfa(&rules, &modes)
+}
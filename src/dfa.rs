@@ -1,20 +1,70 @@
 //! Module for constructing a deterministic finite automaton (DFA) from a nondeterministic finite automaton (NFA).
 //! It provides functionalities for creating a DFA, computing epsilon closures, moving on symbols, and lexing input strings.
 
-use crate::nfa::Nfa;
+use crate::nfa::{INITIAL, Nfa};
 use crate::node::Node;
-use std::cell::RefCell;
+use crate::token::{LexError, LineIndex, Span, Token};
+use std::collections::hash_map::Entry;
 use std::collections::{BTreeSet, HashMap, VecDeque};
-use std::rc::Rc;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+
+/// An input on which two DFAs disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Distinction {
+    /// The input, a shortest one telling the DFAs apart.
+    pub input: String,
+    /// The rule the first DFA accepts the input with, if any.
+    pub left: Option<String>,
+    /// The rule the second DFA accepts the input with, if any.
+    pub right: Option<String>,
+    /// Whether the DFAs disagree on the input at the start of a line, rather than elsewhere.
+    pub at_line_start: bool,
+    /// The mode the DFAs disagree in.
+    pub mode: String,
+}
+
+/// The start states of a DFA in one mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeStart {
+    /// The name of the start condition of the mode, `INITIAL` for the first one.
+    pub name: String,
+    /// The ID of the start node used away from the start of a line.
+    pub start_id: usize,
+    /// The ID of the start node used at the start of a line, the same as `start_id` if no
+    /// rule active in the mode is anchored with `^`.
+    pub line_start_id: usize,
+}
+
+impl ModeStart {
+    /// Creates the start states of the `INITIAL` mode of a DFA without anchored rules.
+    pub(crate) fn initial(start_id: usize) -> ModeStart {
+        ModeStart {
+            name: INITIAL.to_string(),
+            start_id,
+            line_start_id: start_id,
+        }
+    }
+}
 
 /// Represents a deterministic finite automaton (DFA).
 pub struct Dfa {
-    /// Shared reference to the underlying NFA.
-    nfa: Rc<RefCell<Nfa>>,
+    /// Shared, read-only reference to the underlying NFA.
+    nfa: Arc<Nfa>,
     /// Map of DFA node IDs to their corresponding `Node` structures.
     nodes: HashMap<usize, Node>,
     /// The root node ID of the DFA.
     root_id: usize,
+    /// The start nodes of every mode; the first is `INITIAL`, whose start node is the root.
+    modes: Vec<ModeStart>,
+    /// The terminal NFA node of the rule every accepting DFA node accepts, if known.
+    terminals: HashMap<usize, usize>,
+}
+
+impl Default for Dfa {
+    fn default() -> Self {
+        Dfa::new()
+    }
 }
 
 impl Dfa {
@@ -23,7 +73,7 @@ impl Dfa {
     /// # Returns
     /// A new instance of `Dfa`.
     pub fn new() -> Dfa {
-        let nfa = Rc::new(RefCell::new(Nfa::new()));
+        let nfa = Arc::new(Nfa::new());
         let root = Node::new("DFA".to_string(), false);
         let root_id = root.get_id();
         let mut nodes = HashMap::new();
@@ -32,6 +82,64 @@ impl Dfa {
             nfa,
             nodes,
             root_id,
+            modes: vec![ModeStart::initial(root_id)],
+            terminals: HashMap::new(),
+        }
+    }
+
+    /// Creates a DFA from nodes built by another construction.
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - The DFA nodes, named after the rule they accept.
+    /// * `modes` - The start nodes of every mode, starting with `INITIAL`.
+    ///
+    /// # Returns
+    /// A `Dfa` without an underlying NFA.
+    pub(crate) fn from_nodes(nodes: HashMap<usize, Node>, modes: Vec<ModeStart>) -> Dfa {
+        Dfa {
+            nfa: Arc::new(Nfa::new()),
+            nodes,
+            root_id: modes[0].start_id,
+            modes,
+            terminals: HashMap::new(),
+        }
+    }
+
+    /// Returns a reference to the DFA nodes.
+    pub fn get_nodes(&self) -> &HashMap<usize, Node> {
+        &self.nodes
+    }
+
+    /// Returns the ID of the root node.
+    pub fn get_root_id(&self) -> usize {
+        self.root_id
+    }
+
+    /// Returns the ID of the start node used at the start of a line, if rules are anchored
+    /// with `^`.
+    pub fn get_line_start_id(&self) -> Option<usize> {
+        let initial = &self.modes[0];
+        (initial.line_start_id != initial.start_id).then_some(initial.line_start_id)
+    }
+
+    /// Returns the start nodes of every mode, starting with `INITIAL`.
+    pub fn get_modes(&self) -> &[ModeStart] {
+        &self.modes
+    }
+
+    /// Returns the start node for a token.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The number of the mode the lexer is in.
+    /// * `at_line_start` - Whether the token starts at the start of a line.
+    fn start_id(&self, mode: usize, at_line_start: bool) -> usize {
+        let mode = &self.modes[mode];
+        if at_line_start {
+            mode.line_start_id
+        } else {
+            mode.start_id
         }
     }
 
@@ -39,36 +147,51 @@ impl Dfa {
     ///
     /// # Arguments
     ///
-    /// * `nfa` - A shared pointer with interior mutability to the NFA.
-    pub fn set_nfa(&mut self, nfa: Rc<RefCell<Nfa>>) {
+    /// * `nfa` - A shared pointer to the fully constructed NFA.
+    pub fn set_nfa(&mut self, nfa: Arc<Nfa>) {
         self.nfa = nfa;
     }
 
     /// Constructs the DFA using subset construction starting from the epsilon closure of the NFA's root.
-    /// It populates the set of DFA nodes and writes the resulting DFA graph in DOT format to a file.
+    /// It populates the set of DFA nodes.
     pub fn construct_dfa(&mut self) {
         let mut dfa_states: HashMap<BTreeSet<usize>, usize> = HashMap::new();
         let mut unmarked: VecDeque<BTreeSet<usize>> = VecDeque::new();
 
-        // Compute the epsilon closure for the NFA's root.
-        let nfa_root_id = self.nfa.borrow().get_root_id();
-        let start_set: BTreeSet<usize> = [nfa_root_id].iter().cloned().collect();
-        let start_closure = self.epsilon_closure(&start_set);
-        let start_dfa_id = self.create_dfa_state(&start_closure);
-        self.root_id = start_dfa_id;
-        dfa_states.insert(start_closure.clone(), start_dfa_id);
-        unmarked.push_back(start_closure);
+        // Every mode starts from the epsilon closure of its entry node, and from a second
+        // start state at the start of a line if rules active in it are anchored with `^`.
+        let nfa = Arc::clone(&self.nfa);
+        let mut modes = Vec::new();
+        for (mode, name) in nfa.get_mode_names().into_iter().enumerate() {
+            let [start_id, line_start_id] = [false, true].map(|at_line_start| {
+                let closure = nfa.start_set(mode, at_line_start);
+                if let Some(&id) = dfa_states.get(&closure) {
+                    return id;
+                }
+                let id = self.create_dfa_state(&closure);
+                dfa_states.insert(closure.clone(), id);
+                unmarked.push_back(closure);
+                id
+            });
+            modes.push(ModeStart {
+                name: name.to_string(),
+                start_id,
+                line_start_id,
+            });
+        }
+        self.root_id = modes[0].start_id;
+        self.modes = modes;
 
         // Process unmarked states until no more states remain.
         while let Some(current_set) = unmarked.pop_front() {
             let current_dfa_id = dfa_states[&current_set];
 
             // Extract all available transition symbols from the current set.
-            let symbols = self.extract_symbols(&current_set, self.nfa.borrow().get_nodes());
+            let symbols = self.extract_symbols(&current_set, self.nfa.get_nodes());
             for ch in symbols {
                 // Determine the set of NFA states reachable by symbol ch including epsilon moves.
-                let move_set = self.move_nfa(&current_set, &ch);
-                let closure = self.epsilon_closure(&move_set);
+                let move_set = self.nfa.move_on(&current_set, ch);
+                let closure = self.nfa.epsilon_closure(&move_set);
                 if closure.is_empty() {
                     continue;
                 }
@@ -96,65 +219,20 @@ impl Dfa {
                 }
             }
         }
-
-        // Write the DFA in DOT format to a file.
-        let dot_string = self.nodes.get(&self.root_id).unwrap().to_dot(&self.nodes);
-        std::fs::write("dfa.dot", dot_string).expect("Failed to write DFA to file");
     }
 
-    /// Computes the epsilon closure of a given set of NFA state IDs.
-    ///
-    /// # Arguments
-    ///
-    /// * `state_set` - A set of NFA state IDs.
+    /// Generates a DOT format representation of the DFA.
     ///
     /// # Returns
-    /// A set containing all state IDs reachable from `state_set` using epsilon transitions.
-    fn epsilon_closure(&self, state_set: &BTreeSet<usize>) -> BTreeSet<usize> {
-        let mut closure = state_set.clone();
-        let mut stack: Vec<usize> = state_set.iter().cloned().collect();
-
-        while let Some(state_id) = stack.pop() {
-            if let Some(nfa_node) = self.nfa.borrow().get_nodes().get(&state_id) {
-                for edge in nfa_node.get_outgoing_edges().iter() {
-                    let to = edge.get_to();
-                    let name = edge.get_sym().to_string();
-                    if name == "<λ>" && !closure.contains(&to) {
-                        closure.insert(to);
-                        stack.push(to);
-                    }
-                }
-            }
-        }
-
-        closure
-    }
-
-    /// Computes the set of NFA states reachable from a given state set by consuming the specified character.
-    ///
-    /// # Arguments
-    ///
-    /// * `state_set` - A set of NFA state IDs.
-    /// * `ch` - The transition character.
-    ///
-    /// # Returns
-    /// A set containing all state IDs reached over transitions labeled with `ch`.
-    fn move_nfa(&mut self, state_set: &BTreeSet<usize>, ch: &char) -> BTreeSet<usize> {
-        let mut result = BTreeSet::new();
-        for state_id in state_set {
-            if let Some(nfa_node) = self.nfa.borrow().get_nodes().get(state_id) {
-                for edge in nfa_node.get_outgoing_edges().iter() {
-                    let to = edge.get_to();
-                    let sym = edge.get_sym();
-
-                    // Check for transition on the provided character.
-                    if sym.contains(*ch) && !result.contains(&edge.get_to()) {
-                        result.insert(to);
-                    }
-                }
-            }
-        }
-        result
+    /// A `String` containing the DOT representation of the DFA, drawn from the start nodes
+    /// of every mode.
+    pub fn to_dot(&self) -> String {
+        let starts: Vec<usize> = self
+            .modes
+            .iter()
+            .flat_map(|mode| [mode.line_start_id, mode.start_id])
+            .collect();
+        Node::to_dot_from(&starts, &self.nodes)
     }
 
     /// Extracts all non-epsilon symbols available from the transitions of NFA states.
@@ -173,7 +251,7 @@ impl Dfa {
     ) -> BTreeSet<char> {
         let mut result = BTreeSet::new();
         for state_id in state_set {
-            if let Some(nfa_node) = self.nfa.borrow().get_nodes().get(state_id) {
+            if let Some(nfa_node) = self.nfa.get_nodes().get(state_id) {
                 for edge in nfa_node.get_outgoing_edges().iter() {
                     let sym = edge.get_sym().to_string();
                     if sym != "<λ>" {
@@ -202,7 +280,7 @@ impl Dfa {
         let terminal_names: Vec<String> = state_set
             .iter()
             .filter_map(|id| {
-                self.nfa.borrow().get_nodes().get(id).and_then(|node| {
+                self.nfa.get_nodes().get(id).and_then(|node| {
                     if node.is_terminal() {
                         Some(node.get_name().to_string())
                     } else {
@@ -215,10 +293,9 @@ impl Dfa {
         // Determine if the DFA state should be a terminal state.
         let is_terminal = state_set.iter().any(|&id| {
             self.nfa
-                .borrow()
                 .get_nodes()
                 .get(&id)
-                .map_or(false, |node| node.is_terminal())
+                .is_some_and(|node| node.is_terminal())
         });
 
         let name = if is_terminal {
@@ -230,76 +307,628 @@ impl Dfa {
         let new_node = Node::new(name, is_terminal);
         let new_node_id = new_node.get_id();
         self.nodes.insert(new_node_id, new_node);
+        if let Some(terminal_id) = self.nfa.accepting_node(state_set) {
+            self.terminals.insert(new_node_id, terminal_id);
+        }
         new_node_id
     }
 
-    /// Lexes the input string using the constructed DFA.
+    /// Runs the DFA over a whole string.
     ///
-    /// Iterates through the input characters, traversing the DFA transitions until a valid token is found.
-    /// Returns a vector containing tuples of state names and token lexemes.
+    /// # Arguments
+    ///
+    /// * `input` - The string to match.
+    ///
+    /// # Returns
+    /// The name of the accepting state reached after consuming all of `input` from the start
+    /// of a line in `INITIAL`, or `None` if the DFA gets stuck or ends in a non-accepting
+    /// state.
+    pub fn classify(&self, input: &str) -> Option<&str> {
+        let mut current_state_id = self.start_id(0, true);
+        for ch in input.chars() {
+            current_state_id = self.next_state(current_state_id, ch)?;
+        }
+        self.accepted_rule(current_state_id)
+    }
+
+    /// Checks whether two DFAs lex alike.
     ///
     /// # Arguments
     ///
-    /// * `input` - The input string to be lexed.
+    /// * `other` - The DFA to compare with.
     ///
     /// # Returns
-    /// A vector of tuples where each tuple represents (state name, token).
-    pub fn lex(&mut self, input: &str) -> Vec<(String, String)> {
-        let mut tokens = Vec::new();
-        let chars: Vec<char> = input.chars().collect();
-        let mut index = 0;
-
-        // Iterate over the input characters.
-        while index < chars.len() {
-            let mut current_state_id = self.root_id;
-            let mut last_accept_index: Option<usize> = None;
-            let mut last_accept_state_name = String::new();
-            let mut j = index;
-
-            // Traverse the DFA transitions for as long as possible.
-            while j < chars.len() {
-                let current_node = self.nodes.get(&current_state_id).unwrap();
-                let mut found = false;
-
-                // Check for transitions matching the current character.
-                for edge in current_node.get_outgoing_edges().iter() {
-                    if edge.get_sym().contains(&chars[j].to_string()) {
-                        found = true;
-                        current_state_id = edge.get_to();
-                        break;
+    /// `true` if both DFAs accept the same non-empty strings with the same rules.
+    pub fn equivalent(&self, other: &Dfa) -> bool {
+        self.distinguish(other).is_none()
+    }
+
+    /// Finds the shortest input on which two DFAs disagree.
+    ///
+    /// The DFAs are walked in lockstep over their product automaton, breadth first, so the
+    /// first pair of states accepting different rules is reached by a shortest input. A
+    /// missing transition counts as a dead, non-accepting state. The empty string is not
+    /// compared, as lexers never produce empty tokens. The start states of modes with the same
+    /// name are compared, both those used at the start of a line and elsewhere, and the
+    /// shortest input found is returned. A mode only one DFA has is compared with a dead state.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The DFA to compare with.
+    ///
+    /// # Returns
+    /// The distinguishing input with the rules both DFAs accept it with, or `None` if the
+    /// DFAs are equivalent.
+    pub fn distinguish(&self, other: &Dfa) -> Option<Distinction> {
+        let mut names: Vec<&str> = self.modes.iter().map(|mode| mode.name.as_str()).collect();
+        for mode in &other.modes {
+            if !names.contains(&mode.name.as_str()) {
+                names.push(&mode.name);
+            }
+        }
+
+        let mut compared = Vec::new();
+        let mut shortest: Option<Distinction> = None;
+        for name in names {
+            let left = self.modes.iter().find(|mode| mode.name == name);
+            let right = other.modes.iter().find(|mode| mode.name == name);
+            for at_line_start in [true, false] {
+                let start_of = |mode: &ModeStart| {
+                    if at_line_start {
+                        mode.line_start_id
+                    } else {
+                        mode.start_id
                     }
+                };
+                let start = (left.map(start_of), right.map(start_of));
+                if compared.contains(&start) {
+                    continue;
                 }
+                compared.push(start);
+                let Some(distinction) = self.distinguish_from(other, start, name, at_line_start)
+                else {
+                    continue;
+                };
+                if shortest
+                    .as_ref()
+                    .is_none_or(|found| distinction.input.len() < found.input.len())
+                {
+                    shortest = Some(distinction);
+                }
+            }
+        }
+        shortest
+    }
 
-                if !found {
-                    break;
+    /// Finds the shortest input on which two DFAs disagree from one pair of start states.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The DFA to compare with.
+    /// * `start` - The start states of both DFAs, `None` for a DFA lacking the mode.
+    /// * `mode` - The name of the mode the start states belong to.
+    /// * `at_line_start` - Whether the start states are used at the start of a line.
+    ///
+    /// # Returns
+    /// The distinguishing input, or `None` if the DFAs agree from these start states.
+    fn distinguish_from(
+        &self,
+        other: &Dfa,
+        start: (Option<usize>, Option<usize>),
+        mode: &str,
+        at_line_start: bool,
+    ) -> Option<Distinction> {
+        let alphabet: BTreeSet<char> = [self, other]
+            .iter()
+            .flat_map(|dfa| dfa.nodes.values())
+            .flat_map(|node| node.get_outgoing_edges())
+            .flat_map(|edge| edge.get_sym().chars())
+            .collect();
+
+        // Every visited pair of states, with the pair and character it was first reached by.
+        type Pair = (Option<usize>, Option<usize>);
+        let mut parents: HashMap<Pair, Option<(Pair, char)>> = HashMap::from([(start, None)]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(pair) = queue.pop_front() {
+            let (left, right) = pair;
+            let left_rule = left.and_then(|id| self.accepted_rule(id));
+            let right_rule = right.and_then(|id| other.accepted_rule(id));
+            if pair != start && left_rule != right_rule {
+                // Walk back to the start to recover the input.
+                let mut input = Vec::new();
+                let mut current = pair;
+                while let Some((parent, ch)) = parents[&current] {
+                    input.push(ch);
+                    current = parent;
+                }
+                return Some(Distinction {
+                    input: input.into_iter().rev().collect(),
+                    left: left_rule.map(str::to_string),
+                    right: right_rule.map(str::to_string),
+                    at_line_start,
+                    mode: mode.to_string(),
+                });
+            }
+
+            for &ch in &alphabet {
+                let next = (
+                    left.and_then(|id| self.next_state(id, ch)),
+                    right.and_then(|id| other.next_state(id, ch)),
+                );
+                if next != (None, None) && !parents.contains_key(&next) {
+                    parents.insert(next, Some((pair, ch)));
+                    queue.push_back(next);
                 }
+            }
+        }
+        None
+    }
+
+    /// Builds the DFA accepting the strings both DFAs accept.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The second DFA.
+    /// * `name` - The rule the accepting states are named after.
+    ///
+    /// # Returns
+    /// A DFA without an underlying NFA.
+    pub fn intersection(&self, other: &Dfa, name: &str) -> Dfa {
+        self.product(other, name, |left, right| left && right)
+    }
+
+    /// Builds the DFA accepting the strings this DFA accepts and the other one rejects.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The DFA whose strings are removed.
+    /// * `name` - The rule the accepting states are named after.
+    ///
+    /// # Returns
+    /// A DFA without an underlying NFA.
+    pub fn difference(&self, other: &Dfa, name: &str) -> Dfa {
+        self.product(other, name, |left, right| left && !right)
+    }
+
+    /// Builds the DFA accepting the strings this DFA rejects.
+    ///
+    /// The DFA is completed with a dead state over the ASCII characters and the characters on
+    /// its transitions, so the complement is taken over those characters.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The rule the accepting states are named after.
+    ///
+    /// # Returns
+    /// A DFA without an underlying NFA.
+    pub fn complement(&self, name: &str) -> Dfa {
+        self.product(self, name, |accepted, _| !accepted)
+    }
+
+    /// Builds the product automaton of two DFAs.
+    ///
+    /// Both DFAs are completed with a dead state, written as `None`, over the ASCII characters
+    /// and the characters on their transitions. The pair of dead states is only kept if it is
+    /// accepting.
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The second DFA.
+    /// * `name` - The rule the accepting states are named after.
+    /// * `accept` - Whether a pair of states accepts, given whether each of them accepts.
+    ///
+    /// # Returns
+    /// A DFA without an underlying NFA.
+    fn product(&self, other: &Dfa, name: &str, accept: fn(bool, bool) -> bool) -> Dfa {
+        let alphabet: BTreeSet<char> = (0u8..=127)
+            .map(char::from)
+            .chain(
+                [self, other]
+                    .iter()
+                    .flat_map(|dfa| dfa.nodes.values())
+                    .flat_map(|node| node.get_outgoing_edges())
+                    .flat_map(|edge| edge.get_sym().chars()),
+            )
+            .collect();
+
+        type Pair = (Option<usize>, Option<usize>);
+        let keep_dead = accept(false, false);
+        let new_node = |(left, right): Pair| {
+            let is_terminal = accept(
+                left.is_some_and(|id| self.nodes[&id].is_terminal()),
+                right.is_some_and(|id| other.nodes[&id].is_terminal()),
+            );
+            let name = if is_terminal { name } else { "<>" };
+            Node::new(name.to_string(), is_terminal)
+        };
 
-                // Record accepted state if terminal.
-                if self.nodes.get(&current_state_id).unwrap().is_terminal() {
-                    last_accept_index = Some(j + 1);
-                    last_accept_state_name = self
-                        .nodes
-                        .get(&current_state_id)
-                        .unwrap()
-                        .get_name()
-                        .to_string();
+        let start: Pair = (Some(self.root_id), Some(other.root_id));
+        let root = new_node(start);
+        let root_id = root.get_id();
+        let mut nodes = HashMap::from([(root_id, root)]);
+        let mut ids: HashMap<Pair, usize> = HashMap::from([(start, root_id)]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(pair) = queue.pop_front() {
+            let (left, right) = pair;
+            // The transitions of the pair, grouped by target.
+            let mut edges: Vec<(usize, String)> = Vec::new();
+            for &ch in &alphabet {
+                let next = (
+                    left.and_then(|id| self.next_state(id, ch)),
+                    right.and_then(|id| other.next_state(id, ch)),
+                );
+                if next == (None, None) && !keep_dead {
+                    continue;
+                }
+                let next_id = match ids.entry(next) {
+                    Entry::Occupied(entry) => *entry.get(),
+                    Entry::Vacant(entry) => {
+                        let node = new_node(next);
+                        let id = node.get_id();
+                        nodes.insert(id, node);
+                        queue.push_back(next);
+                        *entry.insert(id)
+                    }
+                };
+                match edges.iter_mut().find(|(to, _)| *to == next_id) {
+                    Some((_, label)) => label.push(ch),
+                    None => edges.push((next_id, ch.to_string())),
                 }
-                j += 1;
             }
 
-            // If an accepted state was found, extract the token.
-            if let Some(end_index) = last_accept_index {
-                let token: String = chars[index..end_index].iter().collect();
-                let token = token.trim().to_string();
-                if last_accept_state_name != ";" {
-                    tokens.push((last_accept_state_name, token));
+            let node = nodes.get_mut(&ids[&pair]).unwrap();
+            for (to, label) in edges {
+                node.add_outgoing_edge(to, label);
+            }
+        }
+        Dfa::from_nodes(nodes, vec![ModeStart::initial(root_id)])
+    }
+
+    /// Returns the state reached from a state over a character, if any.
+    fn next_state(&self, state_id: usize, ch: char) -> Option<usize> {
+        self.nodes[&state_id]
+            .get_outgoing_edges()
+            .iter()
+            .find(|edge| edge.get_sym().contains(ch))
+            .map(|edge| edge.get_to())
+    }
+
+    /// Returns the length of the longest prefix of an input the DFA accepts.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The characters to match from the root.
+    ///
+    /// # Returns
+    /// The number of characters of the longest non-empty accepted prefix, or `None` if the DFA
+    /// accepts no prefix of `input`.
+    pub fn longest_match(&self, input: &[char]) -> Option<usize> {
+        self.scan(input).0
+    }
+
+    /// Finds the longest prefix of an input the DFA accepts, and how much of the input it read.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The characters to match from the root.
+    ///
+    /// # Returns
+    /// The number of characters of the longest non-empty accepted prefix, if any, and the
+    /// number of characters read, which is one more than `input.len()` if the DFA could have
+    /// read past the end.
+    pub(crate) fn scan(&self, input: &[char]) -> (Option<usize>, usize) {
+        let mut state_id = self.root_id;
+        let mut longest = None;
+        for (index, &ch) in input.iter().enumerate() {
+            let Some(next) = self.next_state(state_id, ch) else {
+                return (longest, index + 1);
+            };
+            state_id = next;
+            if self.nodes[&state_id].is_terminal() {
+                longest = Some(index + 1);
+            }
+        }
+        (longest, input.len() + 1)
+    }
+
+    /// Returns the rule an unfinished lexeme would have matched once finished.
+    ///
+    /// A lexeme is unfinished if the DFA reads all of it from the root without getting stuck,
+    /// but accepts neither the lexeme nor a prefix of it, such as a string literal missing
+    /// its closing quote at the end of the input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The characters of the lexeme.
+    ///
+    /// # Returns
+    /// The rule accepting the shortest completion of the lexeme, or `None` if the lexeme is
+    /// not unfinished.
+    pub fn unfinished_rule(&self, input: &[char]) -> Option<&str> {
+        let mut state_id = self.root_id;
+        for &ch in input {
+            state_id = self.next_state(state_id, ch)?;
+            if self.nodes[&state_id].is_terminal() {
+                return None;
+            }
+        }
+
+        let mut seen = BTreeSet::from([state_id]);
+        let mut queue = VecDeque::from([state_id]);
+        while let Some(id) = queue.pop_front() {
+            if let Some(name) = self.accepted_rule(id) {
+                return Some(name);
+            }
+            for edge in self.nodes[&id].get_outgoing_edges() {
+                if seen.insert(edge.get_to()) {
+                    queue.push_back(edge.get_to());
                 }
-                index = end_index;
-            } else {
-                index += 1;
             }
         }
+        None
+    }
+
+    /// Returns the name of the rule a state accepts, if any.
+    fn accepted_rule(&self, state_id: usize) -> Option<&str> {
+        let node = &self.nodes[&state_id];
+        node.is_terminal().then(|| node.get_name())
+    }
+
+    /// Lexes the input string using the constructed DFA.
+    ///
+    /// Iterates through the input characters, traversing the DFA transitions until a valid token is found.
+    /// Returns a vector containing the tokens found, named after the accepting states.
+    ///
+    /// All scanning state lives on the stack of this call, so a constructed DFA can be shared
+    /// between threads and used to lex several inputs concurrently.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input string to be lexed.
+    ///
+    /// # Returns
+    /// A vector of tokens with their positions in `input`.
+    pub fn lex(&self, input: &str) -> Vec<Token> {
+        self.lex_with_errors(input).0
+    }
+
+    /// Lexes the input string using the constructed DFA, reporting problems in the input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input string to be lexed.
+    ///
+    /// # Returns
+    /// The tokens with their positions in `input`, and the problems found, such as nested
+    /// comments that are never closed.
+    pub fn lex_with_errors(&self, input: &str) -> (Vec<Token>, Vec<LexError>) {
+        let mut tokens = Vec::new();
+        let errors = self.lex_from(input, Resume::initial(), |token, _, _| {
+            tokens.push(token);
+            ControlFlow::Continue(())
+        });
+        (tokens, errors)
+    }
 
-        tokens
+    /// Lexes an input from some point with the DFA, handing over every token as it is cut.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input string to be lexed.
+    /// * `resume` - Where lexing starts, and in which modes.
+    /// * `emit` - Receives every token, how far the input was read and the modes after the
+    ///   token, as in `munch_from`.
+    ///
+    /// # Returns
+    /// The problems found before lexing ended or was stopped.
+    pub(crate) fn lex_from(
+        &self,
+        input: &str,
+        resume: Resume,
+        emit: impl FnMut(Token, usize, &[(usize, usize)]) -> ControlFlow<()>,
+    ) -> Vec<LexError> {
+        munch_from(
+            input,
+            resume,
+            &self.nfa,
+            |mode, at_line_start| self.start_id(mode, at_line_start),
+            |&state_id, ch| self.next_state(state_id, ch),
+            |&state_id| {
+                self.accepted_rule(state_id)
+                    .map(|name| (name, self.terminals.get(&state_id).copied()))
+            },
+            emit,
+        )
     }
 }
+
+/// Where a maximal munch starts in an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Resume {
+    /// The byte offset lexing starts at, which must be the end of a token or the start of
+    /// the input.
+    pub(crate) offset: usize,
+    /// The modes entered, with how many times in a row, the current one last.
+    pub(crate) modes: Vec<(usize, usize)>,
+}
+
+impl Resume {
+    /// Returns the start of an input in the `INITIAL` mode.
+    pub(crate) fn initial() -> Resume {
+        Resume {
+            offset: 0,
+            modes: vec![(0, 1)],
+        }
+    }
+}
+
+/// Splits an input into tokens using maximal munch over some automaton.
+///
+/// From each position, the automaton is stepped over the input for as long as it can move,
+/// and the longest prefix that ended in an accepting state becomes a token named after that
+/// state. Matches of the skipped rule `;` produce no token, and a character no rule matches
+/// is skipped.
+///
+/// The trailing context of a rule counts towards the length of its match, as in flex, but is
+/// then cut off the token and lexed again. A match whose token would be empty is skipped like
+/// an unmatched character.
+///
+/// Lexing starts in the `INITIAL` mode. After a match, including one of the skipped rule,
+/// the mode changes as the rule says, following the stack semantics of `lrlex`.
+///
+/// When the opener of a nested comment matches, the token is extended up to the closer that
+/// balances it. A comment that is never closed is reported at its opener, and ends lexing.
+///
+/// # Arguments
+///
+/// * `input` - The input string to be lexed.
+/// * `nfa` - The NFA the rules were built in, used to cut off trailing contexts and to look up
+///   mode changes.
+/// * `start` - Returns the start state of the automaton, given the number of the current mode
+///   and whether the token starts at the start of a line.
+/// * `step` - Moves a state over a character, or returns `None` if the automaton is stuck.
+/// * `accept` - Returns the name of the rule a state accepts, if any, and the terminal NFA
+///   node of the rule if it is known.
+///
+/// # Returns
+/// The tokens with their positions in `input`, and the problems found.
+pub(crate) fn maximal_munch<'s, S>(
+    input: &str,
+    nfa: &Nfa,
+    start: impl Fn(usize, bool) -> S,
+    step: impl Fn(&S, char) -> Option<S>,
+    accept: impl Fn(&S) -> Option<(&'s str, Option<usize>)>,
+) -> (Vec<Token>, Vec<LexError>) {
+    let mut tokens = Vec::new();
+    let errors = munch_from(
+        input,
+        Resume::initial(),
+        nfa,
+        start,
+        step,
+        accept,
+        |token, _, _| {
+            tokens.push(token);
+            ControlFlow::Continue(())
+        },
+    );
+    (tokens, errors)
+}
+
+/// Runs `maximal_munch` from some point of an input, handing over every token as it is cut.
+///
+/// Besides each token, `emit` is given how far the input was read so far, as the byte offset
+/// one past the last character any step examined, or `input.len() + 1` if a step reached the
+/// end of the input. Tokens up to one whose reach is at most some offset do not depend on
+/// the input from that offset on. `emit` is also given the modes after the token, and can
+/// stop lexing by returning `ControlFlow::Break`.
+///
+/// # Arguments
+///
+/// * `input` - The input string to be lexed.
+/// * `resume` - Where lexing starts, and in which modes.
+/// * `nfa`, `start`, `step`, `accept` - As in `maximal_munch`.
+/// * `emit` - Receives every token, the reach of lexing and the modes after the token.
+///
+/// # Returns
+/// The problems found before lexing ended or was stopped.
+pub(crate) fn munch_from<'s, S>(
+    input: &str,
+    resume: Resume,
+    nfa: &Nfa,
+    start: impl Fn(usize, bool) -> S,
+    step: impl Fn(&S, char) -> Option<S>,
+    accept: impl Fn(&S) -> Option<(&'s str, Option<usize>)>,
+    mut emit: impl FnMut(Token, usize, &[(usize, usize)]) -> ControlFlow<()>,
+) -> Vec<LexError> {
+    let mut errors = Vec::new();
+    let lines = LineIndex::new(input);
+    let rest = &input[resume.offset..];
+    let chars: Vec<char> = rest.chars().collect();
+    // Byte offset of every character, plus the end of the input.
+    let offsets: Vec<usize> = rest
+        .char_indices()
+        .map(|(offset, _)| resume.offset + offset)
+        .chain(std::iter::once(input.len()))
+        .collect();
+    let starts_line = resume.offset == 0 || input[..resume.offset].ends_with('\n');
+    let mut index = 0;
+    // One past the last character examined, which is `chars.len() + 1` once the end was hit.
+    let mut read = 0;
+    let mut modes = resume.modes;
+
+    // Iterate over the input characters.
+    while index < chars.len() {
+        let at_line_start = if index == 0 {
+            starts_line
+        } else {
+            chars[index - 1] == '\n'
+        };
+        let mode = modes.last().map_or(0, |&(mode, _)| mode);
+        let mut state = start(mode, at_line_start);
+        let mut last_accept: Option<(usize, &str, Option<usize>)> = None;
+        let mut j = index;
+
+        // Step the automaton for as long as possible, recording accepted states.
+        while j < chars.len() {
+            let Some(next) = step(&state, chars[j]) else {
+                break;
+            };
+            state = next;
+            if let Some((name, terminal_id)) = accept(&state) {
+                last_accept = Some((j + 1, name, terminal_id));
+            }
+            j += 1;
+        }
+        read = read.max(j + 1);
+
+        // If an accepted state was found, extract the token.
+        if let Some((mut end_index, name, terminal_id)) = last_accept {
+            if let Some(terminal_id) = terminal_id {
+                let matched = &chars[index..end_index];
+                end_index = index + nfa.token_length(terminal_id, matched, mode, at_line_start);
+            }
+            if end_index == index {
+                index += 1;
+                continue;
+            }
+            let delimiters = terminal_id.and_then(|id| nfa.get_comment_delimiters(id));
+            if let Some(delimiters) = delimiters {
+                let (length, comment_read) = delimiters.comment_length(&chars[end_index..]);
+                read = read.max(end_index + comment_read);
+                let Some(length) = length else {
+                    let what = if name == ";" { "comment" } else { name };
+                    let span = Span::new(offsets[index], offsets[end_index]);
+                    errors.push(LexError::new(
+                        format!("Unterminated {}", what),
+                        span,
+                        &lines,
+                    ));
+                    break;
+                };
+                end_index += length;
+            }
+            if let Some((change, target)) = terminal_id.and_then(|id| nfa.get_mode_change(id)) {
+                change.apply(&mut modes, target);
+            }
+            if name != ";" {
+                let span = Span::new(offsets[index], offsets[end_index]);
+                let token = Token::new(
+                    name.to_string(),
+                    input[span.start..span.end].to_string(),
+                    span,
+                    &lines,
+                );
+                let reach = offsets.get(read).copied().unwrap_or(input.len() + 1);
+                if emit(token, reach, &modes).is_break() {
+                    break;
+                }
+            }
+            index = end_index;
+        } else {
+            index += 1;
+        }
+    }
+
+    errors
+}
@@ -0,0 +1,570 @@
+//! Module for comparing the token streams of the two lexer implementations.
+//! The streams are aligned with Myers' difference algorithm, and the differing regions are
+//! grouped into hunks that can be rendered as a report with a snippet of the source code.
+
+use crate::token::{LineIndex, Token};
+use std::fmt::Write;
+use std::ops::Range;
+
+/// The maximum number of tokens listed per side when rendering a hunk.
+const MAX_LISTED_TOKENS: usize = 5;
+
+/// The kind of disagreement found in a hunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchKind {
+    /// Both lexers produced tokens, but they differ.
+    Changed,
+    /// Only the library lexer produced tokens; the hand-made lexer is missing them.
+    Missing,
+    /// Only the hand-made lexer produced tokens; they are extra.
+    Extra,
+}
+
+impl MismatchKind {
+    /// Returns a short description of the mismatch.
+    ///
+    /// # Arguments
+    ///
+    /// * `candidate` - The name of the lexer checked against the reference, e.g. `hand-made`.
+    pub fn describe(&self, candidate: &str) -> String {
+        match self {
+            MismatchKind::Changed => "the lexers produced different tokens".to_string(),
+            MismatchKind::Missing => format!("the {} lexer is missing tokens", candidate),
+            MismatchKind::Extra => format!("the {} lexer produced extra tokens", candidate),
+        }
+    }
+}
+
+/// One of the two lexers being compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The lexer whose tokens are expected, usually the library lexer.
+    Reference,
+    /// The lexer checked against the reference, usually the hand-made lexer.
+    Candidate,
+}
+
+/// The point at which one of the lexers stopped because no rule matches the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stop {
+    /// The byte offset of the input no rule matches.
+    pub offset: usize,
+    /// The lexer that stopped.
+    pub side: Side,
+}
+
+/// A region in which the two token streams disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// The indices of the library lexer's tokens in this region.
+    pub lib: Range<usize>,
+    /// The indices of the hand-made lexer's tokens in this region.
+    pub hand: Range<usize>,
+}
+
+impl Hunk {
+    /// Returns the kind of disagreement in this hunk.
+    pub fn kind(&self) -> MismatchKind {
+        if self.hand.is_empty() {
+            MismatchKind::Missing
+        } else if self.lib.is_empty() {
+            MismatchKind::Extra
+        } else {
+            MismatchKind::Changed
+        }
+    }
+}
+
+/// The alignment of two token streams.
+///
+/// The streams are called the library and hand-made streams after the usual pair of lexers,
+/// but any two lexers can be compared; `with_labels` names them in the rendered report.
+pub struct TokenDiff {
+    /// The regions in which the streams disagree, in input order.
+    hunks: Vec<Hunk>,
+    /// The names of the reference and candidate lexers.
+    labels: [&'static str; 2],
+    /// The first point at which a lexer stopped, if one did.
+    stop: Option<Stop>,
+    /// Whether the tokens from the stop onwards were left out of the alignment.
+    truncated: bool,
+}
+
+impl TokenDiff {
+    /// Aligns the token streams of the two lexers.
+    ///
+    /// Tokens only match if their kind, lexeme and position are all equal.
+    ///
+    /// # Arguments
+    ///
+    /// * `lib` - The tokens produced by the library lexer.
+    /// * `hand` - The tokens produced by the hand-made lexer.
+    ///
+    /// # Returns
+    ///
+    /// A `TokenDiff` holding every region in which the streams disagree.
+    pub fn new(lib: &[Token], hand: &[Token]) -> TokenDiff {
+        // Strip the common prefix and suffix, which keeps the alignment cheap for the usual
+        // case of a few local differences.
+        let prefix = lib.iter().zip(hand).take_while(|(a, b)| a == b).count();
+        let suffix = lib[prefix..]
+            .iter()
+            .rev()
+            .zip(hand[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let lib_middle = &lib[prefix..lib.len() - suffix];
+        let hand_middle = &hand[prefix..hand.len() - suffix];
+
+        let mut hunks: Vec<Hunk> = Vec::new();
+        let mut current: Option<Hunk> = None;
+        for edit in shortest_edit(lib_middle, hand_middle) {
+            let (lib_index, hand_index) = match edit {
+                Edit::Equal(..) => {
+                    hunks.extend(current.take());
+                    continue;
+                }
+                Edit::LibOnly(index, at) => (prefix + index, prefix + at),
+                Edit::HandOnly(at, index) => (prefix + at, prefix + index),
+            };
+            let hunk = current.get_or_insert(Hunk {
+                lib: lib_index..lib_index,
+                hand: hand_index..hand_index,
+            });
+            match edit {
+                Edit::LibOnly(..) => hunk.lib.end = lib_index + 1,
+                _ => hunk.hand.end = hand_index + 1,
+            }
+        }
+        hunks.extend(current);
+
+        TokenDiff {
+            hunks,
+            labels: ["library", "hand-made"],
+            stop: None,
+            truncated: false,
+        }
+    }
+
+    /// Aligns the token streams of two lexers, up to the offset at which one of them stopped.
+    ///
+    /// A lexer such as the library lexer stops at the first character no rule matches, while
+    /// the hand-made lexers skip it and carry on. The tokens starting at or after the stop are
+    /// left out on both sides, since the lexer that stopped has nothing to compare them to.
+    /// This is the rule both the `compare` subcommand and the differential tests check.
+    ///
+    /// # Arguments
+    ///
+    /// * `lib` - The tokens produced by the reference lexer.
+    /// * `hand` - The tokens produced by the candidate lexer.
+    /// * `stop` - The earliest point at which either lexer stopped, if one did.
+    ///
+    /// # Returns
+    ///
+    /// A `TokenDiff` holding every region before the stop in which the streams disagree, and
+    /// the stop itself. Its hunks index into the full streams, so they can be rendered with
+    /// them.
+    pub fn until(lib: &[Token], hand: &[Token], stop: Option<Stop>) -> TokenDiff {
+        let Some(stop) = stop else {
+            return TokenDiff::new(lib, hand);
+        };
+        let before =
+            |tokens: &[Token]| tokens.partition_point(|token| token.span.start < stop.offset);
+        let mut diff = TokenDiff::new(&lib[..before(lib)], &hand[..before(hand)]);
+        diff.stop = Some(stop);
+        diff.truncated = true;
+        diff
+    }
+
+    /// Records the point at which a lexer stopped and was restarted after the input no rule
+    /// matches, so that the tokens on both sides of it were still aligned.
+    ///
+    /// # Arguments
+    ///
+    /// * `stop` - The first point at which either lexer stopped, if one did.
+    ///
+    /// # Returns
+    ///
+    /// The same `TokenDiff` with the stop recorded.
+    pub fn with_stop(mut self, stop: Option<Stop>) -> TokenDiff {
+        self.stop = stop;
+        self.truncated = false;
+        self
+    }
+
+    /// Names the two lexers in the rendered report.
+    ///
+    /// # Arguments
+    ///
+    /// * `reference` - The name of the lexer whose tokens were passed first.
+    /// * `candidate` - The name of the lexer whose tokens were passed second.
+    ///
+    /// # Returns
+    ///
+    /// The same `TokenDiff` with the new names.
+    pub fn with_labels(mut self, reference: &'static str, candidate: &'static str) -> TokenDiff {
+        self.labels = [reference, candidate];
+        self
+    }
+
+    /// Checks whether the two token streams are identical, up to the stop if they were
+    /// truncated there.
+    pub fn is_empty(&self) -> bool {
+        self.hunks.is_empty()
+    }
+
+    /// Returns the first point at which a lexer stopped, if one did.
+    pub fn stop(&self) -> Option<Stop> {
+        self.stop
+    }
+
+    /// Checks whether the tokens from the stop onwards were left out of the alignment.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns the regions in which the token streams disagree.
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// Returns the kind of the first disagreement, or `None` if the streams are identical.
+    pub fn first_kind(&self) -> Option<MismatchKind> {
+        self.hunks.first().map(Hunk::kind)
+    }
+
+    /// Renders a report of the first disagreement.
+    ///
+    /// The report names the line and column of the first divergence, shows the surrounding
+    /// source with the differing tokens underlined, and lists the kind and lexeme of the
+    /// tokens on both sides. The number of further disagreements is given at the end, followed
+    /// by the point at which a lexer stopped, if one did. Without disagreements, only that
+    /// point is shown.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The lexed source code.
+    /// * `lib` - The tokens produced by the library lexer.
+    /// * `hand` - The tokens produced by the hand-made lexer.
+    ///
+    /// # Returns
+    ///
+    /// The rendered report, or an empty string if the streams are identical and no lexer
+    /// stopped.
+    pub fn render(&self, input: &str, lib: &[Token], hand: &[Token]) -> String {
+        let lines = LineIndex::new(input);
+        let Some(hunk) = self.hunks.first() else {
+            let mut report = String::new();
+            if let Some(stop) = self.stop {
+                let width = input[stop.offset..]
+                    .chars()
+                    .next()
+                    .map_or(0, char::len_utf8);
+                let (line, column) = lines.line_col(stop.offset);
+                let _ = writeln!(
+                    report,
+                    "first divergence at line {}, column {}: {}",
+                    line,
+                    column,
+                    self.describe_stop(stop, "")
+                );
+                show_source(&mut report, input, &lines, stop.offset, stop.offset + width);
+            }
+            return report;
+        };
+        let lib_tokens = &lib[hunk.lib.clone()];
+        let hand_tokens = &hand[hunk.hand.clone()];
+
+        // Locate the divergence at the earliest differing token of either side.
+        let start = lib_tokens
+            .iter()
+            .chain(hand_tokens)
+            .map(|token| token.span.start)
+            .min()
+            .unwrap();
+        let end = lib_tokens
+            .iter()
+            .chain(hand_tokens)
+            .map(|token| token.span.end)
+            .max()
+            .unwrap();
+        let (line, column) = lines.line_col(start);
+
+        let mut report = String::new();
+        let _ = writeln!(
+            report,
+            "first divergence at line {}, column {}: {}",
+            line,
+            column,
+            hunk.kind().describe(self.labels[1])
+        );
+        show_source(&mut report, input, &lines, start, end);
+
+        let label_width = self
+            .labels
+            .iter()
+            .map(|label| label.len() + 1)
+            .max()
+            .unwrap();
+        for (label, tokens) in self.labels.iter().zip([lib_tokens, hand_tokens]) {
+            let _ = writeln!(
+                report,
+                "  {:<label_width$} {}",
+                format!("{}:", label),
+                list_tokens(tokens)
+            );
+        }
+        if self.hunks.len() > 1 {
+            let _ = writeln!(report, "{} more differing regions", self.hunks.len() - 1);
+        }
+        if let Some(stop) = self.stop {
+            let (line, column) = lines.line_col(stop.offset);
+            let at = format!(" at line {}, column {}", line, column);
+            let _ = writeln!(report, "{}", self.describe_stop(stop, &at));
+        }
+        report
+    }
+
+    /// Describes the point at which a lexer stopped, and what was compared after it.
+    ///
+    /// # Arguments
+    ///
+    /// * `stop` - The point at which the lexer stopped.
+    /// * `at` - The position of the stop, or an empty string if it is given elsewhere.
+    fn describe_stop(&self, stop: Stop, at: &str) -> String {
+        let label = match stop.side {
+            Side::Reference => self.labels[0],
+            Side::Candidate => self.labels[1],
+        };
+        let compared = if self.truncated {
+            ", so the tokens from there on were not compared"
+        } else {
+            " and was restarted after it"
+        };
+        format!(
+            "the {} lexer stopped{} where no rule matches{}",
+            label, at, compared
+        )
+    }
+}
+
+/// Shows the line of a divergence and the line before it, with the divergence underlined.
+///
+/// # Arguments
+///
+/// * `report` - The report the source is appended to.
+/// * `input` - The lexed source code.
+/// * `lines` - The line index of `input`.
+/// * `start` - The byte offset the divergence starts at.
+/// * `end` - The byte offset the divergence ends at; only its first line is underlined.
+fn show_source(report: &mut String, input: &str, lines: &LineIndex, start: usize, end: usize) {
+    let (line, column) = lines.line_col(start);
+    let width = line.to_string().len();
+    for number in line.saturating_sub(1).max(1)..=line {
+        let _ = writeln!(report, "  {:>width$} | {}", number, lines.line_text(number));
+    }
+    // The column counts characters, so the end of the line is found from its byte offset.
+    let line_end = lines.line_start(line) + lines.line_text(line).len();
+    let underline = input[start..end.min(line_end)].chars().count().max(1);
+    let _ = writeln!(
+        report,
+        "  {:>width$} | {}{}",
+        "",
+        " ".repeat(column - 1),
+        "^".repeat(underline)
+    );
+}
+
+/// Lists the kind, lexeme and position of a few tokens on one line.
+fn list_tokens(tokens: &[Token]) -> String {
+    if tokens.is_empty() {
+        return "(no tokens)".to_string();
+    }
+    let mut listed: Vec<String> = tokens
+        .iter()
+        .take(MAX_LISTED_TOKENS)
+        .map(|token| {
+            format!(
+                "{} {:?} at {}:{}",
+                token.kind, token.lexeme, token.line, token.column
+            )
+        })
+        .collect();
+    if tokens.len() > MAX_LISTED_TOKENS {
+        listed.push(format!("... {} more", tokens.len() - MAX_LISTED_TOKENS));
+    }
+    listed.join(", ")
+}
+
+/// A single step of an edit script turning one token stream into another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    /// The tokens at these library and hand-made indices match.
+    Equal(usize, usize),
+    /// The library token at the first index has no counterpart; the second index is the
+    /// position in the hand-made stream at which it is missing.
+    LibOnly(usize, usize),
+    /// The hand-made token at the second index has no counterpart; the first index is the
+    /// position in the library stream at which it is extra.
+    HandOnly(usize, usize),
+}
+
+/// Computes a shortest edit script between two token streams using Myers' algorithm.
+///
+/// The linear-space variant is used: the middle snake of an optimal path splits the streams in
+/// two, and each half is aligned on its own, so only two diagonal vectors are kept however far
+/// apart the streams are.
+///
+/// # Arguments
+///
+/// * `a` - The library lexer's tokens.
+/// * `b` - The hand-made lexer's tokens.
+///
+/// # Returns
+///
+/// The edit script in input order.
+fn shortest_edit(a: &[Token], b: &[Token]) -> Vec<Edit> {
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    if a.is_empty() || b.is_empty() {
+        // Nothing can match, so the search is not needed.
+        edits.extend((0..a.len()).map(|x| Edit::LibOnly(x, 0)));
+        edits.extend((0..b.len()).map(|y| Edit::HandOnly(0, y)));
+        return edits;
+    }
+    let max = (a.len() + b.len()).div_ceil(2) + 1;
+    let mut forward = vec![0; 2 * max + 1];
+    let mut backward = vec![0; 2 * max + 1];
+    align(
+        a,
+        b,
+        0..a.len(),
+        0..b.len(),
+        [&mut forward, &mut backward],
+        &mut edits,
+    );
+    edits
+}
+
+/// Appends a shortest edit script between ranges of two token streams.
+///
+/// # Arguments
+///
+/// * `a` - The library lexer's tokens.
+/// * `b` - The hand-made lexer's tokens.
+/// * `xs` - The range of `a` to align.
+/// * `ys` - The range of `b` to align.
+/// * `vectors` - The forward and backward diagonal vectors, reused by every call.
+/// * `edits` - The edit script the alignment is appended to.
+fn align(
+    a: &[Token],
+    b: &[Token],
+    mut xs: Range<usize>,
+    mut ys: Range<usize>,
+    vectors: [&mut Vec<usize>; 2],
+    edits: &mut Vec<Edit>,
+) {
+    let prefix = common_prefix(&a[xs.clone()], &b[ys.clone()]);
+    edits.extend((0..prefix).map(|i| Edit::Equal(xs.start + i, ys.start + i)));
+    xs.start += prefix;
+    ys.start += prefix;
+    let suffix = common_suffix(&a[xs.clone()], &b[ys.clone()]);
+    xs.end -= suffix;
+    ys.end -= suffix;
+
+    if xs.is_empty() || ys.is_empty() {
+        edits.extend(xs.clone().map(|x| Edit::LibOnly(x, ys.start)));
+        edits.extend(ys.clone().map(|y| Edit::HandOnly(xs.start, y)));
+    } else {
+        let [forward, backward] = vectors;
+        let (x, y) = middle_snake(a, b, xs.clone(), ys.clone(), forward, backward);
+        align(
+            a,
+            b,
+            xs.start..x,
+            ys.start..y,
+            [&mut *forward, &mut *backward],
+            edits,
+        );
+        align(a, b, x..xs.end, y..ys.end, [forward, backward], edits);
+    }
+
+    edits.extend((0..suffix).map(|i| Edit::Equal(xs.end + i, ys.end + i)));
+}
+
+/// Finds a point on an optimal path through two ranges that differ at both ends.
+///
+/// Paths are searched forwards from the start and backwards from the end at the same time,
+/// one edit at a time, until they overlap. `forward[k]` holds the furthest x reached on
+/// diagonal k from the start, and `backward[k]` the furthest distance reached from the end.
+///
+/// # Returns
+///
+/// The indices into `a` and `b` at which the middle snake of an optimal path starts.
+fn middle_snake(
+    a: &[Token],
+    b: &[Token],
+    xs: Range<usize>,
+    ys: Range<usize>,
+    forward: &mut [usize],
+    backward: &mut [usize],
+) -> (usize, usize) {
+    let n = xs.len() as isize;
+    let m = ys.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let offset = (forward.len() / 2) as isize;
+    let index = |k: isize| (k + offset) as usize;
+    forward[index(1)] = 0;
+    backward[index(1)] = 0;
+
+    for d in 0..offset {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let y = (x as isize - k) as usize;
+            let start = (xs.start + x, ys.start + y);
+            if x < xs.len() && y < ys.len() {
+                x += common_prefix(&a[xs.start + x..xs.end], &b[ys.start + y..ys.end]);
+            }
+            forward[index(k)] = x;
+            if odd && (k - delta).abs() < d && x + backward[index(delta - k)] >= xs.len() {
+                return start;
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            if x < xs.len() && y < ys.len() {
+                let shared = common_suffix(&a[xs.start..xs.end - x], &b[ys.start..ys.end - y]);
+                x += shared;
+                y += shared;
+            }
+            backward[index(k)] = x;
+            if !odd && (k - delta).abs() <= d && x + forward[index(delta - k)] >= xs.len() {
+                return (xs.end - x, ys.end - y);
+            }
+        }
+    }
+    unreachable!("the forward and backward paths meet within (n + m) / 2 edits")
+}
+
+/// Returns the number of equal tokens at the start of two streams.
+fn common_prefix(a: &[Token], b: &[Token]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// Returns the number of equal tokens at the end of two streams.
+fn common_suffix(a: &[Token], b: &[Token]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
@@ -0,0 +1,166 @@
+//! Module for decoding the escape sequences of string literals.
+//! The lexers return string literals as raw lexemes, quotes included. Decoding is an optional
+//! post-processing step that stores the value of every string literal next to its lexeme, and
+//! reports every invalid escape sequence at the exact line and column it starts at.
+
+use crate::token::{LexError, LineIndex, Span, Token, Value};
+
+/// The kind of the tokens holding string literals in `oat.l`.
+pub const STRING_KIND: &str = "STRINGLITERAL";
+
+/// The largest value of a `\xHH` escape, which is limited to ASCII.
+const MAX_HEX_ESCAPE: u32 = 0x7f;
+
+/// The largest number of hexadecimal digits in a `\u{...}` escape.
+const MAX_UNICODE_DIGITS: usize = 6;
+
+/// An invalid escape sequence in a string literal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapeError {
+    /// A description of the problem.
+    pub message: String,
+    /// The byte range of the escape sequence, relative to the start of the lexeme.
+    pub span: Span,
+}
+
+/// Decodes the escape sequences of a string literal.
+///
+/// The supported escapes are `\n`, `\t`, `\\`, `\"`, `\xHH` with two hexadecimal digits up to
+/// `\x7f`, and `\u{...}` with one to six hexadecimal digits naming a Unicode scalar value.
+/// Decoding goes on after an invalid escape, so that all of them are reported at once.
+///
+/// # Arguments
+///
+/// * `lexeme` - The string literal. Surrounding double quotes are removed before decoding.
+///
+/// # Returns
+///
+/// The decoded string, or every invalid escape sequence found.
+pub fn decode_string(lexeme: &str) -> Result<String, Vec<EscapeError>> {
+    let (body, offset) = match lexeme
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        Some(body) => (body, 1),
+        None => (lexeme, 0),
+    };
+
+    let mut value = String::with_capacity(body.len());
+    let mut errors = Vec::new();
+    let mut chars = body.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        let decoded = decode_escape(&mut chars);
+        match decoded {
+            Ok(c) => value.push(c),
+            Err(problem) => {
+                let end = chars.peek().map_or(body.len(), |&(index, _)| index);
+                errors.push(EscapeError {
+                    message: format!("Escape sequence '{}' {}", &body[start..end], problem),
+                    span: Span::new(offset + start, offset + end),
+                });
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(value)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Decodes every string literal in a token stream.
+///
+/// The decoded value is stored in the `value` of each `STRINGLITERAL` token. Tokens with an
+/// invalid escape keep no value.
+///
+/// # Arguments
+///
+/// * `input` - The lexed input, used to locate invalid escapes.
+/// * `tokens` - The tokens lexed from `input`.
+///
+/// # Returns
+///
+/// An error for every invalid escape sequence, in input order.
+pub fn decode_strings(input: &str, tokens: &mut [Token]) -> Vec<LexError> {
+    let lines = LineIndex::new(input);
+    let mut errors = Vec::new();
+    for token in tokens.iter_mut().filter(|token| token.kind == STRING_KIND) {
+        match decode_string(&token.lexeme) {
+            Ok(value) => token.value = Some(Value::Str(value)),
+            Err(escape_errors) => {
+                errors.extend(escape_errors.into_iter().map(|err| {
+                    let span = Span::new(
+                        token.span.start + err.span.start,
+                        token.span.start + err.span.end,
+                    );
+                    LexError::new(err.message, span, &lines)
+                }));
+            }
+        }
+    }
+    errors
+}
+
+/// Decodes a single escape sequence after its backslash.
+///
+/// # Arguments
+///
+/// * `chars` - The characters following the backslash. The escape sequence is consumed, and
+///   so is the valid prefix of an invalid one.
+///
+/// # Returns
+///
+/// The escaped character, or the problem with the escape sequence.
+fn decode_escape(
+    chars: &mut std::iter::Peekable<std::str::CharIndices>,
+) -> Result<char, &'static str> {
+    match chars.next().map(|(_, c)| c) {
+        None => Err("is unfinished"),
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('\\') => Ok('\\'),
+        Some('"') => Ok('"'),
+        Some('x') => {
+            let digits = hex_digits(chars, 2);
+            if digits.len() != 2 {
+                return Err("needs two hexadecimal digits");
+            }
+            let code = u32::from_str_radix(&digits, 16).expect("digits are hexadecimal");
+            if code > MAX_HEX_ESCAPE {
+                return Err("is above '\\x7f'");
+            }
+            Ok(char::from_u32(code).expect("ASCII is a valid char"))
+        }
+        Some('u') => {
+            if chars.next_if(|&(_, c)| c == '{').is_none() {
+                return Err("needs hexadecimal digits in braces");
+            }
+            let digits = hex_digits(chars, MAX_UNICODE_DIGITS + 1);
+            if chars.next_if(|&(_, c)| c == '}').is_none()
+                || digits.is_empty()
+                || digits.len() > MAX_UNICODE_DIGITS
+            {
+                return Err("needs one to six hexadecimal digits in braces");
+            }
+            let code = u32::from_str_radix(&digits, 16).expect("digits are hexadecimal");
+            char::from_u32(code).ok_or("is not a Unicode scalar value")
+        }
+        Some(_) => Err("is not supported"),
+    }
+}
+
+/// Consumes up to `max` hexadecimal digits.
+fn hex_digits(chars: &mut std::iter::Peekable<std::str::CharIndices>, max: usize) -> String {
+    let mut digits = String::new();
+    while digits.len() < max
+        && let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_hexdigit())
+    {
+        digits.push(c);
+    }
+    digits
+}
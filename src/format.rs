@@ -0,0 +1,191 @@
+//! Module for writing token streams in human-readable and machine-readable formats.
+//! Every format carries the same information per token: the source file, the token kind,
+//! the lexeme, the byte span, the line and column the token starts at, and the value of
+//! literal tokens when literal values were requested.
+
+use crate::token::{Token, Value};
+use clap::ValueEnum;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The supported token output formats.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned columns of kind and lexeme, with a header per file when there are several.
+    Table,
+    /// A single JSON array holding the tokens of all files.
+    Json,
+    /// One JSON object per line and token.
+    Jsonl,
+    /// Comma-separated values with a header row.
+    Csv,
+    /// One S-expression per line and token.
+    Sexp,
+}
+
+/// Writes the tokens of one or more files in the given format.
+///
+/// # Arguments
+///
+/// * `out` - The writer the output is written to.
+/// * `format` - The output format.
+/// * `files` - The lexed files, each with its tokens.
+///
+/// # Returns
+///
+/// An error if writing to `out` fails.
+pub fn write_tokens<W: Write>(
+    out: &mut W,
+    format: Format,
+    files: &[(&Path, &[Token])],
+) -> io::Result<()> {
+    let tokens = files
+        .iter()
+        .flat_map(|(path, tokens)| tokens.iter().map(move |token| (*path, token)));
+
+    match format {
+        Format::Table => {
+            for (path, tokens) in files {
+                if files.len() > 1 {
+                    writeln!(out, "==> {} <==", path.display())?;
+                }
+                for token in *tokens {
+                    match &token.value {
+                        Some(value) => writeln!(out, "{} {}", token, value)?,
+                        None => writeln!(out, "{}", token)?,
+                    }
+                }
+            }
+        }
+        Format::Json => {
+            write!(out, "[")?;
+            for (index, (path, token)) in tokens.enumerate() {
+                let separator = if index == 0 { "" } else { "," };
+                write!(out, "{}\n  {}", separator, json_object(path, token))?;
+            }
+            writeln!(out, "\n]")?;
+        }
+        Format::Jsonl => {
+            for (path, token) in tokens {
+                writeln!(out, "{}", json_object(path, token))?;
+            }
+        }
+        Format::Csv => {
+            writeln!(out, "file,kind,lexeme,start,end,line,column,value")?;
+            for (path, token) in tokens {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{},{}",
+                    csv_field(&path.display().to_string()),
+                    csv_field(&token.kind),
+                    csv_field(&token.lexeme),
+                    token.span.start,
+                    token.span.end,
+                    token.line,
+                    token.column,
+                    token
+                        .value
+                        .as_ref()
+                        .map(|value| render_value(value, csv_field))
+                        .unwrap_or_default()
+                )?;
+            }
+        }
+        Format::Sexp => {
+            for (path, token) in tokens {
+                writeln!(
+                    out,
+                    "(token :file {} :kind {} :lexeme {} :span ({} . {}) :line {} :column {}{})",
+                    sexp_string(&path.display().to_string()),
+                    sexp_string(&token.kind),
+                    sexp_string(&token.lexeme),
+                    token.span.start,
+                    token.span.end,
+                    token.line,
+                    token.column,
+                    token.value.as_ref().map_or(String::new(), |value| {
+                        format!(" :value {}", render_value(value, sexp_string))
+                    })
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders a token as a single-line JSON object.
+fn json_object(path: &Path, token: &Token) -> String {
+    format!(
+        "{{\"file\":{},\"kind\":{},\"lexeme\":{},\"span\":{{\"start\":{},\"end\":{}}},\"line\":{},\"column\":{}{}}}",
+        json_string(&path.display().to_string()),
+        json_string(&token.kind),
+        json_string(&token.lexeme),
+        token.span.start,
+        token.span.end,
+        token.line,
+        token.column,
+        token.value.as_ref().map_or(String::new(), |value| {
+            format!(",\"value\":{}", render_value(value, json_string))
+        })
+    )
+}
+
+/// Renders the value of a literal token, quoting strings with `quote`.
+///
+/// Integers, booleans and `null` are written the same way in every format.
+fn render_value(value: &Value, quote: fn(&str) -> String) -> String {
+    match value {
+        Value::Str(value) => quote(value),
+        value => value.to_string(),
+    }
+}
+
+/// Quotes a string as a JSON string literal.
+///
+/// Quotes, backslashes and all control characters are escaped, so the result never spans
+/// more than one line.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quotes a CSV field if it contains a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Quotes a string as an S-expression string literal.
+fn sexp_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
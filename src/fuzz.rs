@@ -0,0 +1,162 @@
+//! Module for differential testing of the two lexer implementations.
+//! It turns raw fuzzer or property-test bytes into Oat source code, lexes it with both the
+//! library lexer and the hand-made lexer, and checks that they agree. Failing inputs can be
+//! saved as regression files next to the other test inputs.
+//!
+//! The module is only built with the `fuzzing` feature, which the fuzz target and the tests
+//! enable.
+
+use crate::diff::{Side, Stop, TokenDiff};
+use crate::lex4oat::CompiledLexer;
+use crate::liblex4oat::LibLex4Oat;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Source fragments covering every rule of `oat.l`, plus the whitespace between tokens.
+///
+/// Fragments are concatenated without separators, so neighbouring fragments also combine
+/// into longer tokens, such as keywords running into identifiers.
+pub const ALPHABET: &[&str] = &[
+    "global",
+    "var",
+    "null",
+    "true",
+    "false",
+    "new",
+    "return",
+    "for",
+    "while",
+    "if",
+    "if?",
+    "else",
+    "void",
+    "int",
+    "bool",
+    "struct",
+    "string",
+    "length",
+    "(",
+    ")",
+    "{",
+    "}",
+    ",",
+    "[",
+    "]",
+    ";",
+    "=",
+    ".",
+    "?",
+    "->",
+    "\"\"",
+    "\"str\"",
+    "\"a b\\n\"",
+    "x",
+    "_y1",
+    "Foo",
+    "0",
+    "42",
+    "-7",
+    "0x1f",
+    "*",
+    "+",
+    "-",
+    "%",
+    "<<",
+    ">>",
+    ">>>",
+    "<",
+    "<=",
+    ">",
+    ">=",
+    "==",
+    "!=",
+    "&",
+    "|",
+    "[&]",
+    "[|]",
+    "~",
+    "!",
+    "/**/",
+    "/* c */",
+    "/* a*b **/",
+    "// c",
+    "/// d",
+    "/",
+    "\"",
+    " ",
+    " ",
+    "\n",
+    "\t",
+    "\r\n",
+];
+
+/// Builds an Oat source string by using every byte as an index into `ALPHABET`.
+pub fn alphabet_input(data: &[u8]) -> String {
+    data.iter()
+        .map(|&byte| ALPHABET[byte as usize % ALPHABET.len()])
+        .collect()
+}
+
+/// Builds a source string by mapping every byte to an ASCII character.
+///
+/// The hand-made lexer only knows the ASCII alphabet, so bytes are folded into it rather than
+/// decoded as UTF-8.
+pub fn ascii_input(data: &[u8]) -> String {
+    data.iter().map(|&byte| (byte & 0x7f) as char).collect()
+}
+
+/// Lexes an input with both lexer implementations and checks that they agree.
+///
+/// As in the `compare` subcommand, the library lexer restarts after every character no rule
+/// matches, which the hand-made lexer skips, so the tokens are compared up to the end of the
+/// input.
+///
+/// # Arguments
+///
+/// * `input` - The source code to lex.
+/// * `compiled` - The compiled hand-made lexer.
+///
+/// # Returns
+///
+/// `Ok(())` if both lexers produced the same tokens, or a report of the first divergence.
+pub fn check_backends(input: &str, compiled: &CompiledLexer) -> Result<(), String> {
+    let mut lib_lexer = LibLex4Oat::new(input.to_owned());
+    lib_lexer.lex_resuming();
+    let lib_tokens = lib_lexer.tokens();
+
+    let hand_tokens = compiled.lex(input);
+
+    let stop = lib_lexer.error_offset().map(|offset| Stop {
+        offset,
+        side: Side::Reference,
+    });
+    let diff = TokenDiff::new(lib_tokens, &hand_tokens).with_stop(stop);
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(diff.render(input, lib_tokens, &hand_tokens))
+    }
+}
+
+/// Saves an input as a regression file in a directory.
+///
+/// The file is named after a hash of its contents, so saving the same input twice does not
+/// create a second file.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to save the file in, usually `test/`.
+/// * `input` - The source code to save.
+///
+/// # Returns
+///
+/// The path of the saved file.
+pub fn save_regression(dir: &Path, input: &str) -> io::Result<PathBuf> {
+    // FNV-1a keeps the file names stable across Rust versions, unlike `DefaultHasher`.
+    let hash = input.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    let path = dir.join(format!("fuzz-{:016x}.oat", hash));
+    std::fs::write(&path, input)?;
+    Ok(path)
+}
@@ -0,0 +1,297 @@
+//! Module for generating random strings from the hand-made lexer's DFA.
+//! Bounded random walks over the DFA produce lexemes accepted by a given token rule, and single
+//! edits of such lexemes produce near misses that the rule rejects. Every generator is seeded,
+//! so the same seed always yields the same strings for the same specification.
+
+use crate::dfa::Dfa;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+/// The whitespace placed between the tokens of a synthetic token stream.
+///
+/// The skipped `;` rule of a specification is not part of the DFA, so it cannot be sampled.
+const SEPARATORS: &[&str] = &[" ", "  ", "\n", "\t", "\r\n"];
+
+/// The maximum number of edits tried when looking for a near miss.
+const MAX_NEAR_MISS_ATTEMPTS: usize = 64;
+
+/// A small pseudo-random number generator (SplitMix64).
+///
+/// It is kept in-tree so that a seed yields the same strings on every platform and with every
+/// version of the dependencies.
+struct SplitMix64 {
+    /// The current state, advanced on every call.
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// Returns the next pseudo-random number.
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a pseudo-random number below `bound`, which must not be zero.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+/// A token of a synthetic token stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntheticToken {
+    /// The name of the rule the lexeme was generated from.
+    pub kind: String,
+    /// The generated lexeme.
+    pub lexeme: String,
+}
+
+/// A seeded generator of strings accepted or just rejected by the rules of a DFA.
+///
+/// A string is accepted by a rule if the DFA ends in an accepting state named after the rule
+/// after consuming the whole string, i.e. if lexing the string on its own yields exactly one
+/// token of that rule. Strings that an earlier rule claims, such as keywords for the
+/// identifier rule, are therefore never generated for the later rule.
+pub struct Generator<'a> {
+    /// The DFA to walk.
+    dfa: &'a Dfa,
+    /// The source of randomness.
+    rng: SplitMix64,
+    /// The maximum length of a generated lexeme, in characters.
+    max_len: usize,
+    /// Every character on a DFA transition, in ascending order.
+    alphabet: Vec<char>,
+    /// Per rule, the distance from every state to the nearest state accepting the rule.
+    distances: HashMap<String, HashMap<usize, usize>>,
+}
+
+impl<'a> Generator<'a> {
+    /// Creates a new generator.
+    ///
+    /// # Arguments
+    ///
+    /// * `dfa` - The constructed DFA whose rules are sampled.
+    /// * `seed` - The seed of the random walks.
+    /// * `max_len` - The maximum length of a generated lexeme, in characters.
+    ///
+    /// # Returns
+    ///
+    /// A new `Generator`.
+    pub fn new(dfa: &'a Dfa, seed: u64, max_len: usize) -> Generator<'a> {
+        let alphabet: BTreeSet<char> = dfa
+            .get_nodes()
+            .values()
+            .flat_map(|node| node.get_outgoing_edges())
+            .flat_map(|edge| edge.get_sym().chars())
+            .collect();
+        Generator {
+            dfa,
+            rng: SplitMix64 { state: seed },
+            max_len,
+            alphabet: alphabet.into_iter().collect(),
+            distances: HashMap::new(),
+        }
+    }
+
+    /// Returns the names of all rules with an accepting state, in ascending order.
+    pub fn rules(&self) -> Vec<String> {
+        let rules: BTreeSet<&str> = self
+            .dfa
+            .get_nodes()
+            .values()
+            .filter(|node| node.is_terminal())
+            .map(|node| node.get_name())
+            .collect();
+        rules.into_iter().map(str::to_string).collect()
+    }
+
+    /// Generates a lexeme accepted by a rule.
+    ///
+    /// The walk starts at the root and only follows transitions from which an accepting state
+    /// of the rule is still reachable within the length bound. Once such a state is reached,
+    /// the walk stops there with a probability of one in three, or when it cannot continue.
+    ///
+    /// # Arguments
+    ///
+    /// * `rule` - The name of the rule.
+    ///
+    /// # Returns
+    ///
+    /// The generated lexeme, or `None` if the rule accepts no string within the length bound.
+    pub fn sample(&mut self, rule: &str) -> Option<String> {
+        let dfa = self.dfa;
+        let distances = self
+            .distances
+            .entry(rule.to_string())
+            .or_insert_with(|| rule_distances(dfa, rule));
+
+        let mut state = dfa.get_root_id();
+        let mut remaining = self.max_len;
+        if distances
+            .get(&state)
+            .is_none_or(|&distance| distance > remaining)
+        {
+            return None;
+        }
+
+        let mut lexeme = String::new();
+        loop {
+            let node = &dfa.get_nodes()[&state];
+            let accepting = node.is_terminal() && node.get_name() == rule;
+            let edges: Vec<_> = node
+                .get_outgoing_edges()
+                .iter()
+                .filter(|edge| {
+                    distances
+                        .get(&edge.get_to())
+                        .is_some_and(|&distance| distance < remaining)
+                })
+                .collect();
+            if accepting && (edges.is_empty() || self.rng.below(3) == 0) {
+                return Some(lexeme);
+            }
+
+            // A state that does not accept the rule is always within reach of one that does.
+            let edge = edges[self.rng.below(edges.len())];
+            let symbols: Vec<char> = edge.get_sym().chars().collect();
+            lexeme.push(symbols[self.rng.below(symbols.len())]);
+            state = edge.get_to();
+            remaining -= 1;
+        }
+    }
+
+    /// Generates a string just outside a rule.
+    ///
+    /// An accepted lexeme is edited once, by truncating it, appending a character, replacing a
+    /// character or deleting a character, until the result is a non-empty string the rule does
+    /// not accept.
+    ///
+    /// # Arguments
+    ///
+    /// * `rule` - The name of the rule.
+    ///
+    /// # Returns
+    ///
+    /// The near miss, or `None` if none was found.
+    pub fn near_miss(&mut self, rule: &str) -> Option<String> {
+        for _ in 0..MAX_NEAR_MISS_ATTEMPTS {
+            let mut chars: Vec<char> = self.sample(rule)?.chars().collect();
+            let len = chars.len();
+            match self.rng.below(4) {
+                0 if len > 1 => chars.truncate(1 + self.rng.below(len - 1)),
+                1 => chars.push(self.random_char()),
+                2 if len > 0 => chars[self.rng.below(len)] = self.random_char(),
+                3 if len > 1 => {
+                    chars.remove(self.rng.below(len));
+                }
+                _ => continue,
+            }
+
+            let candidate: String = chars.into_iter().collect();
+            if !candidate.is_empty() && self.dfa.classify(&candidate) != Some(rule) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    /// Generates a synthetic token stream.
+    ///
+    /// Tokens are drawn from random rules and separated by random whitespace that ends the
+    /// previous token. Rules without a lexeme within the length bound are never drawn.
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - The number of tokens to generate.
+    ///
+    /// # Returns
+    ///
+    /// The source text and the tokens a lexer is expected to produce for it.
+    pub fn token_stream(&mut self, count: usize) -> (String, Vec<SyntheticToken>) {
+        let rules: Vec<String> = self
+            .rules()
+            .into_iter()
+            .filter(|rule| self.sample(rule).is_some_and(|lexeme| !lexeme.is_empty()))
+            .collect();
+
+        let mut input = String::new();
+        let mut tokens = Vec::new();
+        if rules.is_empty() {
+            return (input, tokens);
+        }
+        while tokens.len() < count {
+            let rule = &rules[self.rng.below(rules.len())];
+            let Some(lexeme) = self.sample(rule).filter(|lexeme| !lexeme.is_empty()) else {
+                continue;
+            };
+            if let Some(previous) = tokens.last().map(|token: &SyntheticToken| &token.lexeme) {
+                // The separator must end the previous token, as only a line break ends a line
+                // comment.
+                let separator = SEPARATORS[self.rng.below(SEPARATORS.len())];
+                let joined: Vec<char> = previous
+                    .chars()
+                    .chain(separator.chars())
+                    .chain(lexeme.chars())
+                    .collect();
+                if self.dfa.longest_match(&joined) != Some(previous.chars().count()) {
+                    continue;
+                }
+                input.push_str(separator);
+            }
+            input.push_str(&lexeme);
+            tokens.push(SyntheticToken {
+                kind: rule.clone(),
+                lexeme,
+            });
+        }
+        (input, tokens)
+    }
+
+    /// Returns a random character from the DFA's alphabet.
+    fn random_char(&mut self) -> char {
+        self.alphabet[self.rng.below(self.alphabet.len())]
+    }
+}
+
+/// Computes the distance from every DFA state to the nearest state accepting a rule.
+///
+/// # Arguments
+///
+/// * `dfa` - The constructed DFA.
+/// * `rule` - The name of the rule.
+///
+/// # Returns
+///
+/// A map from state ID to the number of transitions needed; states from which no accepting
+/// state of the rule is reachable are missing.
+fn rule_distances(dfa: &Dfa, rule: &str) -> HashMap<usize, usize> {
+    let mut predecessors: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (&id, node) in dfa.get_nodes() {
+        for edge in node.get_outgoing_edges() {
+            predecessors.entry(edge.get_to()).or_default().push(id);
+        }
+    }
+
+    // Search backwards from every accepting state of the rule.
+    let mut distances: HashMap<usize, usize> = HashMap::new();
+    let mut queue: VecDeque<usize> = VecDeque::new();
+    for (&id, node) in dfa.get_nodes() {
+        if node.is_terminal() && node.get_name() == rule {
+            distances.insert(id, 0);
+            queue.push_back(id);
+        }
+    }
+    while let Some(id) = queue.pop_front() {
+        let distance = distances[&id] + 1;
+        for &from in predecessors.get(&id).into_iter().flatten() {
+            if let Entry::Vacant(entry) = distances.entry(from) {
+                entry.insert(distance);
+                queue.push_back(from);
+            }
+        }
+    }
+    distances
+}
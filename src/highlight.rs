@@ -0,0 +1,134 @@
+//! Module for rendering source files with their tokens colored by category.
+//! The lossless token stream is rendered piece by piece, so the output shows the input text
+//! unchanged. Keywords, types, literals, comments, operators and identifiers get their own
+//! colors, text no rule matched is marked as an error, and whitespace is left as is.
+
+use crate::category::Category;
+use crate::channel::{Channel, UNKNOWN_KIND};
+use crate::token::Token;
+use clap::ValueEnum;
+use colored::{ColoredString, Colorize};
+use std::fmt::Write;
+
+/// The class of the text no rule matched, in HTML output.
+const ERROR_CLASS: &str = "error";
+
+/// The style sheet of HTML output, with a rule for every category and for errors.
+const STYLE_SHEET: &str = "body { background: #fafafa; color: #24292e; }
+pre.oat { font-family: monospace; line-height: 1.4; }
+.keyword { color: #a626a4; font-weight: bold; }
+.type { color: #0184bc; }
+.string { color: #50a14f; }
+.number { color: #986801; }
+.comment { color: #a0a1a7; font-style: italic; }
+.operator { color: #4078f2; }
+.identifier { color: #24292e; }
+.error { color: #e45649; text-decoration: wavy underline; }
+";
+
+/// The supported highlighting outputs.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// Text with ANSI color codes, for terminals.
+    Ansi,
+    /// A self-contained HTML page with a CSS class per category.
+    Html,
+}
+
+/// Renders an input with its tokens colored by category.
+///
+/// # Arguments
+///
+/// * `style` - The output to render.
+/// * `title` - The title of an HTML page, usually the name of the file.
+/// * `input` - The lexed input.
+/// * `tokens` - The tokens lexed from `input`, by either lexer.
+///
+/// # Returns
+///
+/// The rendered input.
+pub fn highlight(style: Style, title: &str, input: &str, tokens: Vec<Token>) -> String {
+    let pieces = Channel::Lossless.select(input, tokens);
+    match style {
+        Style::Ansi => ansi(&pieces),
+        Style::Html => html(title, &pieces),
+    }
+}
+
+/// Renders the pieces of a lossless token stream with ANSI color codes.
+///
+/// Every line of a piece is colored on its own, so that pagers showing part of a multi-line
+/// comment still color it.
+fn ansi(pieces: &[Token]) -> String {
+    let mut out = String::new();
+    for piece in pieces {
+        let paint: Option<fn(&str) -> ColoredString> = match Category::of_kind(&piece.kind) {
+            Some(Category::Keyword) => Some(|text| text.bright_magenta().bold()),
+            Some(Category::Type) => Some(|text| text.bright_cyan()),
+            Some(Category::String) => Some(|text| text.green()),
+            Some(Category::Number) => Some(|text| text.yellow()),
+            Some(Category::Comment) => Some(|text| text.bright_black().italic()),
+            Some(Category::Operator) => Some(|text| text.bright_blue()),
+            Some(Category::Identifier) => Some(|text| text.white()),
+            None if piece.kind == UNKNOWN_KIND => Some(|text| text.red().underline()),
+            None => None,
+        };
+        let Some(paint) = paint else {
+            out.push_str(&piece.lexeme);
+            continue;
+        };
+        for (index, line) in piece.lexeme.split('\n').enumerate() {
+            if index > 0 {
+                out.push('\n');
+            }
+            if !line.is_empty() {
+                write!(out, "{}", paint(line)).expect("writing to a String cannot fail");
+            }
+        }
+    }
+    out
+}
+
+/// Renders the pieces of a lossless token stream as an HTML page.
+fn html(title: &str, pieces: &[Token]) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    writeln!(out, "<title>{}</title>", escape_html(title))
+        .expect("writing to a String cannot fail");
+    writeln!(out, "<style>\n{}</style>", STYLE_SHEET).expect("writing to a String cannot fail");
+    out.push_str("</head>\n<body>\n<pre class=\"oat\"><code>");
+    for piece in pieces {
+        let class = match Category::of_kind(&piece.kind) {
+            Some(category) => category.name(),
+            None if piece.kind == UNKNOWN_KIND => ERROR_CLASS,
+            None => {
+                out.push_str(&escape_html(&piece.lexeme));
+                continue;
+            }
+        };
+        write!(
+            out,
+            "<span class=\"{}\">{}</span>",
+            class,
+            escape_html(&piece.lexeme)
+        )
+        .expect("writing to a String cannot fail");
+    }
+    out.push_str("</code></pre>\n</body>\n</html>\n");
+    out
+}
+
+/// Escapes the characters of a text that are special in HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,251 @@
+//! Module for re-lexing edited inputs incrementally.
+//! An editor changes a few characters at a time. Instead of lexing the whole buffer again, the
+//! DFA is restarted after the last token that was cut without reading the edited text, and is
+//! stopped as soon as a token ends where an old token ended past the edit, in the same modes.
+//! From there on, the old tokens are reused with their positions shifted.
+
+use crate::dfa::{Dfa, Resume};
+use crate::token::{LexError, LineIndex, Span, Token};
+use std::ops::{ControlFlow, Range};
+
+/// A change to the text of an input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// The bytes of the input that are replaced, at character boundaries.
+    pub range: Span,
+    /// The text replacing them.
+    pub replacement: String,
+}
+
+impl Edit {
+    /// Creates a new edit replacing the bytes `start..end` with some text.
+    pub fn new(start: usize, end: usize, replacement: &str) -> Edit {
+        Edit {
+            range: Span::new(start, end),
+            replacement: replacement.to_string(),
+        }
+    }
+}
+
+/// The tokens an edit changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// The indices of the tokens that were replaced, in the token stream before the edit.
+    pub old: Range<usize>,
+    /// The indices of the tokens replacing them, in the token stream after the edit.
+    pub new: Range<usize>,
+    /// The number of tokens the DFA cut again, including unchanged ones around the edit.
+    pub relexed: usize,
+}
+
+/// How far lexing had read when a token was cut, and the modes after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Checkpoint {
+    /// One past the last byte examined up to and including the token, or the input length
+    /// plus one if the end of the input was reached.
+    reach: usize,
+    /// The modes after the token, with their repeat counts.
+    modes: Vec<(usize, usize)>,
+}
+
+/// A token stream kept up to date with an input as it is edited.
+pub struct IncrementalLexer<'d> {
+    /// The DFA the input is lexed with.
+    dfa: &'d Dfa,
+    /// The current input.
+    input: String,
+    /// The tokens of the current input.
+    tokens: Vec<Token>,
+    /// The checkpoint of every token.
+    checkpoints: Vec<Checkpoint>,
+    /// The problems found in the current input.
+    errors: Vec<LexError>,
+}
+
+impl<'d> IncrementalLexer<'d> {
+    /// Lexes an input in full.
+    ///
+    /// # Arguments
+    ///
+    /// * `dfa` - The DFA to lex with.
+    /// * `input` - The input to be lexed.
+    ///
+    /// # Returns
+    ///
+    /// A new `IncrementalLexer` holding the tokens of `input`.
+    pub fn new(dfa: &'d Dfa, input: String) -> IncrementalLexer<'d> {
+        let mut lexer = IncrementalLexer {
+            dfa,
+            input: String::new(),
+            tokens: Vec::new(),
+            checkpoints: Vec::new(),
+            errors: Vec::new(),
+        };
+        lexer.apply(&Edit::new(0, 0, &input));
+        lexer
+    }
+
+    /// Returns the current input.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Returns the tokens of the current input.
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Returns the problems found in the current input.
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    /// Applies an edit to the input and updates the tokens.
+    ///
+    /// The result is the same as lexing the edited input in full.
+    ///
+    /// # Arguments
+    ///
+    /// * `edit` - The change to the input.
+    ///
+    /// # Returns
+    ///
+    /// The range of tokens that were replaced, and the range of tokens replacing them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range of the edit is out of the input or not at character boundaries.
+    pub fn apply(&mut self, edit: &Edit) -> Change {
+        let Span { start, end } = edit.range;
+        // Lexing from an offset only depends on the text before it through whether the
+        // offset starts a line.
+        let old_starts_line = self.input[..end].ends_with('\n');
+        self.input.replace_range(start..end, &edit.replacement);
+        let new_end = start + edit.replacement.len();
+        let new_starts_line = self.input[..new_end].ends_with('\n');
+        // The offsets at or after the edit in the old input move by the length difference.
+        let shift = |offset: usize| offset - end + new_end;
+
+        let kept = self
+            .checkpoints
+            .partition_point(|checkpoint| checkpoint.reach <= start);
+        let resume = match kept {
+            0 => Resume::initial(),
+            _ => Resume {
+                offset: self.tokens[kept - 1].span.end,
+                modes: self.checkpoints[kept - 1].modes.clone(),
+            },
+        };
+        let old_tokens = self.tokens.split_off(kept);
+        let old_checkpoints = self.checkpoints.split_off(kept);
+        let old_errors = std::mem::take(&mut self.errors);
+        let (mut errors, old_errors): (Vec<LexError>, Vec<LexError>) = old_errors
+            .into_iter()
+            .partition(|err| err.span.end <= resume.offset);
+
+        let mut reach = self
+            .checkpoints
+            .last()
+            .map_or(0, |checkpoint| checkpoint.reach);
+        // The index of the old token the new tokens lined up with, once they did.
+        let mut synced = None;
+        let mut candidate = 0;
+        let tokens = &mut self.tokens;
+        let checkpoints = &mut self.checkpoints;
+        errors.extend(
+            self.dfa
+                .lex_from(&self.input, resume, |token, token_reach, modes| {
+                    let token_end = token.span.end;
+                    tokens.push(token);
+                    reach = reach.max(token_reach);
+                    checkpoints.push(Checkpoint {
+                        reach,
+                        modes: modes.to_vec(),
+                    });
+                    let past_edit = token_end > new_end
+                        || (token_end == new_end && new_starts_line == old_starts_line);
+                    if !past_edit {
+                        return ControlFlow::Continue(());
+                    }
+                    let old_end = token_end - new_end + end;
+                    while candidate < old_tokens.len() && old_tokens[candidate].span.end < old_end {
+                        candidate += 1;
+                    }
+                    match old_tokens.get(candidate) {
+                        Some(old)
+                            if old.span.end == old_end
+                                && old_checkpoints[candidate].modes == modes =>
+                        {
+                            synced = Some(candidate);
+                            ControlFlow::Break(())
+                        }
+                        _ => ControlFlow::Continue(()),
+                    }
+                }),
+        );
+
+        let lines = LineIndex::new(&self.input);
+        let relexed = self.tokens.len() - kept;
+        let replaced = synced.map_or(old_tokens.len(), |index| index + 1);
+        let mut change = Change {
+            old: kept..kept + replaced,
+            new: kept..self.tokens.len(),
+            relexed,
+        };
+        // Leave out the tokens that came out the same on either side of the edit.
+        while !change.old.is_empty()
+            && !change.new.is_empty()
+            && self.tokens[change.new.start] == old_tokens[change.old.start - kept]
+        {
+            change.old.start += 1;
+            change.new.start += 1;
+        }
+        while !change.old.is_empty() && !change.new.is_empty() {
+            let old = &old_tokens[change.old.end - 1 - kept];
+            if old.span.start < end
+                || self.tokens[change.new.end - 1] != shift_token(old.clone(), shift, &lines)
+            {
+                break;
+            }
+            change.old.end -= 1;
+            change.new.end -= 1;
+        }
+
+        if let Some(index) = synced {
+            let synced_end = old_tokens[index].span.end;
+            for (token, checkpoint) in old_tokens.into_iter().zip(old_checkpoints).skip(replaced) {
+                self.tokens.push(shift_token(token, shift, &lines));
+                self.checkpoints.push(Checkpoint {
+                    reach: shift(checkpoint.reach).max(reach),
+                    modes: checkpoint.modes,
+                });
+            }
+            errors.extend(
+                old_errors
+                    .into_iter()
+                    .filter(|err| err.span.start >= synced_end)
+                    .map(|err| {
+                        let span = Span::new(shift(err.span.start), shift(err.span.end));
+                        LexError::new(err.message, span, &lines)
+                    }),
+            );
+        }
+        self.errors = errors;
+        change
+    }
+}
+
+/// Moves a token that follows an edit to its position in the edited input.
+///
+/// # Arguments
+///
+/// * `token` - The token, positioned in the input before the edit.
+/// * `shift` - Maps an offset after the edit in the old input to the edited input.
+/// * `lines` - The line index of the edited input.
+fn shift_token(token: Token, shift: impl Fn(usize) -> usize, lines: &LineIndex) -> Token {
+    let span = Span::new(shift(token.span.start), shift(token.span.end));
+    Token {
+        value: token.value,
+        ..Token::new(token.kind, token.lexeme, span, lines)
+    }
+}
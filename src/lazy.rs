@@ -0,0 +1,226 @@
+//! Module for constructing a DFA lazily while lexing.
+//! Instead of determinizing every reachable subset of NFA states up front, states are only
+//! built when the input reaches them. They are kept in a cache of bounded size that is flushed
+//! and rebuilt from scratch whenever it fills up, so memory stays bounded on large specs.
+
+use crate::dfa::maximal_munch;
+use crate::nfa::Nfa;
+use crate::token::{LexError, Token};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A DFA state built on demand.
+struct LazyState {
+    /// The NFA states this DFA state stands for.
+    nfa_states: BTreeSet<usize>,
+    /// The ID of the NFA node whose rule this state accepts, if any.
+    accept: Option<usize>,
+    /// The transitions computed so far; `None` marks a character the state cannot move on.
+    next: HashMap<char, Option<usize>>,
+}
+
+/// A bounded cache of lazily built DFA states.
+///
+/// States are addressed by their index, which stays valid until the next flush.
+struct StateCache {
+    /// The index of every cached state, keyed by its NFA states.
+    ids: HashMap<BTreeSet<usize>, usize>,
+    /// The cached states; the start states come first.
+    states: Vec<LazyState>,
+    /// The number of start states, which are kept when the cache is flushed.
+    starts: usize,
+    /// The indices of the start states of every mode, used elsewhere and at the start of a
+    /// line.
+    mode_starts: Vec<[usize; 2]>,
+}
+
+impl StateCache {
+    /// Adds a state to the cache, or returns the index of an equal cached state.
+    ///
+    /// # Arguments
+    ///
+    /// * `nfa` - The NFA the states are built from.
+    /// * `nfa_states` - The epsilon-closed set of NFA states.
+    ///
+    /// # Returns
+    ///
+    /// The index of the state.
+    fn intern(&mut self, nfa: &Nfa, nfa_states: BTreeSet<usize>) -> usize {
+        if let Some(&index) = self.ids.get(&nfa_states) {
+            return index;
+        }
+        // The rule defined first wins, as in the eagerly built DFA.
+        let accept = nfa_states
+            .iter()
+            .find(|id| nfa.get_nodes()[id].is_terminal())
+            .copied();
+        let index = self.states.len();
+        self.ids.insert(nfa_states.clone(), index);
+        self.states.push(LazyState {
+            nfa_states,
+            accept,
+            next: HashMap::new(),
+        });
+        index
+    }
+
+    /// Removes every state except the start states.
+    fn flush(&mut self) {
+        self.states.truncate(self.starts);
+        for state in &mut self.states {
+            state.next.clear();
+        }
+        let starts = self.starts;
+        self.ids.retain(|_, &mut index| index < starts);
+    }
+}
+
+/// A DFA whose states are determinized from the NFA as the input reaches them.
+///
+/// Every call to `lex` borrows a cache from a shared pool and returns it afterwards, so
+/// concurrent calls from several threads each work on their own cache. At most one cache per
+/// thread is kept, and each holds at most `limit` states.
+pub struct LazyDfa {
+    /// The NFA the states are built from.
+    nfa: Arc<Nfa>,
+    /// The start states of every mode, used elsewhere and at the start of a line.
+    starts: Vec<[BTreeSet<usize>; 2]>,
+    /// The maximum number of states in a cache.
+    limit: usize,
+    /// The caches not currently used by a call to `lex`.
+    pool: Mutex<Vec<StateCache>>,
+    /// The number of times a cache has been flushed.
+    flushes: AtomicUsize,
+}
+
+impl LazyDfa {
+    /// Creates a new lazy DFA.
+    ///
+    /// # Arguments
+    ///
+    /// * `nfa` - The fully constructed NFA, owned or already shared.
+    /// * `limit` - The maximum number of cached states; values below the number of distinct
+    ///   start states plus one are raised to it.
+    ///
+    /// # Returns
+    ///
+    /// A new `LazyDfa` with no states built yet.
+    pub fn new(nfa: impl Into<Arc<Nfa>>, limit: usize) -> LazyDfa {
+        let nfa = nfa.into();
+        let starts: Vec<[BTreeSet<usize>; 2]> = (0..nfa.get_mode_names().len())
+            .map(|mode| [nfa.start_set(mode, false), nfa.start_set(mode, true)])
+            .collect();
+        let distinct: BTreeSet<&BTreeSet<usize>> = starts.iter().flatten().collect();
+        let min_limit = distinct.len() + 1;
+        LazyDfa {
+            nfa,
+            starts,
+            limit: limit.max(min_limit),
+            pool: Mutex::new(Vec::new()),
+            flushes: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of times a state cache has been flushed.
+    pub fn flushes(&self) -> usize {
+        self.flushes.load(Ordering::Relaxed)
+    }
+
+    /// Lexes an input string, building DFA states as they are reached.
+    ///
+    /// Tokens are cut with maximal munch exactly like `Dfa::lex`, so both produce the same
+    /// tokens.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input string to be lexed.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tokens with their positions in `input`.
+    pub fn lex(&self, input: &str) -> Vec<Token> {
+        self.lex_with_errors(input).0
+    }
+
+    /// Lexes an input string, building DFA states as they are reached, and reports problems
+    /// in the input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input string to be lexed.
+    ///
+    /// # Returns
+    ///
+    /// The tokens with their positions in `input`, and the problems found.
+    pub fn lex_with_errors(&self, input: &str) -> (Vec<Token>, Vec<LexError>) {
+        let cache = self.pool.lock().unwrap().pop().unwrap_or_else(|| {
+            let mut cache = StateCache {
+                ids: HashMap::new(),
+                states: Vec::new(),
+                starts: 0,
+                mode_starts: Vec::new(),
+            };
+            for [start, line_start] in &self.starts {
+                let start = cache.intern(&self.nfa, start.clone());
+                let line_start = cache.intern(&self.nfa, line_start.clone());
+                cache.mode_starts.push([start, line_start]);
+            }
+            cache.starts = cache.states.len();
+            cache
+        });
+        let cache = RefCell::new(cache);
+
+        let lexed = maximal_munch(
+            input,
+            &self.nfa,
+            |mode, at_line_start| cache.borrow().mode_starts[mode][usize::from(at_line_start)],
+            |&index, ch| self.step(&mut cache.borrow_mut(), index, ch),
+            |&index| {
+                let accept = cache.borrow().states[index].accept;
+                accept.map(|id| (self.nfa.get_nodes()[&id].get_name(), Some(id)))
+            },
+        );
+
+        self.pool.lock().unwrap().push(cache.into_inner());
+        lexed
+    }
+
+    /// Moves a cached state over a character, building the next state if needed.
+    ///
+    /// If the cache is full, it is flushed before the next state is added, and the index of
+    /// the next state is only valid in the flushed cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - The cache holding the state.
+    /// * `index` - The index of the state.
+    /// * `ch` - The transition character.
+    ///
+    /// # Returns
+    ///
+    /// The index of the next state, or `None` if the state cannot move on `ch`.
+    fn step(&self, cache: &mut StateCache, index: usize, ch: char) -> Option<usize> {
+        if let Some(&next) = cache.states[index].next.get(&ch) {
+            return next;
+        }
+
+        let moved = self.nfa.move_on(&cache.states[index].nfa_states, ch);
+        let closure = self.nfa.epsilon_closure(&moved);
+        if closure.is_empty() {
+            cache.states[index].next.insert(ch, None);
+            return None;
+        }
+
+        if !cache.ids.contains_key(&closure) && cache.states.len() >= self.limit {
+            // The transition is not recorded, as the state it leaves may be gone.
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+            cache.flush();
+            return Some(cache.intern(&self.nfa, closure));
+        }
+        let next = cache.intern(&self.nfa, closure);
+        cache.states[index].next.insert(ch, Some(next));
+        Some(next)
+    }
+}
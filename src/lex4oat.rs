@@ -1,17 +1,123 @@
 use crate::dfa::Dfa;
 use crate::nfa::Nfa;
-use std::cell::RefCell;
-use std::path::PathBuf;
-use std::rc::Rc;
+use crate::token::{LexError, Token};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// An immutable lexer automaton built from a token specification.
+///
+/// A `CompiledLexer` owns the NFA constructed from the specification and the DFA derived
+/// from it. It carries no per-input state, so one instance can be wrapped in an `Arc` and
+/// used to tokenize many inputs from several threads at the same time.
+pub struct CompiledLexer {
+    /// The NFA the DFA was constructed from.
+    nfa: Arc<Nfa>,
+    /// The DFA used for lexing.
+    dfa: Dfa,
+}
+
+// A compiled lexer is meant to be shared between worker threads.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<CompiledLexer>();
+};
+
+impl CompiledLexer {
+    /// Builds a compiled lexer from a fully constructed NFA.
+    ///
+    /// # Arguments
+    ///
+    /// * `nfa` - The NFA holding all token rules, owned or already shared.
+    ///
+    /// # Returns
+    ///
+    /// A `CompiledLexer` whose DFA has been constructed from `nfa`.
+    pub fn from_nfa(nfa: impl Into<Arc<Nfa>>) -> CompiledLexer {
+        let nfa = nfa.into();
+        let mut dfa = Dfa::new();
+        dfa.set_nfa(nfa.clone());
+        dfa.construct_dfa();
+        CompiledLexer { nfa, dfa }
+    }
+
+    /// Builds a compiled lexer from the token rules in a specification file.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The path of the `.l` specification file.
+    ///
+    /// # Returns
+    ///
+    /// A `CompiledLexer` for the rules in `file`, or an error if a rule regex is malformed.
+    pub fn from_spec_file(file: &Path) -> Result<CompiledLexer, String> {
+        let mut nfa = Nfa::new();
+        nfa.add_keywords_from_file(file);
+        nfa.construct()?;
+        Ok(CompiledLexer::from_nfa(nfa))
+    }
+
+    /// Builds a compiled lexer from the token rules of a specification.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec` - The contents of a `.l` specification file.
+    ///
+    /// # Returns
+    ///
+    /// A `CompiledLexer` for the rules in `spec`, or an error if a rule regex is malformed.
+    pub fn from_spec(spec: &str) -> Result<CompiledLexer, String> {
+        let mut nfa = Nfa::new();
+        nfa.add_keywords(spec);
+        nfa.construct()?;
+        Ok(CompiledLexer::from_nfa(nfa))
+    }
+
+    /// Returns a reference to the NFA.
+    pub fn nfa(&self) -> &Nfa {
+        &self.nfa
+    }
+
+    /// Returns a reference to the DFA.
+    pub fn dfa(&self) -> &Dfa {
+        &self.dfa
+    }
+
+    /// Lexes an input string using the compiled DFA.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The source code to be lexed.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tokens with their positions in `input`.
+    pub fn lex(&self, input: &str) -> Vec<Token> {
+        self.dfa.lex(input)
+    }
+
+    /// Lexes an input string using the compiled DFA, reporting problems in the input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The source code to be lexed.
+    ///
+    /// # Returns
+    ///
+    /// The tokens with their positions in `input`, and the problems found, such as nested
+    /// comments that are never closed.
+    pub fn lex_with_errors(&self, input: &str) -> (Vec<Token>, Vec<LexError>) {
+        self.dfa.lex_with_errors(input)
+    }
+}
 
 /// A lexer for the Oat language that utilizes both NFA and DFA to perform lexical analysis.
 pub struct Lex4Oat {
     /// The input source code to be lexed.
     input: String,
-    /// Reference counted, mutable reference to the NFA used for constructing token rules.
-    nfa: Rc<RefCell<Nfa>>,
-    /// Reference counted, mutable reference to the DFA used for lexing.
-    dfa: Rc<RefCell<Dfa>>,
+    /// The NFA used for constructing token rules, consumed by `construct_dfa`.
+    nfa: Nfa,
+    /// The compiled lexer, available once the DFA has been constructed.
+    compiled: Option<Arc<CompiledLexer>>,
 }
 
 impl Lex4Oat {
@@ -25,25 +131,49 @@ impl Lex4Oat {
     ///
     /// An instance of `Lex4Oat`.
     pub fn new(input: String) -> Lex4Oat {
-        let nfa = Rc::new(RefCell::new(Nfa::new()));
-        let dfa = Rc::new(RefCell::new(Dfa::new()));
-        Lex4Oat { input, nfa, dfa }
+        Lex4Oat {
+            input,
+            nfa: Nfa::new(),
+            compiled: None,
+        }
+    }
+
+    /// Creates a new instance of `Lex4Oat` that reuses an already compiled lexer.
+    ///
+    /// No NFA or DFA construction is needed before calling `lex` on the result.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - A string containing the source code.
+    /// * `compiled` - A shared compiled lexer.
+    ///
+    /// # Returns
+    ///
+    /// An instance of `Lex4Oat`.
+    pub fn with_compiled(input: String, compiled: Arc<CompiledLexer>) -> Lex4Oat {
+        Lex4Oat {
+            input,
+            nfa: Nfa::new(),
+            compiled: Some(compiled),
+        }
     }
 
     /// Constructs the NFA by adding keywords from a file and building the overall automaton.
     ///
     /// The keywords are read from the file located at `src/oat.l`.
-    pub fn construct_nfa(&mut self) {
-        self.nfa
-            .borrow_mut()
-            .add_keywords_from_file(&PathBuf::from("src/oat.l"));
-        self.nfa.borrow_mut().construct();
+    ///
+    /// # Returns
+    ///
+    /// An error if a rule regex of the file is malformed.
+    pub fn construct_nfa(&mut self) -> Result<(), String> {
+        self.nfa.add_keywords_from_file(&PathBuf::from("src/oat.l"));
+        self.nfa.construct()
     }
 
-    /// Constructs the DFA by setting the NFA for the DFA and performing the DFA construction.
+    /// Constructs the DFA from the NFA and freezes both into a `CompiledLexer`.
     pub fn construct_dfa(&mut self) {
-        self.dfa.borrow_mut().set_nfa(self.nfa.clone());
-        self.dfa.borrow_mut().construct_dfa();
+        let nfa = std::mem::take(&mut self.nfa);
+        self.compiled = Some(Arc::new(CompiledLexer::from_nfa(nfa)));
     }
 
     /// Minimizes the DFA.
@@ -51,12 +181,28 @@ impl Lex4Oat {
     /// Currently a placeholder method for DFA minimization logic.
     pub fn minimize_dfa(&mut self) {}
 
+    /// Returns the compiled lexer so it can be shared with other `Lex4Oat` instances.
+    ///
+    /// # Returns
+    ///
+    /// The shared `CompiledLexer`, or `None` if the DFA has not been constructed yet.
+    pub fn compiled(&self) -> Option<Arc<CompiledLexer>> {
+        self.compiled.clone()
+    }
+
     /// Lexes the input string using the constructed DFA.
     ///
     /// # Returns
     ///
-    /// A vector of tuples where each tuple contains the token type and its corresponding lexeme.
-    pub fn lex(&mut self) -> Vec<(String, String)> {
-        self.dfa.borrow_mut().lex(&self.input)
+    /// A vector of tokens with their positions in the input.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the DFA has not been constructed.
+    pub fn lex(&self) -> Vec<Token> {
+        self.compiled
+            .as_ref()
+            .expect("DFA must be constructed before lexing")
+            .lex(&self.input)
     }
 }
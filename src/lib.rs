@@ -0,0 +1,30 @@
+//! Lexers for the Oat language.
+//!
+//! The crate provides two independent lexers that are expected to agree on every input:
+//! a library lexer generated by `lrlex` from `oat.l` ([`liblex4oat`]), and a hand-made
+//! lexer that builds an NFA from the same rules and converts it into a DFA
+//! ([`lex4oat`], [`nfa`], [`dfa`]).
+
+pub mod category;
+pub mod channel;
+pub mod derivative;
+pub mod dfa;
+pub mod diff;
+pub mod escape;
+pub mod format;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+pub mod generate;
+pub mod highlight;
+pub mod incremental;
+pub mod lazy;
+pub mod lex4oat;
+pub mod liblex4oat;
+pub mod literal;
+pub mod lsp;
+pub mod nfa;
+pub mod node;
+pub mod reduce;
+pub mod sources;
+pub mod spec;
+pub mod token;
@@ -2,20 +2,49 @@
 use colored::Colorize;
 use log::error;
 // Import lrlex and lrpar for lexer definition and lexeme handling.
-use lrlex::{lrlex_mod, LexerDef};
-use lrpar::{Lexeme, Lexer, NonStreamingLexer};
+use lrlex::{DefaultLexerTypes, LRNonStreamingLexerDef, LexerDef, lrlex_mod};
+use lrpar::{LexError, Lexeme, Lexer, NonStreamingLexer};
+// Import the shared token representation.
+use crate::token::{LineIndex, Span, Token};
 
 // Generates modules and lexer definitions from the oat.l file.
 lrlex_mod!("oat.l");
 
+/// A lexer definition of the library lexer.
+pub type LibLexerDef = LRNonStreamingLexerDef<DefaultLexerTypes>;
+
+/// Builds a library lexer definition from a specification at runtime.
+///
+/// Unlike the definition generated from `oat.l` at compile time, this one follows the given
+/// specification, so both lexers can be compared on the same rules.
+///
+/// # Arguments
+///
+/// * `spec` - The contents of the specification file.
+///
+/// # Returns
+///
+/// The lexer definition, or the errors `lrlex` reported, one per line.
+pub fn lexer_def_from_spec(spec: &str) -> Result<LibLexerDef, String> {
+    LibLexerDef::from_str(spec).map_err(|errors| {
+        errors
+            .iter()
+            .map(|err| err.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    })
+}
+
 /// A library lexer for the Oat language using lrlex and lrpar.
 ///
 /// This struct holds the input source code and tokens extracted from lexical analysis.
 pub struct LibLex4Oat {
     /// The input source code as a string.
     input: String,
-    /// A vector of tuples mapping token names to their lexemes.
-    tokens: Vec<(String, String)>,
+    /// The tokens extracted from the input.
+    tokens: Vec<Token>,
+    /// The byte offsets at which no rule matched, in input order.
+    stops: Vec<usize>,
 }
 
 impl LibLex4Oat {
@@ -32,6 +61,7 @@ impl LibLex4Oat {
         LibLex4Oat {
             input,
             tokens: Vec::new(),
+            stops: Vec::new(),
         }
     }
 
@@ -39,38 +69,134 @@ impl LibLex4Oat {
     ///
     /// # Returns
     ///
-    /// A reference to a vector containing tuples of token name and token lexeme.
-    pub fn tokens(&self) -> &Vec<(String, String)> {
+    /// A reference to a vector containing the tokens with their positions in the input.
+    pub fn tokens(&self) -> &Vec<Token> {
         &self.tokens
     }
 
+    /// Returns the byte offset at which the last call to `lex` stopped because of an error.
+    ///
+    /// # Returns
+    ///
+    /// The offset of the first input the lexer could not match, or `None` if the whole input
+    /// was lexed.
+    pub fn error_offset(&self) -> Option<usize> {
+        self.stops.first().copied()
+    }
+
+    /// Returns the byte offsets at which no rule matched during the last call to `lex` or
+    /// `lex_resuming_with`.
+    ///
+    /// # Returns
+    ///
+    /// The offsets in input order. `lex` and `lex_with` stop at the first one, so they
+    /// record at most one.
+    pub fn stops(&self) -> &[usize] {
+        &self.stops
+    }
+
     /// Performs lexical analysis on the input source code.
     ///
-    /// This method uses `lrlex` to generate a lexer definition from `oat.l` and processes
-    /// the input code. Tokens are extracted by iterating over lexemes and are stored
-    /// along with their corresponding token names. In case of any lexer error, the error is logged.
+    /// This method uses the lexer definition `lrlex` generated from `oat.l` at compile time.
     pub fn lex(&mut self) {
-        let lexerdef = oat_l::lexerdef();
-        let lexer = lexerdef.lexer(&self.input);
+        self.lex_with(&oat_l::lexerdef());
+    }
+
+    /// Performs lexical analysis on the input source code, restarting after every character
+    /// no rule matches.
+    ///
+    /// This method uses the lexer definition `lrlex` generated from `oat.l` at compile time.
+    pub fn lex_resuming(&mut self) {
+        self.lex_resuming_with(&oat_l::lexerdef());
+    }
 
-        // Iterate through each lexeme generated by the lexer.
-        for lexeme in lexer.iter() {
-            match lexeme {
-                Ok(lexeme) => {
-                    // Get the lexeme slice from the input.
-                    let span = lexer.span_str(lexeme.span());
-                    // Retrieve token id and name based on the lexer definition.
-                    let tok_id = lexeme.tok_id();
-                    let tok_name = lexerdef.get_rule_by_id(tok_id).name().unwrap();
-                    // Store the token name and its lexeme.
-                    self.tokens.push((tok_name.to_string(), span.to_string()));
-                }
-                // Log the error and break the loop if any lexeme results in an error.
-                Err(err) => {
-                    error!("Library lexer error: {}", err.to_string().red());
-                    break;
-                }
+    /// Performs lexical analysis on the input source code with a given lexer definition.
+    ///
+    /// Tokens are extracted by iterating over lexemes and are stored along with their
+    /// corresponding token names and positions. In case of any lexer error, the error is
+    /// logged and its position recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `lexerdef` - The lexer definition, such as one from `lexer_def_from_spec`.
+    pub fn lex_with(&mut self, lexerdef: &LibLexerDef) {
+        let lines = LineIndex::new(&self.input);
+        self.tokens.clear();
+        self.stops.clear();
+        if let Some(stop) = lex_from(lexerdef, &self.input, 0, &lines, &mut self.tokens) {
+            self.stops.push(stop);
+        }
+    }
+
+    /// Performs lexical analysis on the input source code with a given lexer definition,
+    /// restarting after every character no rule matches.
+    ///
+    /// The hand-made lexers skip such characters, so restarting after them lets the tokens
+    /// of both lexers be compared up to the end of the input. The skipped offsets are
+    /// recorded in `stops`.
+    ///
+    /// # Arguments
+    ///
+    /// * `lexerdef` - The lexer definition, such as one from `lexer_def_from_spec`.
+    pub fn lex_resuming_with(&mut self, lexerdef: &LibLexerDef) {
+        let lines = LineIndex::new(&self.input);
+        self.tokens.clear();
+        self.stops.clear();
+        let mut start = 0;
+        while let Some(stop) = lex_from(lexerdef, &self.input, start, &lines, &mut self.tokens) {
+            self.stops.push(stop);
+            let width = self.input[stop..].chars().next().map_or(1, char::len_utf8);
+            start = stop + width;
+        }
+    }
+}
+
+/// Lexes an input from a byte offset with the library lexer, appending the tokens found.
+///
+/// # Arguments
+///
+/// * `lexerdef` - The lexer definition.
+/// * `input` - The whole input.
+/// * `start` - The byte offset to start at.
+/// * `lines` - The line index of the whole input.
+/// * `tokens` - The tokens to append to, with positions in the whole input.
+///
+/// # Returns
+///
+/// The offset at which no rule matched, or `None` if the rest of the input was lexed.
+fn lex_from(
+    lexerdef: &LibLexerDef,
+    input: &str,
+    start: usize,
+    lines: &LineIndex,
+    tokens: &mut Vec<Token>,
+) -> Option<usize> {
+    let lexer = lexerdef.lexer(&input[start..]);
+
+    // Iterate through each lexeme generated by the lexer.
+    for lexeme in lexer.iter() {
+        match lexeme {
+            Ok(lexeme) => {
+                // Get the lexeme slice from the input.
+                let span = lexeme.span();
+                let text = lexer.span_str(span);
+                // Retrieve token id and name based on the lexer definition.
+                let tok_id = lexeme.tok_id();
+                let tok_name = lexerdef.get_rule_by_id(tok_id).name().unwrap();
+                // Store the token name, its lexeme and its position in the whole input.
+                tokens.push(Token::new(
+                    tok_name.to_string(),
+                    text.to_string(),
+                    Span::new(start + span.start(), start + span.end()),
+                    lines,
+                ));
+            }
+            // Log the error and stop at the first lexeme that results in an error.
+            Err(err) => {
+                error!("Library lexer error: {}", err.to_string().red());
+                return Some(start + err.span().start());
             }
         }
     }
+    None
 }
@@ -0,0 +1,82 @@
+//! Module for parsing the values of literal tokens.
+//! Integer literals are parsed into `i64`, reporting the ones that overflow it, `true`,
+//! `false` and `null` get their values, and string literals are decoded by the escape module.
+
+use crate::escape::decode_strings;
+use crate::token::{LexError, LineIndex, Token, Value};
+use std::num::IntErrorKind;
+
+/// The kind of the tokens holding integer literals in `oat.l`.
+pub const INT_KIND: &str = "INTLITERAL";
+
+/// Parses an integer literal.
+///
+/// # Arguments
+///
+/// * `lexeme` - A decimal literal, or a hexadecimal one starting with `0x`, optionally
+///   preceded by `-`.
+///
+/// # Returns
+///
+/// The value of the literal, or a description of the problem if it is not a number or does
+/// not fit in an `i64`.
+pub fn parse_int(lexeme: &str) -> Result<i64, String> {
+    let (negative, magnitude) = match lexeme.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, lexeme),
+    };
+    let (digits, radix) = match magnitude.strip_prefix("0x") {
+        Some(digits) => (digits, 16),
+        None => (magnitude, 10),
+    };
+
+    let overflow = || format!("Integer literal {} overflows a 64-bit integer", lexeme);
+    let magnitude = match u64::from_str_radix(digits, radix) {
+        Ok(magnitude) if !digits.starts_with('+') => magnitude,
+        Err(err) if *err.kind() == IntErrorKind::PosOverflow => return Err(overflow()),
+        _ => return Err(format!("Integer literal {} is not a number", lexeme)),
+    };
+    if negative {
+        0i64.checked_sub_unsigned(magnitude).ok_or_else(overflow)
+    } else {
+        i64::try_from(magnitude).map_err(|_| overflow())
+    }
+}
+
+/// Parses the values of every literal in a token stream.
+///
+/// The value is stored in the `value` of each `INTLITERAL`, `TRUE`, `FALSE`, `NULL` and
+/// `STRINGLITERAL` token. Literals that cannot be parsed keep no value.
+///
+/// # Arguments
+///
+/// * `input` - The lexed input, used to locate invalid literals.
+/// * `tokens` - The tokens lexed from `input`.
+///
+/// # Returns
+///
+/// An error for every integer that overflows and every invalid escape sequence, in input
+/// order.
+pub fn parse_literals(input: &str, tokens: &mut [Token]) -> Vec<LexError> {
+    let lines = LineIndex::new(input);
+    let mut errors = Vec::new();
+    for token in tokens.iter_mut() {
+        token.value = match token.kind.as_str() {
+            INT_KIND => match parse_int(&token.lexeme) {
+                Ok(value) => Some(Value::Int(value)),
+                Err(message) => {
+                    errors.push(LexError::new(message, token.span, &lines));
+                    None
+                }
+            },
+            "TRUE" => Some(Value::Bool(true)),
+            "FALSE" => Some(Value::Bool(false)),
+            "NULL" => Some(Value::Null),
+            _ => continue,
+        };
+    }
+
+    errors.extend(decode_strings(input, tokens));
+    errors.sort_by_key(|err| err.span.start);
+    errors
+}
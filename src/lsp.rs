@@ -0,0 +1,693 @@
+//! Module for the language server behind the `lsp` subcommand.
+//! The server speaks the Language Server Protocol over a pair of streams, usually standard
+//! input and output. Every open document is lexed with the hand-made DFA and re-lexed
+//! incrementally as it is edited. Documents are colored with semantic tokens named after the
+//! categories of their token kinds, and every change publishes diagnostics for the lexical
+//! errors: unterminated literals and comments, and text no rule matches. Only the text around
+//! the tokens an edit changed is checked again.
+
+use crate::category::{Category, DOC_COMMENT_KIND};
+use crate::dfa::Dfa;
+use crate::incremental::{Change, Edit, IncrementalLexer};
+use crate::token::{LexError, LineIndex, Span, Token};
+use log::warn;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+/// The JSON-RPC error code of a message that is not valid JSON.
+const PARSE_ERROR: i64 = -32700;
+/// The JSON-RPC error code of a request that cannot be handled in the server's state.
+const INVALID_REQUEST: i64 = -32600;
+/// The JSON-RPC error code of a request for an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+/// The JSON-RPC error code of a request whose parameters are wrong.
+const INVALID_PARAMS: i64 = -32602;
+
+/// The LSP severity of errors.
+const SEVERITY_ERROR: u64 = 1;
+
+/// The `TextDocumentSyncKind` of documents synchronized by incremental changes.
+const SYNC_INCREMENTAL: u64 = 2;
+
+/// The only semantic token modifier, set on documentation comments.
+const DOCUMENTATION_MODIFIER: &str = "documentation";
+
+/// Reads a message framed by a `Content-Length` header.
+///
+/// # Arguments
+///
+/// * `reader` - The stream the client writes to.
+///
+/// # Returns
+///
+/// The body of the message, or `None` once the stream ends.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            let value = value.trim().parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid Content-Length header")
+            })?;
+            length = Some(value);
+        }
+    }
+
+    let length = length.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Message has no Content-Length header",
+        )
+    })?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Message is not UTF-8"))
+}
+
+/// Writes a message framed by a `Content-Length` header.
+///
+/// # Arguments
+///
+/// * `writer` - The stream the client reads from.
+/// * `message` - The JSON-RPC message.
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Serves a client until it sends `exit` or closes its stream.
+///
+/// # Arguments
+///
+/// * `dfa` - The DFA documents are lexed with.
+/// * `reader` - The stream the client writes to.
+/// * `writer` - The stream the client reads from.
+///
+/// # Returns
+///
+/// Whether the client asked the server to shut down before it exited.
+pub fn serve(dfa: &Dfa, mut reader: impl BufRead, mut writer: impl Write) -> io::Result<bool> {
+    let mut server = Server::new(dfa);
+    while let Some(body) = read_message(&mut reader)? {
+        let replies = match serde_json::from_str::<Value>(&body) {
+            Ok(message) if message["method"] == "exit" => break,
+            Ok(message) => server.handle(&message),
+            Err(err) => vec![error_response(Value::Null, PARSE_ERROR, err.to_string())],
+        };
+        for reply in &replies {
+            write_message(&mut writer, reply)?;
+        }
+    }
+    Ok(server.is_shut_down())
+}
+
+/// The state of a language server: its open documents, each with its tokens.
+pub struct Server<'d> {
+    /// The DFA documents are lexed with.
+    dfa: &'d Dfa,
+    /// The open documents, keyed by URI.
+    documents: HashMap<String, Document<'d>>,
+    /// Whether the client asked the server to shut down.
+    shut_down: bool,
+}
+
+impl<'d> Server<'d> {
+    /// Creates a server without open documents.
+    ///
+    /// # Arguments
+    ///
+    /// * `dfa` - The DFA documents are lexed with.
+    pub fn new(dfa: &'d Dfa) -> Server<'d> {
+        Server {
+            dfa,
+            documents: HashMap::new(),
+            shut_down: false,
+        }
+    }
+
+    /// Returns whether the client asked the server to shut down.
+    pub fn is_shut_down(&self) -> bool {
+        self.shut_down
+    }
+
+    /// Handles a request or notification from the client.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The JSON-RPC message. The `exit` notification is left to the caller.
+    ///
+    /// # Returns
+    ///
+    /// The messages to send back: the response to a request, and the diagnostics of a
+    /// document that was opened, changed or closed.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let Some(method) = message["method"].as_str() else {
+            let is_response = message.get("result").is_some() || message.get("error").is_some();
+            if is_response {
+                // The server sends no requests, so there is nothing to do with responses.
+                return Vec::new();
+            }
+            let message = "Message is neither a request nor a notification";
+            return vec![error_response(Value::Null, INVALID_REQUEST, message.into())];
+        };
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => vec![match self.request(method, params) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => error_response(id.clone(), code, message),
+            }],
+            None => self.notification(method, params),
+        }
+    }
+
+    /// Answers a request.
+    ///
+    /// # Returns
+    ///
+    /// The result of the request, or the error code and message of its failure.
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if self.shut_down {
+            return Err((INVALID_REQUEST, "Server is shut down".to_string()));
+        }
+        match method {
+            "initialize" => Ok(initialize_result()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(Value::Null)
+            }
+            "textDocument/semanticTokens/full" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let lexer = self
+                    .documents
+                    .get(uri)
+                    .ok_or_else(|| (INVALID_PARAMS, format!("Document {} is not open", uri)))?
+                    .lexer();
+                Ok(json!({ "data": semantic_tokens(lexer.input(), lexer.tokens()) }))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+        }
+    }
+
+    /// Follows a notification.
+    ///
+    /// # Returns
+    ///
+    /// The diagnostics of the document the notification opened, changed or closed, if any.
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str();
+        match (method, uri) {
+            ("textDocument/didOpen", Some(uri)) => {
+                let Some(text) = params["textDocument"]["text"].as_str() else {
+                    warn!("Ignoring {} without text", method);
+                    return Vec::new();
+                };
+                let document = Document::new(self.dfa, text.to_string());
+                self.documents.insert(uri.to_string(), document);
+            }
+            ("textDocument/didChange", Some(uri)) => {
+                let Some(document) = self.documents.get_mut(uri) else {
+                    warn!("Ignoring {} of {}, which is not open", method, uri);
+                    return Vec::new();
+                };
+                let changes = params["contentChanges"]
+                    .as_array()
+                    .map_or(&[][..], Vec::as_slice);
+                for change in changes {
+                    let Some(text) = change["text"].as_str() else {
+                        warn!("Ignoring a change of {} without text", uri);
+                        continue;
+                    };
+                    let input = document.lexer().input();
+                    let range = match change.get("range") {
+                        Some(range) => {
+                            let lines = LineIndex::new(input);
+                            let start = offset(&lines, input, &range["start"]);
+                            let end = offset(&lines, input, &range["end"]);
+                            start.zip(end)
+                        }
+                        None => Some((0, input.len())),
+                    };
+                    match range {
+                        Some((start, end)) => {
+                            document.apply(&Edit::new(start, end.max(start), text));
+                        }
+                        None => warn!("Ignoring a change of {} with an invalid range", uri),
+                    }
+                }
+            }
+            ("textDocument/didClose", Some(uri)) => {
+                self.documents.remove(uri);
+                return vec![publish_diagnostics(uri, "", &[])];
+            }
+            _ => return Vec::new(),
+        }
+
+        let uri = uri.expect("documents are opened and changed by URI");
+        let document = &self.documents[uri];
+        let errors = document.diagnostics();
+        vec![publish_diagnostics(uri, document.lexer().input(), &errors)]
+    }
+}
+
+/// The lexical errors of the text between two tokens, which no rule matched.
+#[derive(Debug, Clone)]
+struct Gap {
+    /// The text between the tokens.
+    span: Span,
+    /// One past the last byte examined to check the text, or the input length plus one if the
+    /// end of the input was reached.
+    reach: usize,
+    /// The errors found in the text.
+    errors: Vec<LexError>,
+}
+
+/// An open document, with its tokens and the errors of the text between them kept up to date
+/// as it is edited.
+pub struct Document<'d> {
+    /// The DFA the document is lexed with.
+    dfa: &'d Dfa,
+    /// The tokens of the document.
+    lexer: IncrementalLexer<'d>,
+    /// The errors of every run of text between two tokens, in input order.
+    gaps: Vec<Gap>,
+}
+
+impl<'d> Document<'d> {
+    /// Lexes and checks a document in full.
+    ///
+    /// # Arguments
+    ///
+    /// * `dfa` - The DFA to lex with.
+    /// * `text` - The text of the document.
+    pub fn new(dfa: &'d Dfa, text: String) -> Document<'d> {
+        let lexer = IncrementalLexer::new(dfa, text);
+        let input = lexer.input();
+        let window = Span::new(0, input.len());
+        let gaps = check_gaps(dfa, input, lexer.tokens(), window, &LineIndex::new(input));
+        Document { dfa, lexer, gaps }
+    }
+
+    /// Returns the tokens of the document.
+    pub fn lexer(&self) -> &IncrementalLexer<'d> {
+        &self.lexer
+    }
+
+    /// Applies an edit to the document.
+    ///
+    /// Only the text between the tokens the edit changed is checked again, along with text
+    /// before the edit whose check read into it. The errors of the text after the changed
+    /// tokens are moved by the length difference of the edit.
+    ///
+    /// # Arguments
+    ///
+    /// * `edit` - The change to the text.
+    ///
+    /// # Returns
+    ///
+    /// The tokens the edit changed, as returned by `IncrementalLexer::apply`.
+    pub fn apply(&mut self, edit: &Edit) -> Change {
+        let Span { start, end } = edit.range;
+        let new_end = start + edit.replacement.len();
+        let shift = |offset: usize| offset - end + new_end;
+        let change = self.lexer.apply(edit);
+        let input = self.lexer.input();
+        let tokens = self.lexer.tokens();
+        let lines = LineIndex::new(input);
+
+        // The text between the unchanged tokens before and after the changed ones.
+        let mut window = Span::new(
+            change
+                .new
+                .start
+                .checked_sub(1)
+                .map_or(0, |index| tokens[index].span.end)
+                .min(start),
+            tokens
+                .get(change.new.end)
+                .map_or(input.len(), |token| token.span.start)
+                .max(new_end),
+        );
+        for gap in &self.gaps {
+            if gap.span.start < start && gap.reach > start {
+                window.start = window.start.min(gap.span.start);
+            }
+        }
+        let checked = check_gaps(self.dfa, input, tokens, window, &lines);
+        if let Some(first) = checked.first() {
+            window.start = window.start.min(first.span.start);
+        }
+        if let Some(last) = checked.last() {
+            window.end = window.end.max(last.span.end);
+        }
+
+        let mut gaps = Vec::new();
+        let mut after = Vec::new();
+        for gap in std::mem::take(&mut self.gaps) {
+            if gap.span.end <= window.start && gap.reach <= start {
+                gaps.push(gap);
+            } else if gap.span.start >= end && shift(gap.span.start) >= window.end {
+                let errors = gap.errors.into_iter().map(|err| {
+                    let span = Span::new(shift(err.span.start), shift(err.span.end));
+                    LexError::new(err.message, span, &lines)
+                });
+                after.push(Gap {
+                    span: Span::new(shift(gap.span.start), shift(gap.span.end)),
+                    reach: shift(gap.reach),
+                    errors: errors.collect(),
+                });
+            }
+        }
+        gaps.extend(checked);
+        gaps.extend(after);
+        self.gaps = gaps;
+        change
+    }
+
+    /// Returns the lexical errors of the document, in input order.
+    pub fn diagnostics(&self) -> Vec<LexError> {
+        merge_errors(self.lexer.errors(), &self.gaps)
+    }
+}
+
+/// Finds the lexical errors of an input in full.
+///
+/// Besides the problems the lexer reported, every character no rule matched is an error, as
+/// described at `check_gap`. `Document` finds the same errors, but only checks the text an
+/// edit may have changed.
+///
+/// # Arguments
+///
+/// * `dfa` - The DFA the input was lexed with.
+/// * `input` - The lexed input.
+/// * `tokens` - The tokens lexed from `input`.
+/// * `errors` - The problems the lexer found in `input`.
+///
+/// # Returns
+///
+/// All errors, in input order.
+pub fn lexical_errors(
+    dfa: &Dfa,
+    input: &str,
+    tokens: &[Token],
+    errors: &[LexError],
+) -> Vec<LexError> {
+    let window = Span::new(0, input.len());
+    let gaps = check_gaps(dfa, input, tokens, window, &LineIndex::new(input));
+    merge_errors(errors, &gaps)
+}
+
+/// Merges the problems the lexer reported with the errors of the text between tokens.
+///
+/// # Returns
+///
+/// All errors, in input order.
+fn merge_errors(errors: &[LexError], gaps: &[Gap]) -> Vec<LexError> {
+    let mut found = errors.to_vec();
+    found.extend(gaps.iter().flat_map(|gap| gap.errors.iter().cloned()));
+    found.sort_by_key(|err| err.span.start);
+    found
+}
+
+/// Checks every run of text between two tokens that overlaps a window of the input.
+///
+/// # Arguments
+///
+/// * `dfa` - The DFA the input was lexed with.
+/// * `input` - The lexed input.
+/// * `tokens` - The tokens lexed from `input`.
+/// * `window` - The part of the input to check. Runs of text overlapping it are checked whole.
+/// * `lines` - The line index of `input`.
+///
+/// # Returns
+///
+/// The errors of every run of text, in input order.
+fn check_gaps(
+    dfa: &Dfa,
+    input: &str,
+    tokens: &[Token],
+    window: Span,
+    lines: &LineIndex,
+) -> Vec<Gap> {
+    let first = tokens.partition_point(|token| token.span.end <= window.start);
+    let mut end = first
+        .checked_sub(1)
+        .map_or(0, |index| tokens[index].span.end);
+    let chars: Vec<(usize, char)> = input[end..]
+        .char_indices()
+        .map(|(offset, ch)| (end + offset, ch))
+        .collect();
+    let rest: Vec<char> = chars.iter().map(|&(_, ch)| ch).collect();
+
+    let mut gaps = Vec::new();
+    let mut next = tokens[first..].iter();
+    loop {
+        let token = next.next();
+        let start = token.map_or(input.len(), |token| token.span.start);
+        if start > end {
+            let span = Span::new(end, start);
+            gaps.push(check_gap(dfa, input, &chars, &rest, span, lines));
+        }
+        match token {
+            Some(token) if start < window.end => end = end.max(token.span.end),
+            _ => break,
+        }
+    }
+    gaps
+}
+
+/// Checks a run of text between two tokens.
+///
+/// Whitespace is skipped. A character starting an unfinished lexeme, such as a string literal
+/// that is never closed, is reported as an unterminated token of the rule it would match once
+/// finished. Runs of other unmatched characters are reported together.
+///
+/// # Arguments
+///
+/// * `dfa` - The DFA the input was lexed with.
+/// * `input` - The lexed input.
+/// * `chars` - The characters of `input` from the start of the text on, with their offsets.
+/// * `rest` - The same characters, without their offsets.
+/// * `span` - The text to check.
+/// * `lines` - The line index of `input`.
+fn check_gap(
+    dfa: &Dfa,
+    input: &str,
+    chars: &[(usize, char)],
+    rest: &[char],
+    span: Span,
+    lines: &LineIndex,
+) -> Gap {
+    let mut gap = Gap {
+        span,
+        reach: span.end,
+        errors: Vec::new(),
+    };
+    let first = chars.partition_point(|&(offset, _)| offset < span.start);
+    let mut unexpected: Option<Span> = None;
+    for index in first..first + input[span.start..span.end].chars().count() {
+        let (start, ch) = chars[index];
+        let end = chars
+            .get(index + 1)
+            .map_or(input.len(), |&(offset, _)| offset);
+        if ch.is_whitespace() {
+            // Whitespace is skipped, and ends a run of unexpected characters.
+            if let Some(span) = unexpected.take() {
+                gap.errors.push(unexpected_error(input, span, lines));
+            }
+            continue;
+        }
+        let (longest, read) = dfa.scan(&rest[index..]);
+        let reach = match chars.get(index + read - 1) {
+            Some(&(offset, ch)) => offset + ch.len_utf8(),
+            None => input.len() + 1,
+        };
+        gap.reach = gap.reach.max(reach);
+        let unfinished = dfa.unfinished_rule(&rest[index..]);
+        if unfinished.is_none() && longest.is_none() {
+            // Extend the run of unexpected characters.
+            unexpected = Some(Span::new(unexpected.map_or(start, |span| span.start), end));
+            continue;
+        }
+        if let Some(span) = unexpected.take() {
+            gap.errors.push(unexpected_error(input, span, lines));
+        }
+        if let Some(rule) = unfinished {
+            let message = format!("Unterminated {}", rule);
+            gap.errors
+                .push(LexError::new(message, Span::new(start, end), lines));
+        }
+    }
+    if let Some(span) = unexpected {
+        gap.errors.push(unexpected_error(input, span, lines));
+    }
+    gap
+}
+
+/// Creates the error of a run of characters no rule matched.
+fn unexpected_error(input: &str, span: Span, lines: &LineIndex) -> LexError {
+    let text = &input[span.start..span.end];
+    let what = if text.chars().count() == 1 {
+        "character"
+    } else {
+        "characters"
+    };
+    LexError::new(format!("Unexpected {} {:?}", what, text), span, lines)
+}
+
+/// Encodes the semantic tokens of an input.
+///
+/// Tokens spanning several lines are split into one semantic token per line, and tokens
+/// whose kind has no category are left out.
+///
+/// # Arguments
+///
+/// * `input` - The lexed input.
+/// * `tokens` - The tokens lexed from `input`.
+///
+/// # Returns
+///
+/// The LSP encoding of the semantic tokens: five integers per token, holding the line and
+/// start relative to the previous token, the length, the type and the modifiers.
+pub fn semantic_tokens(input: &str, tokens: &[Token]) -> Vec<usize> {
+    let lines = LineIndex::new(input);
+    let mut data = Vec::new();
+    let (mut previous_line, mut previous_start) = (0, 0);
+    for token in tokens {
+        let Some(category) = Category::of_kind(&token.kind) else {
+            continue;
+        };
+        let modifiers = usize::from(token.kind == DOC_COMMENT_KIND);
+        let mut offset = token.span.start;
+        for piece in token.lexeme.split('\n') {
+            let text = piece.strip_suffix('\r').unwrap_or(piece);
+            if !text.is_empty() {
+                let (line, start) = utf16_position(&lines, input, offset);
+                let delta_start = if line == previous_line {
+                    start - previous_start
+                } else {
+                    start
+                };
+                data.extend([
+                    line - previous_line,
+                    delta_start,
+                    text.encode_utf16().count(),
+                    category.index(),
+                    modifiers,
+                ]);
+                (previous_line, previous_start) = (line, start);
+            }
+            offset += piece.len() + 1;
+        }
+    }
+    data
+}
+
+/// Returns the result of the `initialize` request, announcing what the server supports.
+fn initialize_result() -> Value {
+    let token_types: Vec<&str> = Category::ALL
+        .iter()
+        .map(|&category| token_type(category))
+        .collect();
+    json!({
+        "capabilities": {
+            "textDocumentSync": { "openClose": true, "change": SYNC_INCREMENTAL },
+            "semanticTokensProvider": {
+                "legend": {
+                    "tokenTypes": token_types,
+                    "tokenModifiers": [DOCUMENTATION_MODIFIER],
+                },
+                "full": true,
+            },
+        },
+        "serverInfo": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+    })
+}
+
+/// Returns the LSP semantic token type of a category.
+fn token_type(category: Category) -> &'static str {
+    match category {
+        Category::Identifier => "variable",
+        _ => category.name(),
+    }
+}
+
+/// Creates the `textDocument/publishDiagnostics` notification of a document.
+fn publish_diagnostics(uri: &str, input: &str, errors: &[LexError]) -> Value {
+    let lines = LineIndex::new(input);
+    let range = |span: Span| {
+        let (start_line, start) = utf16_position(&lines, input, span.start);
+        let (end_line, end) = utf16_position(&lines, input, span.end);
+        json!({
+            "start": { "line": start_line, "character": start },
+            "end": { "line": end_line, "character": end },
+        })
+    };
+    let diagnostics: Vec<Value> = errors
+        .iter()
+        .map(|err| {
+            json!({
+                "range": range(err.span),
+                "severity": SEVERITY_ERROR,
+                "source": env!("CARGO_PKG_NAME"),
+                "message": err.message,
+            })
+        })
+        .collect();
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// Creates the response to a request that failed.
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// Converts a byte offset into a 0-based line and a character counted in UTF-16 code units,
+/// as LSP positions are.
+fn utf16_position(lines: &LineIndex, input: &str, offset: usize) -> (usize, usize) {
+    let (line, _) = lines.line_col(offset);
+    let start = lines.line_start(line);
+    (line - 1, input[start..offset].encode_utf16().count())
+}
+
+/// Converts an LSP position into a byte offset.
+///
+/// Positions past the end of their line are moved back to it, and lines past the end of the
+/// input to the end of the input.
+///
+/// # Returns
+///
+/// The byte offset, or `None` if the position is malformed.
+fn offset(lines: &LineIndex, input: &str, position: &Value) -> Option<usize> {
+    let line = usize::try_from(position["line"].as_u64()?).ok()?;
+    let character = usize::try_from(position["character"].as_u64()?).ok()?;
+    if line >= lines.line_count() {
+        return Some(input.len());
+    }
+    let start = lines.line_start(line + 1);
+    let mut units = 0;
+    for (index, ch) in input[start..].char_indices() {
+        if ch == '\n' || units >= character {
+            return Some(start + index);
+        }
+        units += ch.len_utf16();
+    }
+    Some(input.len())
+}
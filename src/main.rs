@@ -1,108 +1,1216 @@
 //! A simple Oat language lexer that demonstrates the use of two lexer implementations
-//! (library-based and hand-made) and compares their tokenization results. The program
-//! reads an input source file, processes it with both lexers, compares the output, and
-//! prints tokens or error messages accordingly.
-
-mod dfa;
-mod lex4oat;
-mod liblex4oat;
-mod nfa;
-mod node;
-
-use crate::liblex4oat::LibLex4Oat;
-use clap::arg;
-use clap::Parser;
+//! (library-based and hand-made). Each job is a subcommand: printing the tokens of one
+//! lexer, cross-checking both lexers on many files in parallel, shrinking an input on which
+//! they disagree, generating sample lexemes per rule, exporting the hand-made automata in DOT
+//! format, validating a lexer specification, serving editors as a language server, and
+//! highlighting Oat source files for terminals or the web.
+
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
-use log::{error, info, warn};
-use std::path::PathBuf;
+use lex4oat::channel::Channel;
+use lex4oat::derivative::dfa_from_spec;
+use lex4oat::dfa::Dfa;
+use lex4oat::diff::{MismatchKind, Side, Stop, TokenDiff};
+use lex4oat::format::{Format, write_tokens};
+use lex4oat::generate::Generator;
+use lex4oat::highlight::{Style, highlight};
+use lex4oat::lazy::LazyDfa;
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::liblex4oat::{LibLex4Oat, LibLexerDef, lexer_def_from_spec};
+use lex4oat::literal::parse_literals;
+use lex4oat::lsp::serve;
+use lex4oat::nfa::{INITIAL, Nfa};
+use lex4oat::reduce::reduce;
+use lex4oat::sources::collect_sources;
+use lex4oat::spec::{check_spec, hand_made_only_feature};
+use lex4oat::token::{LexError, LineIndex, Span, Token};
+use log::{error, info};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
-/// Command line arguments for the Oat language lexer.
+/// Exit status used when an input, output or specification file cannot be accessed.
+const EXIT_IO_ERROR: i32 = 1;
+/// Exit status used when the two lexers produce different tokens for the same text.
 ///
-/// This structure is used to configure the lexer by providing the input file.
-/// The default input file is set to `a.oat`.
+/// Status 2 is left to `clap` for command line usage errors.
+const EXIT_TOKENS_DIFFER: i32 = 3;
+/// Exit status used when a specification file is invalid.
+const EXIT_INVALID_SPEC: i32 = 4;
+/// Exit status used when the candidate lexer is missing tokens the reference lexer produced.
+const EXIT_TOKENS_MISSING: i32 = 5;
+/// Exit status used when the candidate lexer produces tokens the reference lexer did not.
+const EXIT_TOKENS_EXTRA: i32 = 6;
+/// Exit status used when lexing a file reports errors, such as an unterminated comment, an
+/// integer that overflows or an invalid escape.
+const EXIT_LEX_ERRORS: i32 = 7;
+/// Exit status used when the two lexers produce the same tokens, but one of them stopped where
+/// no rule matches.
+const EXIT_LEXER_STOPPED: i32 = 8;
+
+/// Command line arguments for the Oat language lexer.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Sets input Oat source file.
-    #[arg(value_name = "INPUT", default_value = "a.oat")]
-    source_file: PathBuf,
+    /// Sets the lexer specification used by the hand-made lexer.
+    #[arg(long, value_name = "SPEC", default_value = "src/oat.l", global = true)]
+    spec: PathBuf,
+
+    /// Sets the maximum number of states cached by the lazy DFA lexer.
+    #[arg(long, value_name = "N", default_value_t = 4096, global = true)]
+    cache_limit: usize,
+
+    /// The job to run.
+    #[command(subcommand)]
+    command: Command,
 }
 
-/// The main entry point of the lexer application.
+/// The subcommands of the lexer application.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints the tokens of Oat source files using one lexer.
+    Tokens {
+        /// Sets the lexer used to tokenize the input.
+        #[arg(short, long, value_enum, default_value_t = Backend::Hand)]
+        backend: Backend,
+
+        /// Sets the output format of the tokens.
+        #[arg(short, long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+
+        /// Parses the values of literals, decoding the escape sequences of strings, and
+        /// reports integers that overflow and invalid escapes.
+        #[arg(long, alias = "decode-strings")]
+        values: bool,
+
+        /// Sets the tokens printed: all of them, only those a parser sees, or only the
+        /// comments and whitespace between them.
+        #[arg(short, long, value_enum, default_value_t = Channel::All)]
+        channel: Channel,
+
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Lexes Oat source files with two lexers and checks that they agree.
+    Compare {
+        #[command(flatten)]
+        pair: Pair,
+
+        #[command(flatten)]
+        inputs: Inputs,
+    },
+    /// Shrinks an Oat source file on which the lexers disagree to a minimal reproducer.
+    Minimize {
+        #[command(flatten)]
+        pair: Pair,
+
+        /// Sets the Oat source file to shrink.
+        #[arg(value_name = "INPUT")]
+        input: PathBuf,
+
+        /// Sets the output file, defaulting to the input path with a `.min.oat` extension.
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Prints random lexemes accepted by the rules of the specification.
+    Samples {
+        /// Sets the rule to sample, defaulting to every rule.
+        #[arg(short, long, value_name = "RULE")]
+        rule: Option<String>,
+
+        /// Sets the number of lexemes per rule.
+        #[arg(short = 'n', long, value_name = "N", default_value_t = 5)]
+        count: usize,
+
+        /// Sets the seed of the generator.
+        #[arg(long, value_name = "SEED", default_value_t = 0)]
+        seed: u64,
+
+        /// Sets the maximum length of a lexeme, in characters.
+        #[arg(long, value_name = "N", default_value_t = 16)]
+        max_len: usize,
+
+        /// Prints strings just outside the rules instead of accepted lexemes.
+        #[arg(long)]
+        near_misses: bool,
+    },
+    /// Exports the NFA built from the specification in DOT format.
+    Nfa {
+        /// Sets the output file, defaulting to standard output.
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Exports the DFA built from the specification in DOT format.
+    Dfa {
+        /// Sets the construction used to build the DFA.
+        #[arg(short, long, value_enum, default_value_t = Builder::Subset)]
+        builder: Builder,
+
+        /// Sets the output file, defaulting to standard output.
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Checks that two specifications tokenize every input alike.
+    SpecDiff {
+        /// Sets the construction used to build both DFAs.
+        #[arg(short, long, value_enum, default_value_t = Builder::Subset)]
+        builder: Builder,
+
+        /// Sets the original specification.
+        #[arg(value_name = "OLD")]
+        old: PathBuf,
+
+        /// Sets the changed specification.
+        #[arg(value_name = "NEW")]
+        new: PathBuf,
+    },
+    /// Validates a lexer specification against both lexers.
+    CheckSpec {
+        /// Sets the specification to check, defaulting to the one given by `--spec`.
+        #[arg(value_name = "FILE")]
+        file: Option<PathBuf>,
+    },
+    /// Serves editors over the Language Server Protocol on standard input and output.
+    Lsp,
+    /// Renders an Oat source file with its tokens colored by kind.
+    Highlight {
+        /// Sets the lexer used to tokenize the input.
+        #[arg(short, long, value_enum, default_value_t = Backend::Hand)]
+        backend: Backend,
+
+        /// Sets the output: ANSI colors for terminals, or a self-contained HTML page.
+        #[arg(short, long, value_enum, default_value_t = Style::Ansi)]
+        format: Style,
+
+        /// Sets the Oat source file to render.
+        #[arg(value_name = "INPUT")]
+        input: PathBuf,
+
+        /// Sets the output file, defaulting to standard output.
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+}
+
+/// The input files of a lexing subcommand.
+#[derive(clap::Args, Debug)]
+struct Inputs {
+    /// Sets input Oat source files, directories or glob patterns.
+    #[arg(value_name = "INPUT", default_value = "a.oat", num_args = 1..)]
+    source_files: Vec<PathBuf>,
+
+    /// Sets the number of worker threads, defaulting to the available parallelism.
+    #[arg(short, long, value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+}
+
+/// The two lexers compared by a subcommand.
+#[derive(clap::Args, Clone, Copy, Debug)]
+struct Pair {
+    /// Sets the lexer whose tokens are expected.
+    #[arg(long, value_enum, value_name = "BACKEND", default_value_t = Backend::Lib)]
+    reference: Backend,
+
+    /// Sets the lexer checked against the reference.
+    #[arg(long, value_enum, value_name = "BACKEND", default_value_t = Backend::Hand)]
+    candidate: Backend,
+}
+
+/// The available lexer implementations.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    /// The library lexer generated by `lrlex`.
+    Lib,
+    /// The hand-made NFA/DFA lexer.
+    Hand,
+    /// The hand-made NFA, simulated directly without building a DFA.
+    Nfa,
+    /// The hand-made lexer with DFA states built on demand in a bounded cache.
+    Lazy,
+}
+
+impl Backend {
+    /// Returns the name of the lexer used in reports.
+    fn label(self) -> &'static str {
+        match self {
+            Backend::Lib => "library",
+            Backend::Hand => "hand-made",
+            Backend::Nfa => "NFA",
+            Backend::Lazy => "lazy DFA",
+        }
+    }
+}
+
+/// The available DFA constructions.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Builder {
+    /// Subset construction over the Thompson NFA.
+    Subset,
+    /// Brzozowski derivatives of the rule expressions.
+    Derivative,
+}
+
+/// What a lexing subcommand does with every file.
+#[derive(Clone, Copy, Debug)]
+enum Job {
+    /// Lex with a single lexer, optionally parse literal values, and print the tokens of a
+    /// channel.
+    Tokens {
+        /// The lexer used.
+        backend: Backend,
+        /// Whether to parse the values of literals.
+        values: bool,
+        /// The channel of the tokens printed.
+        channel: Channel,
+    },
+    /// Lex with two lexers and check that they agree.
+    Compare(Pair),
+}
+
+impl Job {
+    /// Returns the lexers used by this job.
+    fn backends(self) -> Vec<Backend> {
+        match self {
+            Job::Tokens { backend, .. } => vec![backend],
+            Job::Compare(pair) => vec![pair.reference, pair.candidate],
+        }
+    }
+}
+
+/// The lexers used by a subcommand, shared by all worker threads.
+struct Lexers {
+    /// The library lexer definition built from the specification, only built if the library
+    /// lexer is used.
+    lib: Option<LibLexerDef>,
+    /// The NFA built from the specification.
+    nfa: Arc<Nfa>,
+    /// The compiled DFA lexer, only constructed if the hand-made lexer is used.
+    compiled: Option<CompiledLexer>,
+    /// The lazily constructed DFA, only set up if the lazy lexer is used.
+    lazy: Option<LazyDfa>,
+}
+
+impl Lexers {
+    /// Builds the automata needed by a set of lexers.
+    ///
+    /// The NFA is always built, but the DFA is only constructed for the hand-made lexer, so
+    /// the NFA lexer also works for specifications whose DFA is too large. The library lexer
+    /// is built from the same specification, and the process exits if either lexer rejects
+    /// it.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec` - The contents of the specification file.
+    /// * `backends` - The lexers that will be used.
+    /// * `cache_limit` - The maximum number of states cached by the lazy DFA.
+    ///
+    /// # Returns
+    ///
+    /// The `Lexers` ready for lexing.
+    fn new(spec: &str, backends: &[Backend], cache_limit: usize) -> Lexers {
+        let lib = backends.contains(&Backend::Lib).then(|| {
+            lexer_def_from_spec(spec).unwrap_or_else(|err| {
+                error!(
+                    "The library lexer cannot be built from the specification: {}",
+                    err.red()
+                );
+                process::exit(EXIT_INVALID_SPEC);
+            })
+        });
+        let nfa = Arc::new(build_nfa(spec));
+
+        let compiled = backends.contains(&Backend::Hand).then(|| {
+            info!("Constructing hand-made lexer...");
+            let compiled = CompiledLexer::from_nfa(nfa.clone());
+            info!("Done constructing hand-made lexer");
+            compiled
+        });
+        let lazy = backends
+            .contains(&Backend::Lazy)
+            .then(|| LazyDfa::new(nfa.clone(), cache_limit));
+        Lexers {
+            lib,
+            nfa,
+            compiled,
+            lazy,
+        }
+    }
+
+    /// Lexes an input string with one lexer.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The lexer to use.
+    /// * `input` - The source code to lex.
+    /// * `resume` - Whether the library lexer restarts after a character no rule matches,
+    ///   as the hand-made lexers do, instead of stopping there.
+    ///
+    /// # Returns
+    ///
+    /// The tokens, the problems the hand-made lexers found in the input, and the first offset
+    /// at which the library lexer stopped, if it did. The library lexer logs its errors itself.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the library, hand-made or lazy lexer is used but was not requested in
+    /// `Lexers::new`.
+    fn lex(
+        &self,
+        backend: Backend,
+        input: &str,
+        resume: bool,
+    ) -> (Vec<Token>, Vec<LexError>, Option<usize>) {
+        let (tokens, errors) = match backend {
+            Backend::Lib => {
+                let lexerdef = self
+                    .lib
+                    .as_ref()
+                    .expect("Library lexer must be built before lexing");
+                let mut lib_lexer = LibLex4Oat::new(input.to_owned());
+                if resume {
+                    lib_lexer.lex_resuming_with(lexerdef);
+                } else {
+                    lib_lexer.lex_with(lexerdef);
+                }
+                let stop = lib_lexer.error_offset();
+                return (lib_lexer.tokens().clone(), Vec::new(), stop);
+            }
+            Backend::Hand => self
+                .compiled
+                .as_ref()
+                .expect("DFA must be constructed before lexing")
+                .lex_with_errors(input),
+            Backend::Nfa => self.nfa.lex_with_errors(input),
+            Backend::Lazy => self
+                .lazy
+                .as_ref()
+                .expect("Lazy DFA must be set up before lexing")
+                .lex_with_errors(input),
+        };
+        (tokens, errors, None)
+    }
+
+    /// Lexes an input string with both lexers of a pair and aligns their tokens.
+    ///
+    /// As in the differential tests, the library lexer restarts after every character no rule
+    /// matches, so the tokens are compared up to the end of the input. The first point at
+    /// which either lexer stopped is recorded in the alignment.
+    ///
+    /// # Returns
+    ///
+    /// The reference tokens, the candidate tokens, the problems either lexer found, each
+    /// naming its lexer, and the alignment of the tokens.
+    fn compare(
+        &self,
+        pair: Pair,
+        input: &str,
+    ) -> (Vec<Token>, Vec<Token>, Vec<LexError>, TokenDiff) {
+        let (reference, reference_errors, reference_stop) = self.lex(pair.reference, input, true);
+        let (candidate, candidate_errors, candidate_stop) = self.lex(pair.candidate, input, true);
+        let labelled = |errors: Vec<LexError>, backend: Backend| {
+            errors.into_iter().map(move |mut err| {
+                err.message = format!("{} ({} lexer)", err.message, backend.label());
+                err
+            })
+        };
+        let mut errors: Vec<LexError> = labelled(reference_errors, pair.reference)
+            .chain(labelled(candidate_errors, pair.candidate))
+            .collect();
+        errors.sort_by_key(|err| err.span.start);
+        let stop = [
+            (reference_stop, Side::Reference),
+            (candidate_stop, Side::Candidate),
+        ]
+        .into_iter()
+        .filter_map(|(offset, side)| {
+            Some(Stop {
+                offset: offset?,
+                side,
+            })
+        })
+        .min_by_key(|stop| stop.offset);
+        let diff = TokenDiff::new(&reference, &candidate)
+            .with_stop(stop)
+            .with_labels(pair.reference.label(), pair.candidate.label());
+        (reference, candidate, errors, diff)
+    }
+}
+
+/// The outcome of lexing a single source file.
+struct FileReport {
+    /// The path of the lexed file.
+    path: PathBuf,
+    /// The tokens of the file, or a description of what went wrong.
+    result: Result<Vec<Token>, FileError>,
+    /// The problems found while lexing the file, which do not stop its tokens being printed.
+    errors: Vec<LexError>,
+}
+
+/// The ways lexing a single source file can fail.
+enum FileError {
+    /// The file could not be read.
+    Io(String),
+    /// The two lexers produced different tokens.
+    Mismatch {
+        /// The kind of the first disagreement.
+        kind: MismatchKind,
+        /// The description of the first disagreement.
+        description: String,
+        /// The report of the first divergence.
+        report: String,
+    },
+    /// The two lexers produced the same tokens, but one of them stopped where no rule
+    /// matches and had to be restarted.
+    Stopped {
+        /// The description of the first stop.
+        description: String,
+        /// The report of the first stop.
+        report: String,
+    },
+}
+
+impl FileError {
+    /// Returns the exit status associated with this error.
+    fn exit_code(&self) -> i32 {
+        match self {
+            FileError::Io(_) => EXIT_IO_ERROR,
+            FileError::Mismatch { kind, .. } => match kind {
+                MismatchKind::Changed => EXIT_TOKENS_DIFFER,
+                MismatchKind::Missing => EXIT_TOKENS_MISSING,
+                MismatchKind::Extra => EXIT_TOKENS_EXTRA,
+            },
+            FileError::Stopped { .. } => EXIT_LEXER_STOPPED,
+        }
+    }
+
+    /// Returns the description of this error.
+    fn message(&self) -> &str {
+        match self {
+            FileError::Io(message) => message,
+            FileError::Mismatch { description, .. } | FileError::Stopped { description, .. } => {
+                description
+            }
+        }
+    }
+
+    /// Returns the report of the divergence or stop, if the error has one.
+    fn report(&self) -> Option<&str> {
+        match self {
+            FileError::Io(_) => None,
+            FileError::Mismatch { report, .. } | FileError::Stopped { report, .. } => Some(report),
+        }
+    }
+}
+
+/// Reads a source file and lexes it.
 ///
-/// It initiates logging, parses command line arguments, reads the input file, and
-/// processes it using both the library lexer and the hand-made lexer. The tokens produced
-/// by both lexers are compared; if they match, the tokens are printed with proper color
-/// formatting. Otherwise, an error is logged and the application exits.
-fn main() {
-    // Initialize the logger.
-    env_logger::init();
-    info!("Starting up");
+/// # Arguments
+///
+/// * `path` - The source file to lex.
+/// * `lexers` - The shared lexers.
+/// * `job` - Whether to lex with one lexer, or with two and compare the results.
+///
+/// # Returns
+///
+/// A `FileReport` holding the tokens or the reason the file failed, and the problems found
+/// while lexing it.
+fn lex_file(path: &Path, lexers: &Lexers, job: Job) -> FileReport {
+    let mut errors = Vec::new();
+    let result = std::fs::read_to_string(path)
+        .map_err(|err| FileError::Io(format!("Failed to read input file: {}", err)))
+        .and_then(|input| match job {
+            Job::Tokens {
+                backend,
+                values,
+                channel,
+            } => {
+                let (mut tokens, lex_errors, stop) = lexers.lex(backend, &input, false);
+                errors = lex_errors;
+                if let Some(stop) = stop {
+                    let message = "No rule matches the input, so the library lexer stopped";
+                    let width = input[stop..].chars().next().map_or(0, char::len_utf8);
+                    let span = Span::new(stop, stop + width);
+                    errors.push(LexError::new(
+                        message.to_string(),
+                        span,
+                        &LineIndex::new(&input),
+                    ));
+                }
+                if values {
+                    errors.extend(parse_literals(&input, &mut tokens));
+                    errors.sort_by_key(|err| err.span.start);
+                }
+                Ok(channel.select(&input, tokens))
+            }
+            Job::Compare(pair) => {
+                info!("Lexing {} using both lexers...", path.display());
+                let (reference, candidate, lex_errors, diff) = lexers.compare(pair, &input);
+                errors = lex_errors;
+                let report = diff.render(&input, &reference, &candidate);
+                match (diff.first_kind(), diff.stop()) {
+                    (None, None) => Ok(candidate),
+                    (Some(kind), _) => Err(FileError::Mismatch {
+                        kind,
+                        description: kind.describe(pair.candidate.label()),
+                        report,
+                    }),
+                    (None, Some(stop)) => {
+                        let backend = match stop.side {
+                            Side::Reference => pair.reference,
+                            Side::Candidate => pair.candidate,
+                        };
+                        Err(FileError::Stopped {
+                            description: format!(
+                                "The {} lexer stopped where no rule matches",
+                                backend.label()
+                            ),
+                            report,
+                        })
+                    }
+                }
+            }
+        });
 
-    // Parse command line arguments.
-    info!("Parsing arguments...");
-    let args = Args::parse();
-    info!("Parsed arguments: {:#?}", args);
+    FileReport {
+        path: path.to_path_buf(),
+        result,
+        errors,
+    }
+}
 
-    // Read the source file, logging its name in yellow.
-    info!(
-        "Reading source file {}",
-        args.source_file.display().to_string().yellow()
-    );
-    let input = std::fs::read_to_string(&args.source_file).unwrap_or_else(|err| {
-        // Log error message with colored output and exit the process.
+/// Lexes all source files on a pool of worker threads sharing the same lexers.
+///
+/// Workers pull the next file index from a shared counter, so long files do not hold up
+/// the rest of the queue. The reports are returned in the order of `files`.
+///
+/// # Arguments
+///
+/// * `files` - The source files to lex.
+/// * `lexers` - The shared lexers.
+/// * `job` - Whether to lex with one lexer or compare two.
+/// * `jobs` - The number of worker threads.
+///
+/// # Returns
+///
+/// One `FileReport` per input file.
+fn lex_files(files: &[PathBuf], lexers: &Lexers, job: Job, jobs: usize) -> Vec<FileReport> {
+    let next = AtomicUsize::new(0);
+    let mut reports: Vec<Option<FileReport>> = files.iter().map(|_| None).collect();
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.min(files.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= files.len() {
+                            break;
+                        }
+                        done.push((index, lex_file(&files[index], lexers, job)));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        for worker in workers {
+            for (index, report) in worker.join().expect("Lexer worker panicked") {
+                reports[index] = Some(report);
+            }
+        }
+    });
+
+    reports.into_iter().flatten().collect()
+}
+
+/// Reads the specification file, exiting the process if it cannot be read.
+fn read_spec(spec: &Path) -> String {
+    std::fs::read_to_string(spec).unwrap_or_else(|err| {
         error!(
-            "Failed to read input file {}: {}",
-            args.source_file.display().to_string().yellow(),
+            "Failed to read specification {}: {}",
+            spec.display().to_string().yellow(),
             err.to_string().red()
         );
-        process::exit(1);
+        process::exit(EXIT_IO_ERROR);
+    })
+}
+
+/// Reads the specification of a set of lexers, exiting the process if it cannot be read,
+/// uses features the library lexer does not support while it is one of the lexers, or has
+/// any of the problems `check-spec` reports.
+fn read_lexer_spec(spec: &Path, backends: &[Backend]) -> String {
+    let source = read_spec(spec);
+    if let Some(feature) = hand_made_only_feature(&source)
+        && backends.contains(&Backend::Lib)
+    {
+        error!(
+            "{} uses {}, which the library lexer does not support; {}",
+            spec.display().to_string().yellow(),
+            feature,
+            "choose a hand-made backend instead".red()
+        );
+        process::exit(EXIT_INVALID_SPEC);
+    }
+    let errors = check_spec(&source);
+    if !errors.is_empty() {
+        for err in &errors {
+            error!(
+                "{}:{}: {}",
+                spec.display().to_string().yellow(),
+                err.line,
+                err.message.as_str().red()
+            );
+        }
+        process::exit(EXIT_INVALID_SPEC);
+    }
+    source
+}
+
+/// Builds the NFA of a specification, exiting the process if the hand-made lexer rejects a
+/// rule.
+fn build_nfa(spec: &str) -> Nfa {
+    let mut nfa = Nfa::new();
+    nfa.add_keywords(spec);
+    if let Err(err) = nfa.construct() {
+        error!(
+            "The hand-made lexer cannot be built from the specification: {}",
+            err.red()
+        );
+        process::exit(EXIT_INVALID_SPEC);
+    }
+    nfa
+}
+
+/// Writes rendered output to a file, or to standard output if no file is given.
+///
+/// # Returns
+///
+/// The exit status of the subcommand.
+fn write_output(text: &str, output: Option<&Path>) -> i32 {
+    match output {
+        Some(path) => match std::fs::write(path, text) {
+            Ok(()) => {
+                info!("Wrote {}", path.display().to_string().yellow());
+                0
+            }
+            Err(err) => {
+                error!(
+                    "Failed to write {}: {}",
+                    path.display().to_string().yellow(),
+                    err.to_string().red()
+                );
+                EXIT_IO_ERROR
+            }
+        },
+        None => {
+            print!("{}", text);
+            0
+        }
+    }
+}
+
+/// Runs the `tokens` and `compare` subcommands.
+///
+/// The input files are lexed in parallel. Tokens are printed in the given format when a
+/// single backend is used, while comparisons print one status line per file. The errors found
+/// while lexing are logged per file, and a summary of all files is printed to standard error
+/// at the end.
+///
+/// # Returns
+///
+/// The exit status of the subcommand: the status of the first file that failed, or 0.
+fn run_lex(spec: &Path, cache_limit: usize, inputs: &Inputs, job: Job, format: Format) -> i32 {
+    // Expand directories and patterns into source files.
+    let files = collect_sources(&inputs.source_files).unwrap_or_else(|err| {
+        error!("{}", err.red());
+        process::exit(EXIT_IO_ERROR);
     });
-    info!(
-        "Done reading source file {}",
-        args.source_file.display().to_string().yellow()
-    );
+    if files.is_empty() {
+        error!("{}", "No input files found".red());
+        return EXIT_IO_ERROR;
+    }
 
-    // Library lexer section.
-    info!("Parsing source file using library lexer...");
-    let mut lib_lexer = LibLex4Oat::new(input.to_owned());
-    lib_lexer.lex();
-    let lib_tokens = lib_lexer.tokens();
-    info!("Done parsing library lexer");
-
-    // Handmade lexer section.
-    info!("Parsing source file using hand-baked parser...");
-    let mut hand_lexer = lex4oat::Lex4Oat::new(input.to_owned());
-    hand_lexer.construct_nfa();
-    hand_lexer.construct_dfa();
-    hand_lexer.minimize_dfa();
-    let hand_tokens = hand_lexer.lex();
-    info!("Done parsing hand-made lexer");
-
-    // Compare tokens from both lexer implementations.
-    info!("Checking result...");
-    let mut check = true;
-    if lib_tokens.len() != hand_tokens.len() {
-        // Log error and exit if token count does not match.
-        error!("Length of tokens doesn't match");
-        process::exit(1);
+    // Build the hand-made automata once and share them between all workers.
+    let backends = job.backends();
+    let lexers = Lexers::new(&read_lexer_spec(spec, &backends), &backends, cache_limit);
+
+    let jobs = inputs
+        .jobs
+        .or_else(|| thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    info!("Lexing {} files using {} workers...", files.len(), jobs);
+    let reports = lex_files(&files, &lexers, job, jobs);
+    if let Some(lazy) = &lexers.lazy {
+        info!("Flushed the lazy DFA state cache {} times", lazy.flushes());
+    }
+
+    // Report the results of every file in input order.
+    let mut lexed: Vec<(&Path, &[Token])> = Vec::new();
+    let mut token_count = 0;
+    let mut error_count = 0;
+    let mut status = 0;
+    for report in &reports {
+        for err in &report.errors {
+            error!(
+                "{}: {}",
+                report.path.display().to_string().yellow(),
+                err.to_string().red()
+            );
+        }
+        error_count += report.errors.len();
+        if status == 0 && !report.errors.is_empty() {
+            status = EXIT_LEX_ERRORS;
+        }
+        match &report.result {
+            Ok(tokens) => {
+                if let Job::Compare(_) = job {
+                    println!("{}: {}", report.path.display(), "ok".green());
+                }
+                lexed.push((&report.path, tokens));
+                token_count += tokens.len();
+            }
+            Err(err) => {
+                if let Job::Compare(_) = job {
+                    println!("{}: {}", report.path.display(), "failed".red());
+                }
+                if let Some(report) = err.report() {
+                    print!("{}", report);
+                }
+                error!(
+                    "{}: {}",
+                    report.path.display().to_string().yellow(),
+                    err.message().red()
+                );
+                error_count += 1;
+                if status == 0 {
+                    status = err.exit_code();
+                }
+            }
+        }
+    }
+
+    if let Job::Tokens { .. } = job {
+        let mut out = std::io::stdout().lock();
+        if let Err(err) = write_tokens(&mut out, format, &lexed) {
+            error!("Failed to write tokens: {}", err.to_string().red());
+            return EXIT_IO_ERROR;
+        }
+    }
+
+    let summary = format!(
+        "{} files, {} tokens, {} errors",
+        reports.len(),
+        token_count,
+        error_count
+    );
+    if error_count > 0 {
+        eprintln!("{}", summary.red());
     } else {
-        // Iterate through tokens and compare each pair.
-        for i in 0..hand_tokens.len() {
-            if lib_tokens[i].0 != hand_tokens[i].0 || lib_tokens[i].1 != hand_tokens[i].1 {
-                warn!("Mismatched tokens found: {}", lib_tokens[i].1);
-                check = false;
+        eprintln!("{}", summary.green());
+    }
+    status
+}
+
+/// Runs the `minimize` subcommand.
+///
+/// The input is shrunk for as long as the lexers keep disagreeing in the same way as on the
+/// whole input, i.e. while the first differing region has the same `MismatchKind`. The
+/// reduced input is written to the output file and its divergence report is printed.
+///
+/// # Returns
+///
+/// The exit status of the subcommand.
+fn run_minimize(
+    spec: &Path,
+    cache_limit: usize,
+    pair: Pair,
+    input_path: &Path,
+    output: Option<&Path>,
+) -> i32 {
+    let input = match std::fs::read_to_string(input_path) {
+        Ok(input) => input,
+        Err(err) => {
+            error!(
+                "Failed to read input file {}: {}",
+                input_path.display().to_string().yellow(),
+                err.to_string().red()
+            );
+            return EXIT_IO_ERROR;
+        }
+    };
+
+    let backends = Job::Compare(pair).backends();
+    let lexers = Lexers::new(&read_lexer_spec(spec, &backends), &backends, cache_limit);
+    let mismatch = |candidate: &str| lexers.compare(pair, candidate).3.first_kind();
+    let Some(kind) = mismatch(&input) else {
+        println!(
+            "{}: {}",
+            input_path.display(),
+            "the lexers agree, nothing to minimize".green()
+        );
+        return 0;
+    };
+
+    info!("Minimizing {}...", input_path.display());
+    let reduced = reduce(&input, |candidate| mismatch(candidate) == Some(kind));
+    let output = output.map_or_else(|| input_path.with_extension("min.oat"), Path::to_path_buf);
+    if let Err(err) = std::fs::write(&output, &reduced) {
+        error!(
+            "Failed to write {}: {}",
+            output.display().to_string().yellow(),
+            err.to_string().red()
+        );
+        return EXIT_IO_ERROR;
+    }
+
+    let (reference, candidate, _, diff) = lexers.compare(pair, &reduced);
+    print!("{}", diff.render(&reduced, &reference, &candidate));
+    println!(
+        "{}: reduced {} bytes to {} bytes in {}",
+        input_path.display(),
+        input.len(),
+        reduced.len(),
+        output.display().to_string().yellow()
+    );
+    0
+}
+
+/// Runs the `samples` subcommand.
+///
+/// Prints one `RULE "lexeme"` line per generated string, or a usage error if the requested
+/// rule does not exist.
+///
+/// # Returns
+///
+/// The exit status of the subcommand.
+fn run_samples(
+    spec: &Path,
+    rule: Option<&str>,
+    count: usize,
+    seed: u64,
+    max_len: usize,
+    near_misses: bool,
+) -> i32 {
+    let compiled = CompiledLexer::from_nfa(build_nfa(&read_lexer_spec(spec, &[])));
+    let mut generator = Generator::new(compiled.dfa(), seed, max_len);
+    let rules = match rule {
+        Some(rule) if !generator.rules().iter().any(|name| name == rule) => {
+            Args::command()
+                .error(
+                    ErrorKind::InvalidValue,
+                    format!("no rule named {:?} in {}", rule, spec.display()),
+                )
+                .exit();
+        }
+        Some(rule) => vec![rule.to_string()],
+        None => generator.rules(),
+    };
+
+    for rule in &rules {
+        for _ in 0..count {
+            let generated = if near_misses {
+                generator.near_miss(rule)
+            } else {
+                generator.sample(rule)
+            };
+            match generated {
+                Some(lexeme) => println!("{:<15} {:?}", rule, lexeme),
+                None => {
+                    error!("{}: {}", rule.yellow(), "no string found".red());
+                    break;
+                }
             }
         }
     }
+    0
+}
 
-    // If tokens match, print them with a green success message.
-    if check {
-        info!("{}", "Result matched".green());
-        for (typ, token) in hand_tokens {
-            println!("{:<15} {}", typ, token);
+/// Builds the DFA of a specification file, exiting the process if it cannot be read or is
+/// invalid.
+fn build_dfa(spec: &Path, builder: Builder) -> Dfa {
+    let spec = read_lexer_spec(spec, &[]);
+    match builder {
+        Builder::Subset => {
+            let nfa = build_nfa(&spec);
+            let mut dfa = Dfa::new();
+            dfa.set_nfa(Arc::new(nfa));
+            dfa.construct_dfa();
+            dfa
         }
-        info!("Done, good day!");
+        Builder::Derivative => dfa_from_spec(&spec),
     }
 }
+
+/// Runs the `spec-diff` subcommand.
+///
+/// Prints whether both specifications tokenize alike, or else the shortest input telling
+/// them apart, the rule each accepts it with, and the tokens each produces for it.
+///
+/// # Returns
+///
+/// The exit status of the subcommand.
+fn run_spec_diff(old: &Path, new: &Path, builder: Builder) -> i32 {
+    let old_dfa = build_dfa(old, builder);
+    let new_dfa = build_dfa(new, builder);
+    let Some(distinction) = old_dfa.distinguish(&new_dfa) else {
+        println!(
+            "{} and {}: {}",
+            old.display(),
+            new.display(),
+            "tokenize alike".green()
+        );
+        return 0;
+    };
+
+    let in_initial = distinction.mode == INITIAL;
+    println!(
+        "{} and {}: {} {:?}{}{}",
+        old.display(),
+        new.display(),
+        "differ on".red(),
+        distinction.input,
+        if in_initial {
+            String::new()
+        } else {
+            format!(" in start condition {}", distinction.mode)
+        },
+        if distinction.at_line_start {
+            ""
+        } else {
+            " away from the start of a line"
+        }
+    );
+    for (path, dfa, rule) in [
+        (old, &old_dfa, &distinction.left),
+        (new, &new_dfa, &distinction.right),
+    ] {
+        if !distinction.at_line_start || !in_initial {
+            // Lexing the input on its own would start at the start of a line in `INITIAL`.
+            println!(
+                "  {}: accepted as {}",
+                path.display(),
+                rule.as_deref().unwrap_or("nothing")
+            );
+            continue;
+        }
+        let tokens: Vec<String> = dfa
+            .lex(&distinction.input)
+            .iter()
+            .map(|token| format!("{} {:?}", token.kind, token.lexeme))
+            .collect();
+        println!(
+            "  {}: accepted as {}, lexed as {}",
+            path.display(),
+            rule.as_deref().unwrap_or("nothing"),
+            if tokens.is_empty() {
+                "(no tokens)".to_string()
+            } else {
+                tokens.join(", ")
+            }
+        );
+    }
+    EXIT_TOKENS_DIFFER
+}
+
+/// Runs the `check-spec` subcommand.
+///
+/// # Returns
+///
+/// The exit status of the subcommand.
+fn run_check_spec(file: &Path) -> i32 {
+    let source = read_spec(file);
+    if let Some(feature) = hand_made_only_feature(&source) {
+        println!(
+            "{}: {}",
+            file.display(),
+            format!("uses {}, skipped the library lexer checks", feature).yellow()
+        );
+    }
+    let errors = check_spec(&source);
+    if errors.is_empty() {
+        println!("{}: {}", file.display(), "ok".green());
+        return 0;
+    }
+    for err in &errors {
+        println!(
+            "{}:{}: {}",
+            file.display(),
+            err.line,
+            err.message.as_str().red()
+        );
+    }
+    EXIT_INVALID_SPEC
+}
+
+/// Runs the `lsp` subcommand.
+///
+/// Documents are lexed with the hand-made lexer. Logs go to standard error, since standard
+/// output carries the protocol.
+///
+/// # Returns
+///
+/// The exit status of the subcommand: 0 if the client shut the server down before exiting,
+/// as the protocol asks, or 1 otherwise.
+fn run_lsp(spec: &Path) -> i32 {
+    let compiled = CompiledLexer::from_nfa(build_nfa(&read_lexer_spec(spec, &[])));
+    info!("Serving the Language Server Protocol on standard input and output...");
+    match serve(
+        compiled.dfa(),
+        std::io::stdin().lock(),
+        std::io::stdout().lock(),
+    ) {
+        Ok(true) => 0,
+        Ok(false) => {
+            error!("{}", "Client exited without shutting the server down".red());
+            EXIT_IO_ERROR
+        }
+        Err(err) => {
+            error!("Failed to talk to the client: {}", err.to_string().red());
+            EXIT_IO_ERROR
+        }
+    }
+}
+
+/// Runs the `highlight` subcommand.
+///
+/// Lexing errors are logged, and the text they cover is still rendered, marked as an error.
+/// ANSI colors are written even when the output is not a terminal, unless `NO_COLOR` is set.
+///
+/// # Returns
+///
+/// The exit status of the subcommand.
+fn run_highlight(
+    spec: &Path,
+    cache_limit: usize,
+    backend: Backend,
+    style: Style,
+    input_path: &Path,
+    output: Option<&Path>,
+) -> i32 {
+    let input = match std::fs::read_to_string(input_path) {
+        Ok(input) => input,
+        Err(err) => {
+            error!(
+                "Failed to read input file {}: {}",
+                input_path.display().to_string().yellow(),
+                err.to_string().red()
+            );
+            return EXIT_IO_ERROR;
+        }
+    };
+
+    let lexers = Lexers::new(&read_lexer_spec(spec, &[backend]), &[backend], cache_limit);
+    let (tokens, errors, _) = lexers.lex(backend, &input, false);
+    for err in errors {
+        error!(
+            "{}: {}",
+            input_path.display().to_string().yellow(),
+            err.to_string().red()
+        );
+    }
+
+    if style == Style::Ansi {
+        colored::control::set_override(std::env::var_os("NO_COLOR").is_none());
+    }
+    let title = input_path.display().to_string();
+    write_output(&highlight(style, &title, &input, tokens), output)
+}
+
+/// The main entry point of the lexer application.
+///
+/// It initiates logging, parses command line arguments, runs the chosen subcommand, and
+/// exits with the status the subcommand returned.
+fn main() {
+    // Initialize the logger.
+    env_logger::init();
+    info!("Starting up");
+
+    // Parse command line arguments.
+    info!("Parsing arguments...");
+    let args = Args::parse();
+    info!("Parsed arguments: {:#?}", args);
+
+    let status = match &args.command {
+        Command::Tokens {
+            backend,
+            format,
+            values,
+            channel,
+            inputs,
+        } => run_lex(
+            &args.spec,
+            args.cache_limit,
+            inputs,
+            Job::Tokens {
+                backend: *backend,
+                values: *values,
+                channel: *channel,
+            },
+            *format,
+        ),
+        Command::Compare { pair, inputs } => run_lex(
+            &args.spec,
+            args.cache_limit,
+            inputs,
+            Job::Compare(*pair),
+            Format::Table,
+        ),
+        Command::Minimize {
+            pair,
+            input,
+            output,
+        } => run_minimize(
+            &args.spec,
+            args.cache_limit,
+            *pair,
+            input,
+            output.as_deref(),
+        ),
+        Command::Samples {
+            rule,
+            count,
+            seed,
+            max_len,
+            near_misses,
+        } => run_samples(
+            &args.spec,
+            rule.as_deref(),
+            *count,
+            *seed,
+            *max_len,
+            *near_misses,
+        ),
+        Command::Nfa { output } => {
+            let nfa = build_nfa(&read_lexer_spec(&args.spec, &[]));
+            write_output(&nfa.to_dot(), output.as_deref())
+        }
+        Command::Dfa { builder, output } => {
+            let dot = build_dfa(&args.spec, *builder).to_dot();
+            write_output(&dot, output.as_deref())
+        }
+        Command::SpecDiff { builder, old, new } => run_spec_diff(old, new, *builder),
+        Command::CheckSpec { file } => run_check_spec(file.as_deref().unwrap_or(&args.spec)),
+        Command::Lsp => run_lsp(&args.spec),
+        Command::Highlight {
+            backend,
+            format,
+            input,
+            output,
+        } => run_highlight(
+            &args.spec,
+            args.cache_limit,
+            *backend,
+            *format,
+            input,
+            output.as_deref(),
+        ),
+    };
+
+    info!("Done, good day!");
+    process::exit(status);
+}
@@ -1,16 +1,99 @@
 // Use Node for NFA node representation.
-use crate::node::Node;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use crate::dfa::{Dfa, maximal_munch};
+use crate::node::{Edge, Node};
+use crate::token::{LexError, Token};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
 
 /// Represents a nondeterministic finite automaton (NFA) used for lexical analysis.
 pub struct Nfa {
-    /// A list of keyword definitions where each tuple contains the regex and its token name.
-    keywords: Vec<(String, String)>,
+    /// The rules of the specification, in priority order.
+    rules: Vec<Rule>,
+    /// The nested comments of the specification, which take priority over the rules.
+    nested_comments: Vec<NestedComment>,
+    /// Whether `&` and `~` are set operators, as declared with `%set-operators`, rather than
+    /// the characters themselves.
+    set_operators: bool,
     /// A map of node IDs to their corresponding Node structures.
     nodes: HashMap<usize, Node>,
     /// The ID of the root node of the NFA.
     root_id: usize,
+    /// The modes the NFA can lex in, one per start condition; the first is `INITIAL`.
+    modes: Vec<Mode>,
+    /// The node the trailing context of a rule starts at, keyed by the rule's terminal node.
+    lookaheads: HashMap<usize, usize>,
+    /// The mode change of a rule and the mode it targets, keyed by the rule's terminal node.
+    mode_changes: HashMap<usize, (ModeChange, usize)>,
+    /// The delimiters of every nested comment, keyed by the terminal node of its opener.
+    comments: HashMap<usize, CommentDelimiters>,
+}
+
+/// The automata matching the delimiters of a nested comment.
+pub(crate) struct CommentDelimiters {
+    /// Matches the regex opening the comment.
+    open: Dfa,
+    /// Matches the regex closing the comment.
+    close: Dfa,
+}
+
+impl CommentDelimiters {
+    /// Finds where a nested comment ends.
+    ///
+    /// Openers and closers are counted from a depth of one, after the first opener. Where
+    /// both match, the closer wins.
+    ///
+    /// # Arguments
+    ///
+    /// * `rest` - The input after the first opener.
+    ///
+    /// # Returns
+    ///
+    /// The number of characters up to and including the closer that brings the depth back to
+    /// zero, or `None` if the comment is never closed, and the number of characters read,
+    /// which is one more than `rest.len()` if the search could have read past the end.
+    pub(crate) fn comment_length(&self, rest: &[char]) -> (Option<usize>, usize) {
+        let mut depth = 1;
+        let mut index = 0;
+        let mut read = 0;
+        while index < rest.len() {
+            let (close, close_read) = self.close.scan(&rest[index..]);
+            read = read.max(index + close_read);
+            if let Some(length) = close {
+                depth -= 1;
+                index += length;
+                if depth == 0 {
+                    return (Some(index), read);
+                }
+                continue;
+            }
+            let (open, open_read) = self.open.scan(&rest[index..]);
+            read = read.max(index + open_read);
+            if let Some(length) = open {
+                depth += 1;
+                index += length;
+            } else {
+                index += 1;
+            }
+        }
+        (None, rest.len() + 1)
+    }
+}
+
+/// A start condition of the NFA with the nodes its rules start from.
+struct Mode {
+    /// The start condition the mode was declared as.
+    condition: StartCondition,
+    /// The ID of the node the rules active in the mode start from.
+    entry_id: usize,
+    /// The ID of the node rules anchored with `^` start from, once such a rule was added.
+    line_entry_id: Option<usize>,
+}
+
+impl Default for Nfa {
+    fn default() -> Self {
+        Nfa::new()
+    }
 }
 
 impl Nfa {
@@ -24,6 +107,77 @@ impl Nfa {
         self.root_id
     }
 
+    /// Returns the ID of the node rules anchored with `^` start from, if there are any.
+    ///
+    /// The node has a lambda transition to the root, so every rule can match at the start of
+    /// a line.
+    pub fn get_line_start_id(&self) -> Option<usize> {
+        self.modes[0].line_entry_id
+    }
+
+    /// Returns the names of the modes, in the order they are numbered in.
+    ///
+    /// The first mode is `INITIAL`, followed by the start conditions declared in the
+    /// specification.
+    pub fn get_mode_names(&self) -> Vec<&str> {
+        self.modes
+            .iter()
+            .map(|mode| mode.condition.name.as_str())
+            .collect()
+    }
+
+    /// Returns the number of a mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the start condition.
+    ///
+    /// # Returns
+    ///
+    /// The index of the mode, or `None` if no such start condition was declared.
+    pub fn get_mode_index(&self, name: &str) -> Option<usize> {
+        self.modes
+            .iter()
+            .position(|mode| mode.condition.name == name)
+    }
+
+    /// Returns how matching a rule changes the mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `terminal_id` - The ID of the rule's terminal node.
+    ///
+    /// # Returns
+    ///
+    /// The mode change and the number of the mode it targets, or `None` if the rule keeps
+    /// the mode.
+    pub fn get_mode_change(&self, terminal_id: usize) -> Option<(ModeChange, usize)> {
+        self.mode_changes.get(&terminal_id).copied()
+    }
+
+    /// Returns the delimiters of the nested comment a rule opens, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `terminal_id` - The ID of the rule's terminal node.
+    pub(crate) fn get_comment_delimiters(&self, terminal_id: usize) -> Option<&CommentDelimiters> {
+        self.comments.get(&terminal_id)
+    }
+
+    /// Returns the node the trailing context of a rule starts at.
+    ///
+    /// # Arguments
+    ///
+    /// * `terminal_id` - The ID of the rule's terminal node.
+    ///
+    /// # Returns
+    ///
+    /// The ID of the node reached at the `/` of the rule, or `None` if the rule has no
+    /// trailing context.
+    pub fn get_lookahead(&self, terminal_id: usize) -> Option<usize> {
+        self.lookaheads.get(&terminal_id).copied()
+    }
+
     /// Creates a new NFA with an initial root node.
     pub fn new() -> Nfa {
         let root = Node::new("NFA".to_string(), false);
@@ -31,34 +185,64 @@ impl Nfa {
         let mut nodes = HashMap::new();
         nodes.insert(root_id, root);
         Nfa {
-            keywords: Vec::new(),
+            rules: Vec::new(),
+            nested_comments: Vec::new(),
+            set_operators: false,
             nodes,
             root_id,
+            modes: vec![Mode {
+                condition: StartCondition {
+                    name: INITIAL.to_string(),
+                    exclusive: false,
+                },
+                entry_id: root_id,
+                line_entry_id: None,
+            }],
+            lookaheads: HashMap::new(),
+            mode_changes: HashMap::new(),
+            comments: HashMap::new(),
         }
     }
 
     /// Reads keywords from a file and adds them to the NFA.
     ///
     /// The file is expected to contain lines where each keyword is paired with its token name.
-    /// Lines starting with "%%" or empty lines are ignored.
+    /// Lines starting with "%%" or empty lines are ignored, and lines declaring start
+    /// conditions add a mode.
     ///
     /// # Arguments
     ///
     /// * `file` - A reference to the file path containing the keywords.
-    pub fn add_keywords_from_file(&mut self, file: &PathBuf) {
+    pub fn add_keywords_from_file(&mut self, file: &Path) {
         let input = std::fs::read_to_string(file).expect("Failed to read input file");
-        let lines = input.lines();
+        self.add_keywords(&input);
+    }
 
-        for line in lines {
-            if line.starts_with("%%") || line.is_empty() {
+    /// Adds the keywords of a specification to the NFA.
+    ///
+    /// Uses the same line format as `add_keywords_from_file`.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The contents of a specification file.
+    pub fn add_keywords(&mut self, input: &str) {
+        let spec = parse_spec(input);
+        for condition in spec.start_conditions {
+            if self.get_mode_index(&condition.name).is_some() {
                 continue;
             }
-
-            let parts = line.split_whitespace().collect::<Vec<&str>>();
-            let name = parts.last().unwrap().trim_matches('"').to_string();
-            let keyword = parts[..parts.len() - 1].join(" ");
-            self.keywords.push((keyword, name));
+            let entry = Node::new(condition.name.clone(), false);
+            let entry_id = entry.get_id();
+            self.nodes.insert(entry_id, entry);
+            self.modes.push(Mode {
+                condition,
+                entry_id,
+                line_entry_id: None,
+            });
         }
+        self.rules.extend(spec.rules);
+        self.nested_comments.extend(spec.nested_comments);
+        self.set_operators |= spec.set_operators;
     }
 
     /// Parses a regex set (character class) and connects it to an existing node.
@@ -73,8 +257,14 @@ impl Nfa {
     ///
     /// # Returns
     ///
-    /// The new node ID created for the character set.
-    pub fn parse_regex_set(&mut self, regex: &str, name: &str, start_node_id: usize) -> usize {
+    /// The new node ID created for the character set, or an error if the set ends with an
+    /// unfinished escape.
+    pub fn parse_regex_set(
+        &mut self,
+        regex: &str,
+        name: &str,
+        start_node_id: usize,
+    ) -> Result<usize, String> {
         let mut chars = regex.chars().peekable();
 
         let new_node = Node::new(name.to_owned(), false);
@@ -82,11 +272,9 @@ impl Nfa {
 
         // Check if the set is negated with a '^' at the start.
         let mut is_negated = false;
-        if let Some(&first) = chars.peek() {
-            if first == '^' {
-                is_negated = true;
-                chars.next(); // consume '^'
-            }
+        if chars.peek() == Some(&'^') {
+            is_negated = true;
+            chars.next(); // consume '^'
         }
 
         let mut set_chars: Vec<char> = Vec::new();
@@ -95,7 +283,9 @@ impl Nfa {
         while let Some(c) = chars.next() {
             if c == '\\' {
                 // Process escaped characters.
-                let next = chars.next().unwrap();
+                let Some(next) = chars.next() else {
+                    return Err("Character class ends with an unfinished escape".to_string());
+                };
                 match next {
                     's' => {
                         set_chars.push(' ');
@@ -104,19 +294,17 @@ impl Nfa {
                         set_chars.push('\r');
                     }
                     _ => {
-                        set_chars.push(next);
+                        set_chars.push(unescape(next));
                     }
                 }
-                prev_char = Some(next);
-            } else if c == '-' {
-                // Process range.
-                if let Some(start) = prev_char {
-                    let end_char = chars.next().unwrap();
-                    for ch in ((start as u8 + 1) as char)..=end_char {
-                        set_chars.push(ch);
-                    }
-                    prev_char = Some(end_char);
-                }
+                prev_char = Some(unescape(next));
+            } else if c == '-'
+                && let Some(start) = prev_char
+                && let Some(end_char) = chars.next()
+            {
+                // Process range; as in `lrlex`, a '-' ending the set stands for itself.
+                set_chars.extend((start as u32 + 1..=end_char as u32).filter_map(char::from_u32));
+                prev_char = Some(end_char);
             } else {
                 set_chars.push(c);
                 prev_char = Some(c);
@@ -126,8 +314,9 @@ impl Nfa {
         let mut edge_name = String::new();
 
         if is_negated {
-            // For negated sets, add transitions for all ASCII characters not in set_chars.
-            for code in 32u8..=126u8 {
+            // For negated sets, add transitions for all ASCII characters not in set_chars,
+            // including line breaks and other control characters.
+            for code in 0u8..=127u8 {
                 let ch = code as char;
                 if !set_chars.contains(&ch) {
                     edge_name.push(ch);
@@ -145,7 +334,7 @@ impl Nfa {
             .unwrap()
             .add_outgoing_edge(new_node_id, edge_name);
         self.nodes.insert(new_node_id, new_node);
-        new_node_id
+        Ok(new_node_id)
     }
 
     /// Parses a regex group (parenthesized expression) and connects it to an existing node.
@@ -160,8 +349,13 @@ impl Nfa {
     ///
     /// # Returns
     ///
-    /// The final node ID after parsing the group.
-    pub fn parse_regex_group(&mut self, regex: &str, name: &str, start_node_id: usize) -> usize {
+    /// The final node ID after parsing the group, or an error if its regex is malformed.
+    pub fn parse_regex_group(
+        &mut self,
+        regex: &str,
+        name: &str,
+        start_node_id: usize,
+    ) -> Result<usize, String> {
         // Create a group start node.
         let group_start = Node::new("(".to_string(), false);
         let group_start_id = group_start.get_id();
@@ -174,16 +368,18 @@ impl Nfa {
             .add_outgoing_edge(group_start_id, "<λ>".to_string());
 
         // Recursively parse the inner group expression starting at group_start.
-        let group_end_id = self.parse_regex(regex, name, group_start_id, false);
-
-        // Return the final node of the group.
-        group_end_id
+        self.parse_regex(regex, name, group_start_id, false)
     }
 
     /// Parses a regex pattern and constructs corresponding NFA nodes and transitions.
     ///
     /// This method supports alternation, escaped characters, character classes, groups,
-    /// and repetition operators (*, +, ?).
+    /// repetition operators (*, +, ?), and, once the specification declares them, the set
+    /// operators described at `SetExpr`.
+    ///
+    /// Every atom starts at a fresh node reached by a lambda transition, so that repetition
+    /// operators only loop over the atom itself and never over other transitions leaving the
+    /// node in front of it.
     ///
     /// # Arguments
     ///
@@ -194,223 +390,1165 @@ impl Nfa {
     ///
     /// # Returns
     ///
-    /// The ID of the ending node for the parsed regex.
+    /// The ID of the ending node for the parsed regex, or an error if the regex ends with an
+    /// unfinished escape or leaves a class or group open.
     pub fn parse_regex(
         &mut self,
         regex: &str,
         name: &str,
         start_node_id: usize,
         mark_ending: bool,
-    ) -> usize {
+    ) -> Result<usize, String> {
+        let (branches, _) = split_top_level(regex, &['|']);
+        if self.set_operators
+            && branches
+                .iter()
+                .any(|branch| SetExpr::parse(branch).is_some())
+        {
+            return self.parse_set_branches(&branches, name, start_node_id, mark_ending);
+        }
+
         let mut chars = regex.chars().peekable();
 
         // Save the branch start to be used for all alternates.
-        let branch_start = *(&start_node_id);
+        let branch_start = start_node_id;
         let mut alternatives: Vec<usize> = Vec::new();
 
-        // Our stack starts with the branch start.
-        let mut stack: Vec<usize> = Vec::new();
-        stack.push(branch_start);
-
-        let mut end_node_id = start_node_id;
+        // The end node of the current branch, and the start node of its last atom.
+        let mut current = branch_start;
+        let mut atom_start: Option<usize> = None;
 
         while let Some(c) = chars.next() {
             if c == '|' {
-                // End current alternative branch.
-                let current_alt = *stack.last().unwrap();
-                alternatives.push(current_alt);
-                // Reset the branch by replacing current branch with branch_start.
-                stack.pop();
-                stack.push(branch_start);
+                // End current alternative branch and restart from the branch start.
+                alternatives.push(current);
+                current = branch_start;
+                atom_start = None;
                 continue;
-            } else if c == '\\' {
-                let next = *chars.peek().unwrap();
+            } else if c == '*' || c == '+' || c == '?' {
+                // A repetition operator without an atom is ignored; check-spec reports it.
+                let Some(repeat_start) = atom_start else {
+                    continue;
+                };
+                if c == '?' {
+                    // Skip the optional atom.
+                    self.add_lambda(repeat_start, current);
+                    continue;
+                }
+                // Allow repetition, and leave the loop through a fresh node, so that skipping
+                // an enclosing group never enters the loop halfway.
+                self.add_lambda(current, repeat_start);
+                let end = Node::new(name.to_string(), false);
+                let end_id = end.get_id();
+                self.nodes.insert(end_id, end);
+                self.add_lambda(current, end_id);
+                if c == '*' {
+                    // Skipping the atom ends at the same node as repeating it.
+                    self.add_lambda(repeat_start, end_id);
+                }
+                current = end_id;
+                continue;
+            }
+
+            // Create the start node of the next atom.
+            let new_start = Node::new(name.to_string(), false);
+            let new_start_id = new_start.get_id();
+            self.nodes.insert(new_start_id, new_start);
+            self.add_lambda(current, new_start_id);
+
+            current = if c == '\\' {
+                let Some(next) = chars.next() else {
+                    return Err("Regex ends with an unfinished escape".to_string());
+                };
+                let new_node = Node::new(unescape(next).to_string(), false);
+                let new_node_id = new_node.get_id();
+                self.nodes.insert(new_node_id, new_node);
+                let start_node = self.nodes.get_mut(&new_start_id).unwrap();
                 match next {
                     's' => {
-                        let new_node = Node::new(name.to_string(), false);
-                        let new_node_id = new_node.get_id();
-                        self.nodes.insert(new_node_id, new_node);
                         for ws in [" ", "\t", "\n", "\r"] {
-                            self.nodes
-                                .get_mut(stack.last().unwrap())
-                                .unwrap()
-                                .add_outgoing_edge(new_node_id, ws.to_string());
-                        }
-                        stack.push(new_node_id);
-                        chars.next();
-                    }
-                    _ => {
-                        chars.next();
-                        let new_node = Node::new(next.to_string(), false);
-                        let new_node_id = new_node.get_id();
-                        self.nodes.insert(new_node_id, new_node);
-                        self.nodes
-                            .get_mut(stack.last().unwrap())
-                            .unwrap()
-                            .add_outgoing_edge(new_node_id, next.to_string());
-                        stack.push(new_node_id);
-                        if chars.peek().is_none() && mark_ending {
-                            self.nodes
-                                .get_mut(stack.last().unwrap())
-                                .unwrap()
-                                .set_terminal(true);
-                            self.nodes
-                                .get_mut(stack.last().unwrap())
-                                .unwrap()
-                                .set_name(name.to_string());
-                            end_node_id = new_node_id;
+                            start_node.add_outgoing_edge(new_node_id, ws.to_string());
                         }
                     }
+                    _ => start_node.add_outgoing_edge(new_node_id, unescape(next).to_string()),
                 }
+                new_node_id
+            } else if c == '.' {
+                // As in `lrlex`, '.' matches any character, line breaks included.
+                self.parse_regex_set("^", name, new_start_id)?
             } else if c == '[' {
                 let mut char_set = String::new();
-                while let Some(c) = chars.next() {
+                let mut closed = false;
+                for c in chars.by_ref() {
                     if c == ']' {
+                        closed = true;
                         break;
                     }
                     char_set.push(c);
                 }
-                let current_node_id = *stack.last().unwrap();
-                let result_node_id = self.parse_regex_set(char_set.as_str(), name, current_node_id);
-                stack.push(result_node_id);
-                if chars.peek().is_none() && mark_ending {
-                    self.nodes
-                        .get_mut(&result_node_id)
-                        .unwrap()
-                        .set_terminal(true);
+                if !closed {
+                    return Err("Character class is missing a closing ']'".to_string());
                 }
+                self.parse_regex_set(char_set.as_str(), name, new_start_id)?
             } else if c == '(' {
                 let mut group_expr = String::new();
-                while let Some(c) = chars.next() {
+                let mut closed = false;
+                for c in chars.by_ref() {
                     if c == ')' {
+                        closed = true;
                         break;
                     }
                     group_expr.push(c);
                 }
-                let current_node_id = *stack.last().unwrap();
-                let result_node_id =
-                    self.parse_regex_group(group_expr.as_str(), name, current_node_id);
-                stack.push(result_node_id);
-                if chars.peek().is_none() && mark_ending {
-                    self.nodes
-                        .get_mut(&result_node_id)
-                        .unwrap()
-                        .set_terminal(true);
+                if !closed {
+                    return Err("Group is missing a closing ')'".to_string());
                 }
-            } else if c == '*' {
-                let repeat_node_id = stack.pop().unwrap();
-                let prev_node_id = *stack.last().unwrap();
-                let merge_node = Node::new(name.to_owned(), false);
-                let merge_node_id = merge_node.get_id();
-                self.nodes.insert(merge_node_id, merge_node);
-                stack.push(merge_node_id);
-                // Skip the repeated pattern.
-                self.nodes
-                    .get_mut(&prev_node_id)
-                    .unwrap()
-                    .add_outgoing_edge(merge_node_id, "<λ>".to_string());
-                // Finalize one occurrence.
-                self.nodes
-                    .get_mut(&repeat_node_id)
-                    .unwrap()
-                    .add_outgoing_edge(merge_node_id, "<λ>".to_string());
-                // Allow repetition.
-                self.nodes
-                    .get_mut(&merge_node_id)
-                    .unwrap()
-                    .add_outgoing_edge(prev_node_id, "<λ>".to_string());
-                if chars.peek().is_none() && mark_ending {
-                    self.nodes
-                        .get_mut(&merge_node_id)
-                        .unwrap()
-                        .set_terminal(true);
-                }
-            } else if c == '+' {
-                let repeat_node_id = stack.pop().unwrap();
-                let prev_node_id = *stack.last().unwrap();
-                stack.push(repeat_node_id);
-                self.nodes
-                    .get_mut(&repeat_node_id)
-                    .unwrap()
-                    .add_outgoing_edge(prev_node_id, "<λ>".to_string());
-                if chars.peek().is_none() && mark_ending {
-                    self.nodes
-                        .get_mut(&repeat_node_id)
-                        .unwrap()
-                        .set_terminal(true);
-                }
-            } else if c == '?' {
-                let optional_id = stack.pop().unwrap();
-                let prev_id = stack.last().unwrap();
-                let merge_node = Node::new(c.to_string(), false);
-                let merge_node_id = merge_node.get_id();
-                self.nodes
-                    .get_mut(prev_id)
-                    .unwrap()
-                    .add_outgoing_edge(merge_node_id, "<λ>".to_string());
-                self.nodes
-                    .get_mut(&optional_id)
-                    .unwrap()
-                    .add_outgoing_edge(merge_node_id, "<λ>".to_string());
-                self.nodes.insert(merge_node_id, merge_node);
-                stack.push(merge_node_id);
+                self.parse_regex_group(group_expr.as_str(), name, new_start_id)?
             } else {
                 let new_node = Node::new(c.to_string(), false);
                 let new_node_id = new_node.get_id();
                 self.nodes.insert(new_node_id, new_node);
                 self.nodes
-                    .get_mut(stack.last().unwrap())
+                    .get_mut(&new_start_id)
                     .unwrap()
                     .add_outgoing_edge(new_node_id, c.to_string());
-                stack.push(new_node_id);
-                if chars.peek().is_none() && mark_ending {
-                    self.nodes.get_mut(&new_node_id).unwrap().set_terminal(true);
-                    self.nodes
-                        .get_mut(&new_node_id)
-                        .unwrap()
-                        .set_name(name.to_string());
-                    end_node_id = new_node_id;
-                }
-            }
+                new_node_id
+            };
+            atom_start = Some(new_start_id);
         }
 
+        let mut end_node_id = current;
+
         // If at least one alternate was detected, merge all branches.
         if !alternatives.is_empty() {
             // Add the current branch endpoint to alternatives.
-            alternatives.push(*stack.last().unwrap());
+            alternatives.push(current);
             // Create a merge node.
             let merge_node = Node::new(name.to_string(), false);
             let merge_node_id = merge_node.get_id();
             self.nodes.insert(merge_node_id, merge_node);
             // Connect every alternative branch to the merge node.
             for alt_end in alternatives {
-                self.nodes
-                    .get_mut(&alt_end)
-                    .unwrap()
-                    .add_outgoing_edge(merge_node_id, "<λ>".to_string());
+                self.add_lambda(alt_end, merge_node_id);
             }
             end_node_id = merge_node_id;
         }
 
+        if mark_ending {
+            let end_node = self.nodes.get_mut(&end_node_id).unwrap();
+            end_node.set_terminal(true);
+            end_node.set_name(name.to_string());
+        }
+
+        Ok(end_node_id)
+    }
+
+    /// Parses the branches of a regex using set operators and merges them.
+    ///
+    /// Branches with set operators are determinized on their own, combined with the product
+    /// construction, and spliced in as a sub-automaton. The other branches are parsed as usual.
+    ///
+    /// # Arguments
+    ///
+    /// * `branches` - The top-level alternatives of the regex.
+    /// * `name` - The token name associated with the pattern.
+    /// * `start_node_id` - The starting node ID for the regex.
+    /// * `mark_ending` - A boolean indicating whether the ending node should be marked as terminal.
+    ///
+    /// # Returns
+    ///
+    /// The ID of the node all branches merge into, or an error if a branch is malformed.
+    fn parse_set_branches(
+        &mut self,
+        branches: &[String],
+        name: &str,
+        start_node_id: usize,
+        mark_ending: bool,
+    ) -> Result<usize, String> {
+        let merge_node = Node::new(name.to_string(), false);
+        let merge_node_id = merge_node.get_id();
+        self.nodes.insert(merge_node_id, merge_node);
+
+        for branch in branches {
+            let branch_end = match SetExpr::parse(branch) {
+                Some(expr) => self.splice_dfa(&expr.to_dfa(name)?, name, start_node_id),
+                None => self.parse_regex(branch, name, start_node_id, false)?,
+            };
+            self.add_lambda(branch_end, merge_node_id);
+        }
+
+        if mark_ending {
+            let end_node = self.nodes.get_mut(&merge_node_id).unwrap();
+            end_node.set_terminal(true);
+            end_node.set_name(name.to_string());
+        }
+        Ok(merge_node_id)
+    }
+
+    /// Copies the reachable states of a DFA into the NFA.
+    ///
+    /// The copy is entered by a lambda transition from the start node, and every accepting
+    /// state leaves it by a lambda transition to a new end node. The copied states are not
+    /// terminal themselves.
+    ///
+    /// # Arguments
+    ///
+    /// * `dfa` - The DFA to copy.
+    /// * `name` - The token name associated with the copied states.
+    /// * `start_node_id` - The node the copy is entered from.
+    ///
+    /// # Returns
+    ///
+    /// The ID of the end node of the copy.
+    fn splice_dfa(&mut self, dfa: &Dfa, name: &str, start_node_id: usize) -> usize {
+        let end_node = Node::new(name.to_string(), false);
+        let end_node_id = end_node.get_id();
+        self.nodes.insert(end_node_id, end_node);
+
+        // Create a copy of every reachable state first, so that edges can point at them.
+        let mut copies: HashMap<usize, usize> = HashMap::new();
+        let mut queue = VecDeque::from([dfa.get_root_id()]);
+        while let Some(id) = queue.pop_front() {
+            if copies.contains_key(&id) {
+                continue;
+            }
+            let copy = Node::new(name.to_string(), false);
+            copies.insert(id, copy.get_id());
+            self.nodes.insert(copy.get_id(), copy);
+            queue.extend(
+                dfa.get_nodes()[&id]
+                    .get_outgoing_edges()
+                    .iter()
+                    .map(Edge::get_to),
+            );
+        }
+
+        for (id, &copy_id) in &copies {
+            let node = &dfa.get_nodes()[id];
+            let copy = self.nodes.get_mut(&copy_id).unwrap();
+            for edge in node.get_outgoing_edges() {
+                copy.add_outgoing_edge(copies[&edge.get_to()], edge.get_sym().to_string());
+            }
+            if node.is_terminal() {
+                self.add_lambda(copy_id, end_node_id);
+            }
+        }
+
+        self.add_lambda(start_node_id, copies[&dfa.get_root_id()]);
         end_node_id
     }
 
-    /// Constructs the NFA by parsing all keywords.
+    /// Adds a lambda (epsilon) transition between two nodes.
+    ///
+    /// # Arguments
     ///
-    /// Each keyword is processed into an NFA fragment and then linked together,
-    /// with the final NFA being output in DOT format.
-    pub fn construct(&mut self) {
-        let keywords = self.keywords.clone();
+    /// * `from` - The ID of the node the transition leaves.
+    /// * `to` - The ID of the node the transition enters.
+    fn add_lambda(&mut self, from: usize, to: usize) {
+        self.nodes
+            .get_mut(&from)
+            .unwrap()
+            .add_outgoing_edge(to, "<λ>".to_string());
+    }
+
+    /// Parses a rule, with its anchors, trailing context and mode change.
+    ///
+    /// A rule starts at the entry node of every mode it is active in, or at the line start
+    /// node of the mode if it is anchored with `^`. If the rule has a trailing context, its
+    /// regex is followed by the context, ending in a newline for `$`, and the node between the
+    /// two is recorded as the rule's lookahead.
+    ///
+    /// # Arguments
+    ///
+    /// * `rule` - The rule to parse.
+    ///
+    /// # Returns
+    ///
+    /// The ID of the terminal node of the rule, or `None` if it is active in no mode, or an
+    /// error if its regex is malformed.
+    fn parse_rule(&mut self, rule: &Rule) -> Result<Option<usize>, String> {
+        let modes: Vec<usize> = if rule.start_conditions.is_empty() {
+            (0..self.modes.len())
+                .filter(|&mode| !self.modes[mode].condition.exclusive)
+                .collect()
+        } else {
+            rule.start_conditions
+                .iter()
+                .filter_map(|name| self.get_mode_index(name))
+                .collect()
+        };
+        let regex = RuleRegex::parse(&rule.regex);
+        let entries: Vec<usize> = modes
+            .into_iter()
+            .map(|mode| {
+                if regex.line_start {
+                    self.line_start(mode)
+                } else {
+                    self.modes[mode].entry_id
+                }
+            })
+            .collect();
+        let start_node_id = match entries[..] {
+            [] => return Ok(None),
+            [entry] => entry,
+            _ => {
+                // A rule active in several modes is entered from each of them.
+                let start = Node::new(rule.name.clone(), false);
+                let start_id = start.get_id();
+                self.nodes.insert(start_id, start);
+                for entry in entries {
+                    self.add_lambda(entry, start_id);
+                }
+                start_id
+            }
+        };
 
-        for (keyword, name) in &keywords {
-            if name == ";" {
+        let end_node_id = self
+            .parse_rule_regex(&regex, &rule.name, start_node_id)
+            .map_err(|err| format!("{} in rule {} {}", err, rule.regex, rule.name))?;
+        if let Some((change, target)) = &rule.target
+            && let Some(target) = self.get_mode_index(target)
+        {
+            self.mode_changes.insert(end_node_id, (*change, target));
+        }
+        Ok(Some(end_node_id))
+    }
+
+    /// Parses the regex of a rule, with its trailing context.
+    ///
+    /// # Arguments
+    ///
+    /// * `rule` - The regex of the rule, split around its anchors and trailing context.
+    /// * `name` - The token name of the rule.
+    /// * `start_node_id` - The ID of the node the rule starts from.
+    ///
+    /// # Returns
+    ///
+    /// The ID of the terminal node of the rule, or an error if its regex is malformed.
+    fn parse_rule_regex(
+        &mut self,
+        rule: &RuleRegex,
+        name: &str,
+        start_node_id: usize,
+    ) -> Result<usize, String> {
+        if !rule.has_trailing_context() {
+            return self.parse_regex(&rule.token, name, start_node_id, true);
+        }
+
+        let lookahead = self.parse_regex(&rule.token, name, start_node_id, false)?;
+        let context = rule.context.as_deref().unwrap_or("");
+        let mut end_node_id = self.parse_regex(context, name, lookahead, false)?;
+        if rule.line_end {
+            let newline = Node::new("\n".to_string(), false);
+            let newline_id = newline.get_id();
+            self.nodes.insert(newline_id, newline);
+            self.nodes
+                .get_mut(&end_node_id)
+                .unwrap()
+                .add_outgoing_edge(newline_id, "\n".to_string());
+            end_node_id = newline_id;
+        }
+
+        let end_node = self.nodes.get_mut(&end_node_id).unwrap();
+        end_node.set_terminal(true);
+        end_node.set_name(name.to_string());
+        self.lookaheads.insert(end_node_id, lookahead);
+        Ok(end_node_id)
+    }
+
+    /// Returns the line start node of a mode, creating it on first use.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The number of the mode.
+    fn line_start(&mut self, mode: usize) -> usize {
+        if let Some(line_entry_id) = self.modes[mode].line_entry_id {
+            return line_entry_id;
+        }
+        let line_start = Node::new("^".to_string(), false);
+        let line_start_id = line_start.get_id();
+        self.nodes.insert(line_start_id, line_start);
+        self.add_lambda(line_start_id, self.modes[mode].entry_id);
+        self.modes[mode].line_entry_id = Some(line_start_id);
+        line_start_id
+    }
+
+    /// Constructs the NFA by parsing all keywords.
+    ///
+    /// Each keyword is processed into an NFA fragment and then linked together. Skipped `;`
+    /// rules are left out unless they change the mode. The opener of every nested comment
+    /// comes first, as a rule active wherever rules without a start condition prefix are.
+    ///
+    /// # Returns
+    ///
+    /// An error naming the first rule whose regex is malformed, as `check_spec` reports it.
+    pub fn construct(&mut self) -> Result<(), String> {
+        let nested_comments = self.nested_comments.clone();
+        let set_operators = self.set_operators;
+        for comment in &nested_comments {
+            let Some(terminal_id) = self.parse_rule(&comment.opener_rule())? else {
                 continue;
+            };
+            let delimiter = |regex: &str| {
+                regex_to_dfa(regex, &comment.name, set_operators)
+                    .map_err(|err| format!("{} in nested comment delimiter {}", err, regex))
+            };
+            let delimiters = CommentDelimiters {
+                open: delimiter(&comment.open)?,
+                close: delimiter(&comment.close)?,
+            };
+            self.comments.insert(terminal_id, delimiters);
+        }
+
+        let rules = self.rules.clone();
+
+        for rule in rules.iter().filter(|rule| rule.is_lexed()) {
+            self.parse_rule(rule)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the states lexing starts in.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - The number of the mode the lexer is in.
+    /// * `at_line_start` - Whether the next token starts at the start of a line, where rules
+    ///   anchored with `^` can match too.
+    ///
+    /// # Returns
+    ///
+    /// The epsilon closure of the entry node of the mode, or of its line start node.
+    pub fn start_set(&self, mode: usize, at_line_start: bool) -> BTreeSet<usize> {
+        let mode = &self.modes[mode];
+        let start = match mode.line_entry_id {
+            Some(line_entry_id) if at_line_start => line_entry_id,
+            _ => mode.entry_id,
+        };
+        self.epsilon_closure(&BTreeSet::from([start]))
+    }
+
+    /// Computes how much of a match of a rule belongs to its token.
+    ///
+    /// A rule with trailing context matches its token followed by the context, but only the
+    /// token is kept. The token is the longest prefix of the match that the regex before the
+    /// `/` matches and whose remainder the context matches.
+    ///
+    /// # Arguments
+    ///
+    /// * `terminal_id` - The ID of the terminal node of the matched rule.
+    /// * `matched` - The characters the rule matched.
+    /// * `mode` - The number of the mode the rule matched in.
+    /// * `at_line_start` - Whether the match starts at the start of a line.
+    ///
+    /// # Returns
+    ///
+    /// The number of characters of the token, all of `matched` if the rule has no trailing
+    /// context.
+    pub fn token_length(
+        &self,
+        terminal_id: usize,
+        matched: &[char],
+        mode: usize,
+        at_line_start: bool,
+    ) -> usize {
+        let Some(lookahead) = self.get_lookahead(terminal_id) else {
+            return matched.len();
+        };
+
+        // Every length after which the lookahead node is reached.
+        let mut lengths = Vec::new();
+        let mut state_set = self.start_set(mode, at_line_start);
+        for (length, &ch) in matched.iter().enumerate() {
+            if state_set.contains(&lookahead) {
+                lengths.push(length);
+            }
+            state_set = self.epsilon_closure(&self.move_on(&state_set, ch));
+        }
+        if state_set.contains(&lookahead) {
+            lengths.push(matched.len());
+        }
+
+        lengths
+            .into_iter()
+            .rev()
+            .find(|&length| {
+                let mut state_set = self.epsilon_closure(&BTreeSet::from([lookahead]));
+                for &ch in &matched[length..] {
+                    state_set = self.epsilon_closure(&self.move_on(&state_set, ch));
+                }
+                state_set.contains(&terminal_id)
+            })
+            .unwrap_or(0)
+    }
+
+    /// Computes the epsilon closure of a given set of NFA state IDs.
+    ///
+    /// # Arguments
+    ///
+    /// * `state_set` - A set of NFA state IDs.
+    ///
+    /// # Returns
+    ///
+    /// A set containing all state IDs reachable from `state_set` using epsilon transitions.
+    pub fn epsilon_closure(&self, state_set: &BTreeSet<usize>) -> BTreeSet<usize> {
+        let mut closure = state_set.clone();
+        let mut stack: Vec<usize> = state_set.iter().cloned().collect();
+
+        while let Some(state_id) = stack.pop() {
+            if let Some(node) = self.nodes.get(&state_id) {
+                for edge in node.get_outgoing_edges().iter() {
+                    let to = edge.get_to();
+                    if edge.get_sym() == "<λ>" && !closure.contains(&to) {
+                        closure.insert(to);
+                        stack.push(to);
+                    }
+                }
+            }
+        }
+
+        closure
+    }
+
+    /// Computes the set of NFA states reachable from a given state set by consuming a character.
+    ///
+    /// # Arguments
+    ///
+    /// * `state_set` - A set of NFA state IDs.
+    /// * `ch` - The transition character.
+    ///
+    /// # Returns
+    ///
+    /// A set containing all state IDs reached over transitions labeled with `ch`.
+    pub fn move_on(&self, state_set: &BTreeSet<usize>, ch: char) -> BTreeSet<usize> {
+        let mut result = BTreeSet::new();
+        for state_id in state_set {
+            if let Some(node) = self.nodes.get(state_id) {
+                for edge in node.get_outgoing_edges().iter() {
+                    // Check for transition on the provided character, skipping lambda
+                    // transitions whose label would otherwise match '<' and '>'.
+                    let sym = edge.get_sym();
+                    if sym != "<λ>" && sym.contains(ch) {
+                        result.insert(edge.get_to());
+                    }
+                }
             }
+        }
+        result
+    }
+
+    /// Returns the rule accepted by a set of NFA states.
+    ///
+    /// When several rules accept, the one defined first in the specification wins, which is
+    /// the terminal node with the lowest ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `state_set` - A set of NFA state IDs.
+    ///
+    /// # Returns
+    ///
+    /// The name of the accepted rule, or `None` if no state in the set is terminal.
+    pub fn accepting_rule(&self, state_set: &BTreeSet<usize>) -> Option<&str> {
+        self.accepting_node(state_set)
+            .map(|id| self.nodes[&id].get_name())
+    }
+
+    /// Returns the terminal node of the rule accepted by a set of NFA states.
+    ///
+    /// # Arguments
+    ///
+    /// * `state_set` - A set of NFA state IDs.
+    ///
+    /// # Returns
+    ///
+    /// The ID of the terminal node with the lowest ID, or `None` if no state in the set is
+    /// terminal.
+    pub fn accepting_node(&self, state_set: &BTreeSet<usize>) -> Option<usize> {
+        state_set
+            .iter()
+            .copied()
+            .find(|id| self.nodes.get(id).is_some_and(Node::is_terminal))
+    }
+
+    /// Lexes an input string by simulating the NFA directly.
+    ///
+    /// Instead of building a DFA, the set of active NFA states is stepped over the input and
+    /// closed under epsilon transitions after every character. Tokens are cut with maximal
+    /// munch exactly like `Dfa::lex`, so both must produce the same tokens; a difference points
+    /// at the subset construction rather than at the regex parser.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input string to be lexed.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tokens with their positions in `input`.
+    pub fn lex(&self, input: &str) -> Vec<Token> {
+        self.lex_with_errors(input).0
+    }
+
+    /// Lexes an input string by simulating the NFA directly, reporting problems in the input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The input string to be lexed.
+    ///
+    /// # Returns
+    ///
+    /// The tokens with their positions in `input`, and the problems found.
+    pub fn lex_with_errors(&self, input: &str) -> (Vec<Token>, Vec<LexError>) {
+        let starts: Vec<[BTreeSet<usize>; 2]> = (0..self.modes.len())
+            .map(|mode| [self.start_set(mode, false), self.start_set(mode, true)])
+            .collect();
+        maximal_munch(
+            input,
+            self,
+            |mode, at_line_start| starts[mode][usize::from(at_line_start)].clone(),
+            |state_set, ch| {
+                let next = self.epsilon_closure(&self.move_on(state_set, ch));
+                (!next.is_empty()).then_some(next)
+            },
+            |state_set| {
+                self.accepting_node(state_set)
+                    .map(|id| (self.nodes[&id].get_name(), Some(id)))
+            },
+        )
+    }
+
+    /// Generates a DOT format representation of the NFA.
+    ///
+    /// # Returns
+    ///
+    /// A `String` containing the DOT representation of the NFA, drawn from the start nodes
+    /// of every mode.
+    pub fn to_dot(&self) -> String {
+        let starts: Vec<usize> = self
+            .modes
+            .iter()
+            .map(|mode| mode.line_entry_id.unwrap_or(mode.entry_id))
+            .collect();
+        Node::to_dot_from(&starts, &self.nodes)
+    }
+}
 
-            let _ = self.parse_regex(keyword, name, self.root_id, true);
+/// The name of the start condition lexing starts in.
+pub const INITIAL: &str = "INITIAL";
+
+/// A start condition declared in a specification, a mode the lexer can switch to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartCondition {
+    /// The name of the start condition.
+    pub name: String,
+    /// Whether the start condition was declared with `%x` rather than `%s`, so that rules
+    /// without a start condition prefix are inactive in it.
+    pub exclusive: bool,
+}
+
+/// How matching a rule changes the stack of modes, as in `lrlex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeChange {
+    /// `<S>`: replaces the whole stack with the mode, like `BEGIN(S)` in flex.
+    Begin,
+    /// `<+S>`: pushes the mode onto the stack.
+    Push,
+    /// `<-S>`: pops the top of the stack, falling back to `INITIAL` if it empties.
+    Pop,
+}
+
+impl ModeChange {
+    /// Applies the mode change to a stack of modes.
+    ///
+    /// Repeated pushes of the mode already on top are counted rather than stacked, so each
+    /// of them needs its own pop.
+    ///
+    /// # Arguments
+    ///
+    /// * `stack` - The modes with their repeat counts, the current one last.
+    /// * `target` - The number of the mode the rule targets.
+    pub fn apply(self, stack: &mut Vec<(usize, usize)>, target: usize) {
+        match self {
+            ModeChange::Begin => {
+                stack.clear();
+                stack.push((target, 1));
+            }
+            ModeChange::Push => match stack.last_mut() {
+                Some((mode, count)) if *mode == target => *count += 1,
+                _ => stack.push((target, 1)),
+            },
+            ModeChange::Pop => match stack.last_mut() {
+                Some((_, count)) if *count > 1 => *count -= 1,
+                _ => {
+                    stack.pop();
+                    if stack.is_empty() {
+                        stack.push((0, 1));
+                    }
+                }
+            },
         }
+    }
+}
+
+/// A rule of a specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// The regex of the rule, without its start condition prefix.
+    pub regex: String,
+    /// The token name of the rule, unquoted, or `;` for a skipped rule.
+    pub name: String,
+    /// The start conditions of the `<A,B>` prefix. Without a prefix, the rule is active in
+    /// `INITIAL` and every start condition declared with `%s`.
+    pub start_conditions: Vec<String>,
+    /// The mode change written before the token name, as in `<+S>"NAME"`, and the start
+    /// condition it targets.
+    pub target: Option<(ModeChange, String)>,
+}
 
-        let dot_string = self.nodes.get(&self.root_id).unwrap().to_dot(&self.nodes);
-        std::fs::write("nfa.dot", dot_string).expect("Failed to write NFA to file");
+impl Rule {
+    /// Parses a rule line of the form `<A,B>regex <S>"NAME"`.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - A rule line; the last whitespace-separated part is the token name.
+    ///
+    /// # Returns
+    ///
+    /// The parsed rule.
+    pub fn parse(line: &str) -> Rule {
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        let mut name = parts.last().copied().unwrap_or_default();
+        let mut target = None;
+        if let Some(rest) = name.strip_prefix('<')
+            && let Some((condition, rest)) = rest.split_once('>')
+        {
+            let (change, condition) = if let Some(condition) = condition.strip_prefix('+') {
+                (ModeChange::Push, condition)
+            } else if let Some(condition) = condition.strip_prefix('-') {
+                (ModeChange::Pop, condition)
+            } else {
+                (ModeChange::Begin, condition)
+            };
+            target = Some((change, condition.to_string()));
+            name = rest;
+        }
+
+        let regex = parts[..parts.len().saturating_sub(1)].join(" ");
+        let (start_conditions, regex) = match regex
+            .strip_prefix('<')
+            .and_then(|rest| rest.split_once('>'))
+        {
+            Some((conditions, regex)) => (
+                conditions.split(',').map(str::to_string).collect(),
+                regex.to_string(),
+            ),
+            None => (Vec::new(), regex),
+        };
+        Rule {
+            regex,
+            name: name.trim_matches('"').to_string(),
+            start_conditions,
+            target,
+        }
+    }
+
+    /// Returns whether the rule is built into the hand-made automata.
+    ///
+    /// Skipped `;` rules are left out, so the characters they match are skipped one at a
+    /// time, unless they change the mode.
+    pub fn is_lexed(&self) -> bool {
+        self.name != ";" || self.target.is_some()
+    }
+}
+
+/// The start conditions, rules and declarations of a specification.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Spec {
+    /// The declared start conditions, in the order of the specification.
+    pub start_conditions: Vec<StartCondition>,
+    /// The rules, in the order of the specification.
+    pub rules: Vec<Rule>,
+    /// The nested comments, in the order of the specification.
+    pub nested_comments: Vec<NestedComment>,
+    /// Whether the specification declares `%set-operators`, which makes `&` and `~` the set
+    /// operators described at `SetExpr` for the hand-made lexers. Otherwise they stand for
+    /// themselves, as in `lrlex`.
+    pub set_operators: bool,
+}
+
+/// A comment that nests, declared with `%nested OPEN CLOSE "NAME"`.
+///
+/// Such a comment is not regular, so it cannot be a rule: the opener is matched like a rule,
+/// and lexing then counts openers and closers until they balance. The whole comment becomes
+/// one token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NestedComment {
+    /// The regex opening the comment and every comment nested in it.
+    pub open: String,
+    /// The regex closing the innermost open comment.
+    pub close: String,
+    /// The token name of the comment, unquoted, or `;` to skip it.
+    pub name: String,
+}
+
+impl NestedComment {
+    /// Returns the rule matching the opener of the comment.
+    pub fn opener_rule(&self) -> Rule {
+        Rule {
+            regex: self.open.clone(),
+            name: self.name.clone(),
+            start_conditions: Vec::new(),
+            target: None,
+        }
+    }
+}
+
+/// Splits the lines of a specification into start condition declarations and rules.
+///
+/// Lines of the form `%s A B` declare inclusive start conditions, `%x A B` exclusive ones,
+/// `%nested OPEN CLOSE "NAME"` a nested comment, and `%set-operators` turns on the set
+/// operators. Every other line holds a regex followed by its token name, which may be quoted.
+/// Lines starting with "%%" or empty lines are ignored.
+///
+/// # Arguments
+///
+/// * `input` - The contents of a specification file.
+///
+/// # Returns
+///
+/// The start conditions, rules and nested comments, in the order of the specification, and
+/// whether the set operators are declared.
+pub fn parse_spec(input: &str) -> Spec {
+    let mut spec = Spec::default();
+    for line in input.lines() {
+        if line.starts_with("%%") || line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let first = parts.next();
+        if first == Some(SET_OPERATORS) {
+            spec.set_operators = true;
+            continue;
+        }
+        if first == Some("%nested") {
+            if let [open, close, name] = parts.collect::<Vec<&str>>()[..] {
+                spec.nested_comments.push(NestedComment {
+                    open: open.to_string(),
+                    close: close.to_string(),
+                    name: name.trim_matches('"').to_string(),
+                });
+            }
+            continue;
+        }
+        if let Some(exclusive) = first.and_then(declaration_kind) {
+            spec.start_conditions
+                .extend(parts.map(|name| StartCondition {
+                    name: name.to_string(),
+                    exclusive,
+                }));
+            continue;
+        }
+        spec.rules.push(Rule::parse(line));
+    }
+    spec
+}
+
+/// Returns whether a word starts a start condition declaration, and of which kind.
+///
+/// Like `lrlex`, any word of `%` and letters or digits starting with `s` or `x` counts.
+///
+/// # Returns
+///
+/// `Some(true)` for an exclusive declaration, `Some(false)` for an inclusive one, or `None`
+/// if the word declares nothing.
+fn declaration_kind(word: &str) -> Option<bool> {
+    let mut chars = word.strip_prefix('%')?.chars();
+    let exclusive = match chars.next()? {
+        's' | 'S' => false,
+        'x' | 'X' => true,
+        _ => return None,
+    };
+    chars
+        .all(|c| c.is_ascii_alphanumeric())
+        .then_some(exclusive)
+}
+
+/// The regex of a rule, split around its anchors and trailing context.
+///
+/// A leading `^` only lets the rule match at the start of a line. A top-level `/` ends the
+/// token: the rule only matches if the regex after it, the trailing context, follows, but the
+/// context is not part of the token. A trailing `$` only lets the rule match before a newline,
+/// like a trailing context of `\n`. Elsewhere, and escaped, the three characters stand for
+/// themselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleRegex {
+    /// Whether the rule is anchored at the start of a line with `^`.
+    pub line_start: bool,
+    /// The regex of the token itself.
+    pub token: String,
+    /// The regex of the trailing context after `/`, if any.
+    pub context: Option<String>,
+    /// Whether the rule is anchored at the end of a line with `$`.
+    pub line_end: bool,
+}
+
+impl RuleRegex {
+    /// Splits the regex of a rule around its anchors and trailing context.
+    ///
+    /// # Arguments
+    ///
+    /// * `regex` - The regex of a rule.
+    ///
+    /// # Returns
+    ///
+    /// The parts of the rule. Any `/` after the first one is part of the context.
+    pub fn parse(regex: &str) -> RuleRegex {
+        let (line_start, regex) = match regex.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, regex),
+        };
+        let (pieces, anchors) = split_top_level(regex, &['$']);
+        let line_end = !anchors.is_empty() && pieces.last().is_some_and(String::is_empty);
+        let regex = if line_end {
+            &regex[..regex.len() - 1]
+        } else {
+            regex
+        };
+
+        let (pieces, _) = split_top_level(regex, &['/']);
+        let context = (pieces.len() > 1).then(|| pieces[1..].join("/"));
+        RuleRegex {
+            line_start,
+            token: pieces[0].clone(),
+            context,
+            line_end,
+        }
+    }
+
+    /// Returns whether the rule has a trailing context, given with `/` or `$`.
+    pub fn has_trailing_context(&self) -> bool {
+        self.context.is_some() || self.line_end
+    }
+}
+
+/// The declaration that makes `&` and `~` set operators in the rules of a specification.
+pub const SET_OPERATORS: &str = "%set-operators";
+
+/// A regex branch combined with set operators.
+///
+/// Set operators are only read in specifications declaring `%set-operators`; elsewhere `&`
+/// and `~` stand for themselves, as in `lrlex`. Within a branch, `r&s` matches the strings matched by both `r` and `s`, `r~s` the strings
+/// matched by `r` but not by `s`, and a leading `~r` the strings not matched by `r`. The
+/// operators bind more loosely than concatenation and more tightly than `|`, and are evaluated
+/// from left to right, so `[a-z]+~if|[0-9]+` is an identifier other than `if`, or a number.
+/// Operators inside a group apply within the group. A complement is taken over the ASCII
+/// characters and the characters its operand mentions, like a negated set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetExpr {
+    /// A regex without top-level set operators.
+    Regex(String),
+    /// The strings not matched by an expression.
+    Complement(Box<SetExpr>),
+    /// The strings matched by both expressions.
+    Intersection(Box<SetExpr>, Box<SetExpr>),
+    /// The strings matched by the first expression but not by the second.
+    Difference(Box<SetExpr>, Box<SetExpr>),
+}
+
+impl SetExpr {
+    /// Parses a regex branch with top-level set operators.
+    ///
+    /// A missing operand is read as the empty regex.
+    ///
+    /// # Arguments
+    ///
+    /// * `branch` - A regex without top-level alternation.
+    ///
+    /// # Returns
+    ///
+    /// The parsed expression, or `None` if the branch uses no top-level set operators.
+    pub fn parse(branch: &str) -> Option<SetExpr> {
+        let (operands, operators) = split_top_level(branch, &['&', '~']);
+        if operators.is_empty() {
+            return None;
+        }
+
+        let mut index = 0;
+        let mut expr = SetExpr::parse_operand(&operands, &operators, &mut index);
+        while index < operators.len() {
+            let operator = operators[index];
+            index += 1;
+            let right = Box::new(SetExpr::parse_operand(&operands, &operators, &mut index));
+            expr = match operator {
+                '&' => SetExpr::Intersection(Box::new(expr), right),
+                _ => SetExpr::Difference(Box::new(expr), right),
+            };
+        }
+        Some(expr)
+    }
+
+    /// Parses an operand with its leading complements.
+    ///
+    /// # Arguments
+    ///
+    /// * `operands` - The text between the operators.
+    /// * `operators` - The operators, where operator `i` follows operand `i`.
+    /// * `index` - The index of the operand to parse, advanced past it.
+    ///
+    /// # Returns
+    ///
+    /// The parsed operand.
+    fn parse_operand(operands: &[String], operators: &[char], index: &mut usize) -> SetExpr {
+        let mut complements = 0;
+        while operands[*index].is_empty() && operators.get(*index) == Some(&'~') {
+            complements += 1;
+            *index += 1;
+        }
+        let mut expr = SetExpr::Regex(operands[*index].clone());
+        for _ in 0..complements {
+            expr = SetExpr::Complement(Box::new(expr));
+        }
+        expr
+    }
+
+    /// Builds a DFA accepting the strings matched by the expression.
+    ///
+    /// Every regex operand gets its own NFA and subset-construction DFA, which are then
+    /// combined with the product construction. Set operators inside the groups of an operand
+    /// are read as such.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The token name the accepting states are named after.
+    ///
+    /// # Returns
+    ///
+    /// A DFA without an underlying NFA, or an error if a regex operand is malformed.
+    pub fn to_dfa(&self, name: &str) -> Result<Dfa, String> {
+        Ok(match self {
+            SetExpr::Regex(regex) => regex_to_dfa(regex, name, true)?,
+            SetExpr::Complement(expr) => expr.to_dfa(name)?.complement(name),
+            SetExpr::Intersection(left, right) => {
+                left.to_dfa(name)?.intersection(&right.to_dfa(name)?, name)
+            }
+            SetExpr::Difference(left, right) => {
+                left.to_dfa(name)?.difference(&right.to_dfa(name)?, name)
+            }
+        })
+    }
+}
+
+/// Returns the character an escaped character of a regex stands for.
+///
+/// As in `lrlex`, `\n`, `\t` and `\r` stand for a line feed, a tab and a carriage return, and
+/// any other escaped character stands for itself. The class `\s` is handled by the callers.
+///
+/// # Arguments
+///
+/// * `escaped` - The character following the backslash.
+///
+/// # Returns
+///
+/// The character matched by the escape.
+pub fn unescape(escaped: char) -> char {
+    match escaped {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+/// Builds the subset-construction DFA of a single regex.
+///
+/// # Arguments
+///
+/// * `regex` - The regex to build.
+/// * `name` - The token name the accepting states are named after.
+/// * `set_operators` - Whether `&` and `~` are set operators in the regex.
+///
+/// # Returns
+///
+/// The DFA, or an error if the regex is malformed.
+fn regex_to_dfa(regex: &str, name: &str, set_operators: bool) -> Result<Dfa, String> {
+    let mut nfa = Nfa::new();
+    nfa.set_operators = set_operators;
+    let root_id = nfa.root_id;
+    nfa.parse_regex(regex, name, root_id, true)?;
+    let mut dfa = Dfa::new();
+    dfa.set_nfa(Arc::new(nfa));
+    dfa.construct_dfa();
+    Ok(dfa)
+}
+
+/// Checks whether a regex uses set operators anywhere, including inside groups.
+///
+/// # Arguments
+///
+/// * `regex` - The regex of a rule.
+///
+/// # Returns
+///
+/// `true` if the regex contains an unescaped `&` or `~` outside of a character class.
+pub fn has_set_operators(regex: &str) -> bool {
+    let mut chars = regex.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' => {
+                chars.by_ref().find(|&c| c == ']');
+            }
+            '&' | '~' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Splits a regex at operators outside of escapes, character classes and groups.
+///
+/// Classes end at the first `]` and groups at the first `)`, as in `Nfa::parse_regex`.
+///
+/// # Arguments
+///
+/// * `regex` - The regex to split.
+/// * `operators` - The characters to split at.
+///
+/// # Returns
+///
+/// The text between the operators, and the operators found, where operator `i` follows
+/// text `i`. There is always one more piece of text than there are operators.
+pub fn split_top_level(regex: &str, operators: &[char]) -> (Vec<String>, Vec<char>) {
+    let mut pieces = Vec::new();
+    let mut found = Vec::new();
+    let mut current = String::new();
+    let mut chars = regex.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.push(c);
+                current.extend(chars.next());
+            }
+            '[' | '(' => {
+                let close = if c == '[' { ']' } else { ')' };
+                current.push(c);
+                for c in chars.by_ref() {
+                    current.push(c);
+                    if c == close {
+                        break;
+                    }
+                }
+            }
+            _ if operators.contains(&c) => {
+                pieces.push(std::mem::take(&mut current));
+                found.push(c);
+            }
+            _ => current.push(c),
+        }
     }
+    pieces.push(current);
+    (pieces, found)
 }
@@ -192,9 +192,25 @@ impl Node {
     ///
     /// A `String` containing the DOT representation of the automaton.
     pub fn to_dot(&self, nodes: &HashMap<usize, Node>) -> String {
+        Node::to_dot_from(&[self.id], nodes)
+    }
+
+    /// Converts the part of an automaton reachable from several start nodes to DOT format.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_ids` - The IDs of the start nodes.
+    /// * `nodes` - A reference to a map of node IDs to `Node` instances.
+    ///
+    /// # Returns
+    ///
+    /// A `String` containing the DOT representation of the automaton.
+    pub fn to_dot_from(start_ids: &[usize], nodes: &HashMap<usize, Node>) -> String {
         let mut dot_string = String::from("digraph FA {\n");
         let mut visited = HashSet::new();
-        self.write_dot(&mut dot_string, nodes, &mut visited);
+        for start_id in start_ids {
+            nodes[start_id].write_dot(&mut dot_string, nodes, &mut visited);
+        }
         dot_string.push_str("}\n");
         dot_string
     }
@@ -220,13 +236,7 @@ impl Node {
         visited.insert(self.id);
         for edge in &self.outgoing_edges {
             let to = nodes.get(&edge.to).unwrap();
-            let escaped_label = edge
-                .path
-                .replace("\\", "\\\\")
-                .replace("\"", "\\\"")
-                .replace("\t", "\\\\t")
-                .replace("\n", "\\\\n")
-                .replace("\r", "\\\\r");
+            let escaped_label = escape_dot(&edge.path);
             dot_string.push_str(&format!(
                 "    {} -> {} [label=\"{}\"];\n",
                 self.id, to.id, escaped_label
@@ -234,13 +244,7 @@ impl Node {
             to.write_dot(dot_string, nodes, visited);
         }
         if self.terminal {
-            let escaped_name = self
-                .name
-                .replace("\\", "\\\\")
-                .replace("\"", "\\\"")
-                .replace("\t", "\\\\t")
-                .replace("\n", "\\\\n")
-                .replace("\r", "\\\\r");
+            let escaped_name = escape_dot(&self.name);
             dot_string.push_str(&format!(
                 "    {} [shape=doublecircle, label=\"{}\"];\n",
                 self.id, escaped_name
@@ -248,3 +252,23 @@ impl Node {
         }
     }
 }
+
+/// Escapes a label for use inside a quoted DOT string.
+///
+/// Tabs and line breaks are shown as escape sequences, and other control characters as
+/// their hexadecimal code, so that every label stays printable.
+fn escape_dot(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\t' => escaped.push_str("\\\\t"),
+            '\n' => escaped.push_str("\\\\n"),
+            '\r' => escaped.push_str("\\\\r"),
+            c if c.is_control() => escaped.push_str(&format!("\\\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,133 @@
+//! Module for shrinking inputs with delta debugging.
+//! An input is reduced in three passes of increasing precision: whole lines are deleted
+//! first, then tokens, then single characters. Each pass uses Zeller's `ddmin` algorithm and
+//! only keeps a deletion if the input is still interesting, e.g. if the lexers still disagree.
+
+/// The unit of deletion of a reduction pass.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Granularity {
+    /// Lines, including their line terminator.
+    Lines,
+    /// Identifier-like words, whitespace runs and single punctuation characters.
+    Tokens,
+    /// Single characters.
+    Chars,
+}
+
+/// Shrinks an input for as long as it stays interesting.
+///
+/// # Arguments
+///
+/// * `input` - The input to shrink; it must be interesting itself.
+/// * `interesting` - A predicate telling whether a candidate input still shows the behaviour
+///   being reduced.
+///
+/// # Returns
+///
+/// A reduced input for which `interesting` holds, and from which no single line, token or
+/// character can be deleted on its own without losing the behaviour.
+pub fn reduce<F: FnMut(&str) -> bool>(input: &str, mut interesting: F) -> String {
+    let mut current = input.to_string();
+    for granularity in [Granularity::Lines, Granularity::Tokens, Granularity::Chars] {
+        let units = split_units(&current, granularity);
+        let kept = ddmin(units, |units| interesting(&units.concat()));
+        current = kept.concat();
+    }
+    current
+}
+
+/// Splits an input into units of deletion of the given granularity.
+///
+/// Concatenating the units gives back the input.
+fn split_units(input: &str, granularity: Granularity) -> Vec<&str> {
+    match granularity {
+        Granularity::Lines => input.split_inclusive('\n').collect(),
+        Granularity::Chars => input
+            .char_indices()
+            .map(|(offset, c)| &input[offset..offset + c.len_utf8()])
+            .collect(),
+        Granularity::Tokens => {
+            let mut units = Vec::new();
+            let mut start = 0;
+            let mut chars = input.char_indices().peekable();
+            while let Some((offset, c)) = chars.next() {
+                // Words and whitespace extend over runs of the same class of character.
+                let same_class = |next: char| {
+                    (is_word_char(c) && is_word_char(next))
+                        || (c.is_whitespace() && next.is_whitespace())
+                };
+                if !chars.peek().is_some_and(|&(_, next)| same_class(next)) {
+                    let end = offset + c.len_utf8();
+                    units.push(&input[start..end]);
+                    start = end;
+                }
+            }
+            units
+        }
+    }
+}
+
+/// Checks whether a character can be part of an identifier or number.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Finds a 1-minimal subsequence of units that is still interesting.
+///
+/// The units are cut into `n` chunks. If a single chunk, or everything but one chunk, is
+/// still interesting, the search continues on that. Otherwise the chunks are made smaller,
+/// until every chunk is a single unit.
+///
+/// # Arguments
+///
+/// * `units` - The units of an interesting input.
+/// * `interesting` - A predicate over candidate units.
+///
+/// # Returns
+///
+/// The remaining units.
+fn ddmin<'a, F: FnMut(&[&'a str]) -> bool>(
+    mut units: Vec<&'a str>,
+    mut interesting: F,
+) -> Vec<&'a str> {
+    let mut n = 2;
+    while units.len() >= 2 {
+        let chunk_size = units.len().div_ceil(n);
+        let chunks: Vec<(usize, usize)> = (0..units.len())
+            .step_by(chunk_size)
+            .map(|start| (start, (start + chunk_size).min(units.len())))
+            .collect();
+
+        // Reduce to a single chunk.
+        if let Some(&(start, end)) = chunks
+            .iter()
+            .find(|&&(start, end)| interesting(&units[start..end]))
+        {
+            units = units[start..end].to_vec();
+            n = 2;
+            continue;
+        }
+
+        // Reduce to the complement of a chunk.
+        let complement = chunks.iter().find_map(|&(start, end)| {
+            let candidate: Vec<&str> = units[..start]
+                .iter()
+                .chain(&units[end..])
+                .copied()
+                .collect();
+            interesting(&candidate).then_some(candidate)
+        });
+        if let Some(candidate) = complement {
+            units = candidate;
+            n = (n - 1).max(2);
+            continue;
+        }
+
+        // Increase the granularity.
+        if n >= units.len() {
+            break;
+        }
+        n = (n * 2).min(units.len());
+    }
+    units
+}
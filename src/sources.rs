@@ -0,0 +1,86 @@
+//! Module for resolving command line inputs into a list of Oat source files.
+//! Inputs may be plain file paths, directories that are searched recursively, or glob patterns.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// The file extension of Oat source files.
+const OAT_EXTENSION: &str = "oat";
+
+/// Expands a list of inputs into the Oat source files they refer to.
+///
+/// Plain paths are kept as given, even if they do not exist, so that the caller can report
+/// them as unreadable. Directories are searched recursively for `.oat` files, and inputs
+/// containing `*`, `?` or `[` are expanded as glob patterns. Files found through a directory
+/// or a pattern are sorted, and duplicates are removed while keeping the first occurrence.
+///
+/// # Arguments
+///
+/// * `inputs` - The paths, directories and patterns to expand.
+///
+/// # Returns
+///
+/// The list of source files in a stable order, or an error message for an invalid pattern.
+pub fn collect_sources(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut sources: Vec<PathBuf> = Vec::new();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+
+    for input in inputs {
+        let mut found = Vec::new();
+        if input.is_dir() {
+            walk_dir(input, &mut found)
+                .map_err(|err| format!("Failed to read directory {}: {}", input.display(), err))?;
+        } else if is_pattern(input) {
+            let pattern = input.to_string_lossy();
+            let paths = glob::glob(&pattern)
+                .map_err(|err| format!("Invalid pattern {}: {}", pattern, err))?;
+            // Unreadable entries are skipped, the same way a shell would not expand them.
+            found.extend(paths.flatten().filter(|path| path.is_file()));
+        } else {
+            found.push(input.clone());
+        }
+        found.sort();
+
+        for path in found {
+            if seen.insert(path.clone()) {
+                sources.push(path);
+            }
+        }
+    }
+
+    Ok(sources)
+}
+
+/// Checks whether an input contains glob metacharacters.
+fn is_pattern(input: &Path) -> bool {
+    input
+        .to_string_lossy()
+        .chars()
+        .any(|c| matches!(c, '*' | '?' | '['))
+}
+
+/// Recursively collects the Oat source files below a directory.
+///
+/// Symbolic links to directories are not followed, so a link pointing back up the tree cannot
+/// make the search recurse forever. Symbolic links to files are collected like files.
+///
+/// # Arguments
+///
+/// * `dir` - The directory to search.
+/// * `found` - The vector the discovered files are appended to.
+fn walk_dir(dir: &Path, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        // Unlike `Path::is_dir`, the file type of an entry does not follow symbolic links.
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk_dir(&path, found)?;
+        } else if file_type.is_symlink() && path.is_dir() {
+            continue;
+        } else if path.extension().is_some_and(|ext| ext == OAT_EXTENSION) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
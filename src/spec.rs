@@ -0,0 +1,353 @@
+//! Module for validating lexer specification (`.l`) files.
+//! A specification is checked against both lexer implementations: the `lrlex` parser used by the
+//! library lexer, and the subset of regex syntax understood by the hand-made NFA construction.
+//! Specifications using set operators, trailing context, line anchors or nested comments are
+//! only checked against the hand-made lexer, since `lrlex` reads `/`, `^` and `$` differently
+//! and has no `%set-operators` or `%nested` declaration. Without `%set-operators`, `&` and `~`
+//! are plain characters, as in `lrlex`.
+
+use crate::nfa::{INITIAL, RuleRegex, has_set_operators, parse_spec};
+use cfgrammar::Spanned;
+use lrlex::{DefaultLexerTypes, LRNonStreamingLexerDef, LexerDef};
+use std::fmt;
+
+/// A problem found in a lexer specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecError {
+    /// The 1-based line number the problem was found on.
+    pub line: usize,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Checks a lexer specification for errors.
+///
+/// The specification is first parsed by `lrlex`, whose errors are reported with the line they
+/// point at, unless it uses features only the hand-made lexer supports. Every rule is then
+/// checked for constructs that the hand-made NFA construction does not support, so that both
+/// lexers can be built from the same file.
+///
+/// # Arguments
+///
+/// * `source` - The contents of the specification file.
+///
+/// # Returns
+///
+/// All problems found, ordered by line. An empty vector means the specification is valid.
+pub fn check_spec(source: &str) -> Vec<SpecError> {
+    let mut errors = Vec::new();
+
+    let lib_errors = if hand_made_only_feature(source).is_some() {
+        Ok(())
+    } else {
+        LRNonStreamingLexerDef::<DefaultLexerTypes>::from_str(source).map(|_| ())
+    };
+    if let Err(lib_errors) = lib_errors {
+        for err in lib_errors {
+            let offset = err.spans().first().map_or(0, |span| span.start());
+            errors.push(SpecError {
+                line: line_of_offset(source, offset),
+                message: format!("{} (library lexer)", err),
+            });
+        }
+    }
+
+    let spec = parse_spec(source);
+    let mut conditions = vec![INITIAL.to_string()];
+    conditions.extend(
+        spec.start_conditions
+            .into_iter()
+            .map(|condition| condition.name),
+    );
+    let mut in_rules = false;
+    for (index, line) in source.lines().enumerate() {
+        if line.starts_with("%%") {
+            in_rules = true;
+            continue;
+        }
+        let result = if line.starts_with("%nested") {
+            check_nested_comment(line, spec.set_operators)
+        } else if !in_rules || line.trim().is_empty() {
+            continue;
+        } else {
+            check_rule(line, &conditions, spec.set_operators)
+        };
+        if let Err(message) = result {
+            errors.push(SpecError {
+                line: index + 1,
+                message: format!("{} (hand-made lexer)", message),
+            });
+        }
+    }
+
+    errors.sort_by_key(|err| err.line);
+    errors
+}
+
+/// Checks whether a specification declares set operators with `%set-operators`.
+///
+/// Such specifications cannot be lexed by the library lexer. Without the declaration, `&` and
+/// `~` match themselves in both lexers.
+///
+/// # Arguments
+///
+/// * `source` - The contents of the specification file.
+///
+/// # Returns
+///
+/// `true` if `&` and `~` are set operators in the rules of the specification.
+pub fn uses_set_operations(source: &str) -> bool {
+    parse_spec(source).set_operators
+}
+
+/// Returns the first feature of a specification that only the hand-made lexer supports.
+///
+/// The library lexer cannot be built from a specification using such a feature.
+///
+/// # Arguments
+///
+/// * `source` - The contents of the specification file.
+///
+/// # Returns
+///
+/// A description of the feature, or `None` if both lexers support the whole specification.
+pub fn hand_made_only_feature(source: &str) -> Option<&'static str> {
+    if uses_set_operations(source) {
+        return Some("set operators '%set-operators'");
+    }
+    let spec = parse_spec(source);
+    let rules: Vec<RuleRegex> = spec
+        .rules
+        .iter()
+        .map(|rule| RuleRegex::parse(&rule.regex))
+        .collect();
+    if rules.iter().any(|rule| rule.context.is_some()) {
+        Some("trailing context '/'")
+    } else if rules.iter().any(|rule| rule.line_start || rule.line_end) {
+        Some("the line anchors '^' and '$'")
+    } else if !spec.nested_comments.is_empty() {
+        Some("nested comments '%nested'")
+    } else {
+        None
+    }
+}
+
+/// Checks a single rule line against the syntax supported by the hand-made lexer.
+///
+/// # Arguments
+///
+/// * `line` - A rule line of the form `regex "NAME"` or `regex ;`, optionally with start
+///   conditions as in `<A,B>regex <+C>"NAME"`.
+/// * `conditions` - The names of the declared start conditions, and `INITIAL`.
+/// * `set_operators` - Whether the specification declares set operators.
+///
+/// # Returns
+///
+/// `Ok(())` if the rule is supported, or a description of the problem otherwise.
+fn check_rule(line: &str, conditions: &[String], set_operators: bool) -> Result<(), String> {
+    let parts = line.split_whitespace().collect::<Vec<&str>>();
+    if parts.len() < 2 {
+        return Err("Rule is missing a token name".to_string());
+    }
+
+    let mut name = parts[parts.len() - 1];
+    if let Some(rest) = name.strip_prefix('<') {
+        let Some((target, rest)) = rest.split_once('>') else {
+            return Err(
+                "Start condition before the token name is missing a closing '>'".to_string(),
+            );
+        };
+        check_start_condition(target.trim_start_matches(['+', '-']), conditions)?;
+        name = rest;
+    }
+    check_token_name(name)?;
+
+    let mut regex = parts[..parts.len() - 1].join(" ");
+    if let Some(rest) = regex.strip_prefix('<') {
+        let Some((prefix, rest)) = rest.split_once('>') else {
+            return Err("Start condition prefix is missing a closing '>'".to_string());
+        };
+        for condition in prefix.split(',') {
+            check_start_condition(condition, conditions)?;
+        }
+        regex = rest.to_string();
+    }
+    check_regex(&regex, set_operators)
+}
+
+/// Checks a nested comment declaration against the syntax supported by the hand-made lexer.
+///
+/// # Arguments
+///
+/// * `line` - A declaration of the form `%nested OPEN CLOSE "NAME"`.
+/// * `set_operators` - Whether the specification declares set operators.
+///
+/// # Returns
+///
+/// `Ok(())` if the declaration is supported, or a description of the problem otherwise.
+fn check_nested_comment(line: &str, set_operators: bool) -> Result<(), String> {
+    let parts = line.split_whitespace().collect::<Vec<&str>>();
+    let ["%nested", open, close, name] = parts[..] else {
+        return Err("Nested comment must be declared as %nested OPEN CLOSE \"NAME\"".to_string());
+    };
+    check_token_name(name)?;
+    for regex in [open, close] {
+        let parts = RuleRegex::parse(regex);
+        if parts.line_start
+            || parts.has_trailing_context()
+            || (set_operators && has_set_operators(regex))
+        {
+            return Err(format!(
+                "Nested comment delimiter {} must be a plain regex",
+                regex
+            ));
+        }
+        check_regex(regex, false)?;
+    }
+    Ok(())
+}
+
+/// Checks that a token name is quoted or the skipped rule `;`.
+fn check_token_name(name: &str) -> Result<(), String> {
+    let is_quoted = name.len() >= 2 && name.starts_with('"') && name.ends_with('"');
+    if name != ";" && !is_quoted {
+        return Err(format!("Token name {} must be quoted or ';'", name));
+    }
+    Ok(())
+}
+
+/// Checks a rule regex against the syntax supported by the hand-made lexer.
+///
+/// # Arguments
+///
+/// * `regex` - The regex of a rule, without its start condition prefix.
+/// * `set_operators` - Whether `&` and `~` are set operators rather than characters.
+///
+/// # Returns
+///
+/// `Ok(())` if the regex is supported, or a description of the problem otherwise.
+fn check_regex(regex: &str, set_operators: bool) -> Result<(), String> {
+    let mut chars = regex.chars().peekable();
+    // Whether the previous item can be the operand of a repetition operator.
+    let mut has_operand = false;
+    // The last set operator, while it still waits for its right operand.
+    let mut set_operator = None;
+    // Whether a top-level '/' started the trailing context.
+    let mut has_context = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if chars.next().is_none() {
+                    return Err("Regex ends with an unfinished escape".to_string());
+                }
+                has_operand = true;
+            }
+            '[' => {
+                // The hand-made lexer ends a class at the first ']', even an escaped one, so
+                // a backslash right before it leaves an escape unfinished.
+                let mut closed = false;
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        closed = true;
+                        break;
+                    }
+                    escaped = c == '\\' && !escaped;
+                }
+                if !closed {
+                    return Err("Character class is missing a closing ']'".to_string());
+                }
+                if escaped {
+                    return Err("Character class ends with an unfinished escape".to_string());
+                }
+                has_operand = true;
+            }
+            '(' => {
+                // Groups end at the first ')' in the same way.
+                let mut closed = false;
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    if c == '(' && !escaped {
+                        return Err("Nested groups are not supported".to_string());
+                    } else if c == ')' {
+                        closed = true;
+                        break;
+                    }
+                    escaped = c == '\\' && !escaped;
+                }
+                if !closed {
+                    return Err("Group is missing a closing ')'".to_string());
+                }
+                if escaped {
+                    return Err("Group ends with an unfinished escape".to_string());
+                }
+                has_operand = true;
+            }
+            ')' | ']' => return Err(format!("Unmatched '{}'", c)),
+            '*' | '+' | '?' => {
+                if !has_operand {
+                    return Err(format!("Repetition operator '{}' has no operand", c));
+                }
+            }
+            '&' | '~' if set_operators => {
+                // A '~' without a left operand complements its right operand.
+                if c == '&' && !has_operand {
+                    return Err("Set operator '&' has no left operand".to_string());
+                }
+                set_operator = Some(c);
+                has_operand = false;
+                continue;
+            }
+            '|' | '/' => {
+                if let Some(operator) = set_operator {
+                    return Err(format!("Set operator '{}' has no right operand", operator));
+                }
+                if c == '/' {
+                    if has_context {
+                        return Err("Rule has more than one trailing context '/'".to_string());
+                    }
+                    has_context = true;
+                }
+                has_operand = false;
+            }
+            _ => has_operand = true,
+        }
+        if has_operand {
+            set_operator = None;
+        }
+    }
+
+    if let Some(operator) = set_operator {
+        return Err(format!("Set operator '{}' has no right operand", operator));
+    }
+    Ok(())
+}
+
+/// Checks that a start condition used by a rule was declared.
+///
+/// # Arguments
+///
+/// * `condition` - The name of the start condition.
+/// * `conditions` - The names of the declared start conditions, and `INITIAL`.
+///
+/// # Returns
+///
+/// `Ok(())` if the start condition was declared, or a description of the problem otherwise.
+fn check_start_condition(condition: &str, conditions: &[String]) -> Result<(), String> {
+    if conditions.iter().any(|declared| declared == condition) {
+        Ok(())
+    } else {
+        Err(format!("Unknown start condition '{}'", condition))
+    }
+}
+
+/// Converts a byte offset into a 1-based line number.
+fn line_of_offset(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
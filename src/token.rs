@@ -0,0 +1,201 @@
+//! Module defining the tokens produced by both lexer implementations, together with their
+//! position in the source code.
+
+use std::fmt;
+
+/// A half-open range `start..end` of byte offsets into the lexed input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The byte offset of the first byte of the token.
+    pub start: usize,
+    /// The byte offset one past the last byte of the token.
+    pub end: usize,
+}
+
+impl Span {
+    /// Creates a new span covering the bytes `start..end`.
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// A token produced by a lexer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    /// The token name, e.g. `ID` or `INTLITERAL`.
+    pub kind: String,
+    /// The text of the token.
+    pub lexeme: String,
+    /// The position of the token in the input.
+    pub span: Span,
+    /// The 1-based line the token starts on.
+    pub line: usize,
+    /// The 1-based column, counted in characters, the token starts at.
+    pub column: usize,
+    /// The value of a literal token, set only when literal values were requested.
+    pub value: Option<Value>,
+}
+
+impl Token {
+    /// Creates a new token, computing its line and column from the input.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - The token name.
+    /// * `lexeme` - The text of the token.
+    /// * `span` - The position of the token in the input.
+    /// * `lines` - The line index of the input.
+    ///
+    /// # Returns
+    ///
+    /// A new `Token` instance.
+    pub fn new(kind: String, lexeme: String, span: Span, lines: &LineIndex) -> Token {
+        let (line, column) = lines.line_col(span.start);
+        Token {
+            kind,
+            lexeme,
+            span,
+            line,
+            column,
+            value: None,
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<15} {}", self.kind, self.lexeme)
+    }
+}
+
+/// The value of a literal token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// The value of an `INTLITERAL`.
+    Int(i64),
+    /// The value of `true` or `false`.
+    Bool(bool),
+    /// The value of `null`.
+    Null,
+    /// The value of a `STRINGLITERAL`, with its escape sequences decoded.
+    Str(String),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Null => write!(f, "null"),
+            Value::Str(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+/// A problem a lexer found in its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    /// A description of the problem.
+    pub message: String,
+    /// The part of the input the problem is reported at.
+    pub span: Span,
+    /// The 1-based line the problem is reported at.
+    pub line: usize,
+    /// The 1-based column, counted in characters, the problem is reported at.
+    pub column: usize,
+}
+
+impl LexError {
+    /// Creates a new lexer error, computing its line and column from the input.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - A description of the problem.
+    /// * `span` - The part of the input the problem is reported at.
+    /// * `lines` - The line index of the input.
+    ///
+    /// # Returns
+    ///
+    /// A new `LexError` instance.
+    pub fn new(message: String, span: Span, lines: &LineIndex) -> LexError {
+        let (line, column) = lines.line_col(span.start);
+        LexError {
+            message,
+            span,
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// An index of line starts used to convert byte offsets into lines and columns.
+pub struct LineIndex<'a> {
+    /// The indexed input.
+    input: &'a str,
+    /// The byte offset at which each line starts.
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    /// Builds the line index of an input string.
+    pub fn new(input: &'a str) -> LineIndex<'a> {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        LineIndex { input, line_starts }
+    }
+
+    /// Converts a byte offset into a 1-based line and a 1-based character column.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - A byte offset into the input, at a character boundary.
+    ///
+    /// # Returns
+    ///
+    /// The `(line, column)` pair of the offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        let column = self.input[line_start..offset].chars().count() + 1;
+        (line, column)
+    }
+
+    /// Returns the byte offset a 1-based line starts at.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - A line number between 1 and `line_count()`.
+    pub fn line_start(&self, line: usize) -> usize {
+        self.line_starts[line - 1]
+    }
+
+    /// Returns the number of lines in the input.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the text of a 1-based line without its line terminator.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - A line number between 1 and `line_count()`.
+    pub fn line_text(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.input.len(), |&next| next - 1);
+        self.input[start..end].trim_end_matches('\r')
+    }
+}
@@ -0,0 +1,206 @@
+//! Helpers shared by the integration tests.
+//!
+//! The lexers built from `src/oat.l` are built once per test binary, and the agreement checks
+//! run every hand-made backend and both DFA builders over a specification. Each test binary
+//! uses only some of them.
+
+#![allow(dead_code)]
+
+pub mod specs;
+
+use lex4oat::derivative::dfa_from_spec;
+use lex4oat::lazy::LazyDfa;
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::liblex4oat::LibLex4Oat;
+use lex4oat::nfa::Nfa;
+use lex4oat::spec::hand_made_only_feature;
+use lex4oat::token::Token;
+use lrlex::{DefaultLexerTypes, LRNonStreamingLexerDef, LexerDef};
+use lrpar::{Lexeme, Lexer, NonStreamingLexer};
+use std::borrow::Borrow;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The Oat specification.
+pub const OAT_SPEC: &str = include_str!("../../src/oat.l");
+
+/// Returns the hand-made lexer compiled from `src/oat.l`, built once for all tests.
+pub fn compiled() -> &'static CompiledLexer {
+    static COMPILED: OnceLock<CompiledLexer> = OnceLock::new();
+    COMPILED.get_or_init(|| CompiledLexer::from_spec(OAT_SPEC).unwrap())
+}
+
+/// Returns a lazy DFA over the hand-made lexer's NFA with a cache small enough to be flushed
+/// on most inputs.
+pub fn lazy() -> &'static LazyDfa {
+    static LAZY: OnceLock<LazyDfa> = OnceLock::new();
+    LAZY.get_or_init(|| LazyDfa::new(construct(OAT_SPEC), 8))
+}
+
+/// Lexes an input with the hand-made lexer built from `oat.l`.
+pub fn hand_lex(input: &str) -> Vec<Token> {
+    compiled().lex(input)
+}
+
+/// Lexes an input with the library lexer.
+pub fn lib_lex(input: &str) -> Vec<Token> {
+    let mut lexer = LibLex4Oat::new(input.to_string());
+    lexer.lex();
+    lexer.tokens().clone()
+}
+
+/// Lexes an input with `lrlex`, built from a specification at run time.
+pub fn lrlex_kinds(spec: &str, input: &str) -> Vec<(String, String)> {
+    let lexerdef = LRNonStreamingLexerDef::<DefaultLexerTypes>::from_str(spec).unwrap();
+    let lexer = lexerdef.lexer(input);
+    lexer
+        .iter()
+        .map(|lexeme| {
+            let lexeme = lexeme.unwrap();
+            let name = lexerdef.get_rule_by_id(lexeme.tok_id()).name().unwrap();
+            (name.to_string(), lexer.span_str(lexeme.span()).to_string())
+        })
+        .collect()
+}
+
+/// Returns the kinds and lexemes of tokens, owned or borrowed.
+pub fn kinds<T: Borrow<Token>>(tokens: impl IntoIterator<Item = T>) -> Vec<(String, String)> {
+    tokens
+        .into_iter()
+        .map(|token| {
+            let token = token.borrow();
+            (token.kind.clone(), token.lexeme.clone())
+        })
+        .collect()
+}
+
+/// Builds a specification from rule lines.
+pub fn spec_of(rules: &[&str]) -> String {
+    format!("%%\n{}\n", rules.join("\n"))
+}
+
+/// Converts expected kinds and lexemes into owned strings.
+pub fn owned(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+    expected
+        .iter()
+        .map(|(kind, lexeme)| (kind.to_string(), lexeme.to_string()))
+        .collect()
+}
+
+/// Builds the NFA of a specification.
+fn construct(spec: &str) -> Nfa {
+    let mut nfa = Nfa::new();
+    nfa.add_keywords(spec);
+    nfa.construct().unwrap();
+    nfa
+}
+
+/// Asserts that the subset DFA, the NFA simulation and a lazy DFA cut the same tokens and
+/// report the same errors on every input, and, unless the specification uses a feature only
+/// the hand-made lexers support, the same tokens as `lrlex`.
+///
+/// # Arguments
+///
+/// * `spec` - The specification to build the lexers from.
+/// * `inputs` - The inputs to lex.
+/// * `cache_limit` - The number of states the lazy DFA may cache.
+///
+/// # Returns
+///
+/// The lazy DFA, so that callers can check how often its cache was flushed.
+pub fn assert_backends_agree(spec: &str, inputs: &[&str], cache_limit: usize) -> LazyDfa {
+    let compiled = CompiledLexer::from_spec(spec).unwrap();
+    let lazy = LazyDfa::new(construct(spec), cache_limit);
+    let lrlex = hand_made_only_feature(spec).is_none();
+    for input in inputs {
+        let dfa_lexed = compiled.lex_with_errors(input);
+        if lrlex {
+            assert_eq!(
+                lrlex_kinds(spec, input),
+                kinds(dfa_lexed.0.clone()),
+                "lrlex on {:?}",
+                input
+            );
+        }
+        assert_eq!(
+            compiled.nfa().lex_with_errors(input),
+            dfa_lexed,
+            "NFA on {:?}",
+            input
+        );
+        assert_eq!(
+            lazy.lex_with_errors(input),
+            dfa_lexed,
+            "lazy DFA on {:?}",
+            input
+        );
+    }
+    lazy
+}
+
+/// Asserts that the derivative builder and the subset construction build equivalent DFAs.
+pub fn assert_builders_agree(spec: &str) {
+    let subset = CompiledLexer::from_spec(spec).unwrap();
+    assert_eq!(
+        subset.dfa().distinguish(&dfa_from_spec(spec)),
+        None,
+        "the derivative and subset DFAs differ for:\n{}",
+        spec
+    );
+}
+
+/// Runs a subcommand on an input written to a temporary file, with a specification other
+/// than `oat.l` if one is given.
+///
+/// # Arguments
+///
+/// * `spec` - The specification, or `None` for `src/oat.l`.
+/// * `args` - The subcommand and its options, which the input file follows.
+/// * `input` - The contents of the input file.
+///
+/// # Returns
+///
+/// The exit status and the standard error of the run.
+pub fn run_lex4oat(spec: Option<&str>, args: &[&str], input: &str) -> (Option<i32>, String) {
+    run(spec, args, Some(input))
+}
+
+/// Runs a subcommand that takes no input file with a specification.
+///
+/// # Returns
+///
+/// The exit status and the standard error of the run.
+pub fn run_lex4oat_on_spec(spec: &str, args: &[&str]) -> (Option<i32>, String) {
+    run(Some(spec), args, None)
+}
+
+/// Runs a subcommand in a temporary directory holding the specification and the input file.
+fn run(spec: Option<&str>, args: &[&str], input: Option<&str>) -> (Option<i32>, String) {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "lex4oat-{}-{}",
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_lex4oat"));
+    match spec {
+        Some(spec) => {
+            std::fs::write(dir.join("spec.l"), spec).unwrap();
+            command.arg("--spec").arg(dir.join("spec.l"))
+        }
+        None => command.args(["--spec", concat!(env!("CARGO_MANIFEST_DIR"), "/src/oat.l")]),
+    };
+    command.args(args);
+    if let Some(input) = input {
+        std::fs::write(dir.join("input.oat"), input).unwrap();
+        command.arg(dir.join("input.oat"));
+    }
+    let output = command.env("NO_COLOR", "1").output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
@@ -0,0 +1,75 @@
+//! Specifications exercising the features of the specification language beyond `oat.l`,
+//! with inputs touching their rules.
+//!
+//! Each feature has its own test file; `tests/features.rs` runs the checks every feature
+//! shares over all of them.
+
+/// A specification using every set operator.
+pub const SET_OPERATIONS: &str = "%set-operators
+%%
+if \"IF\"
+[a-z]+~if \"ID\"
+[0-9]+&~(0[0-9]*) \"NUM\"
+0 \"ZERO\"
+\\/\\*(~[^\\n]*\\*\\/[^\\n]*)\\*\\/ \"COMMENT\"
+[ \\t\\n\\r]+ ;
+";
+
+/// An input touching every rule of `SET_OPERATIONS`.
+pub const SET_OPERATIONS_INPUT: &str = "if ifx iff abc 120 007 0 /* a */ b */ /**/";
+
+/// A specification using trailing context and both anchors.
+pub const TRAILING_CONTEXT: &str = "%%
+x+/x \"XS\"
+[a-z]+/\\( \"CALL\"
+[a-z]+ \"ID\"
+\\-/[0-9] \"NEG\"
+\\- \"DASH\"
+[0-9]+ \"INT\"
+^#[a-z]+ \"DIRECTIVE\"
+# \"HASH\"
+[a-z]+$ \"LAST\"
+\\( \"LPAREN\"
+[ \\t\\n\\r]+ ;
+";
+
+/// An input touching every rule of `TRAILING_CONTEXT`.
+pub const TRAILING_CONTEXT_INPUT: &str = "#define f(y) -1 - 2\n  #if foo\nxxx end";
+
+/// A specification lexing strings with escapes and nested comments in exclusive modes.
+pub const START_CONDITIONS: &str = r#"%x STRING COMMENT
+%%
+\" <STRING>"QUOTE"
+<STRING>\\[\"\\t] "ESCAPE"
+<STRING>[^\"\\]+ "CHARS"
+<STRING>\" <INITIAL>"END_QUOTE"
+\(\* <+COMMENT>;
+<COMMENT>\(\* <+COMMENT>;
+<COMMENT>\*\) <-COMMENT>;
+<COMMENT>[^\(\*\)]+ ;
+<COMMENT>[\(\*\)] ;
+[a-z]+ "ID"
+[ \t\n\r]+ ;
+"#;
+
+/// An input touching every rule of `START_CONDITIONS`.
+pub const START_CONDITIONS_INPUT: &str = r#"say "a\"b\tc" (* x (* y *) "no" *) done "end""#;
+
+/// A specification with an inclusive start condition.
+pub const INCLUSIVE_START_CONDITION: &str = "%s HEX
+%%
+0x <HEX>\"PREFIX\"
+<HEX>[0-9a-f]+ <INITIAL>\"DIGITS\"
+[0-9]+ \"INT\"
+[a-z]+ \"ID\"
+[ ] ;
+";
+
+/// A specification with C-style comments that nest.
+pub const NESTED_COMMENTS: &str = "%nested \\/\\* \\*\\/ \"COMMENT\"
+%%
+[a-z]+ \"ID\"
+\\/ \"SLASH\"
+\\* \"TIMES\"
+[ \\t\\n\\r]+ ;
+";
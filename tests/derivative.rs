@@ -0,0 +1,93 @@
+//! Equivalence tests for DFAs.
+//!
+//! The derivative-based DFA builder and the subset construction read the same specification;
+//! any difference between the resulting DFAs points at a bug in one of the two constructions.
+//! Edited specifications are compared with the original to find the shortest input whose
+//! tokenization changed.
+
+mod common;
+
+use common::{OAT_SPEC, assert_builders_agree, spec_of};
+use lex4oat::derivative::dfa_from_spec;
+use lex4oat::dfa::Distinction;
+use lex4oat::lex4oat::CompiledLexer;
+
+#[test]
+fn oat_spec_builders_agree() {
+    assert_builders_agree(OAT_SPEC);
+}
+
+#[test]
+fn repetition_and_alternation_builders_agree() {
+    assert_builders_agree(&spec_of(&[
+        "a*+ \"STACKED\"",
+        "(ab|c)?d \"OPTIONAL_GROUP\"",
+        "x||y \"EMPTY_BRANCH\"",
+        "x| \"TRAILING_BAR\"",
+        "+q \"LEADING_PLUS\"",
+        "(a|b)+c* \"GROUP_PLUS\"",
+        "\\s+z \"SPACED\"",
+    ]));
+}
+
+#[test]
+fn set_builders_agree() {
+    assert_builders_agree(&spec_of(&[
+        "[^a-c]+ \"NEGATED\"",
+        "[-a] \"LEADING_DASH\"",
+        "[\\s0-2] \"ESCAPED_WHITESPACE\"",
+        "[a-cx-z]+ \"RANGES\"",
+        "[] \"EMPTY_SET\"",
+    ]));
+}
+
+#[test]
+fn different_specs_are_not_equivalent() {
+    let subset = CompiledLexer::from_spec(&spec_of(&["a+ \"A\"", "b \"B\""])).unwrap();
+    let renamed = dfa_from_spec(&spec_of(&["a+ \"A\"", "b \"C\""]));
+    let longer = dfa_from_spec(&spec_of(&["a+b? \"A\"", "b \"B\""]));
+    assert!(!subset.dfa().equivalent(&renamed));
+    assert!(!subset.dfa().equivalent(&longer));
+}
+
+#[test]
+fn reordering_prefix_free_rules_keeps_tokenization() {
+    let original = OAT_SPEC;
+    let reordered = original.replace("\\< \"LT\"\n\\<\\= \"LE\"", "\\<\\= \"LE\"\n\\< \"LT\"");
+    assert_ne!(original, reordered);
+    let old = CompiledLexer::from_spec(original).unwrap();
+    let new = CompiledLexer::from_spec(&reordered).unwrap();
+    assert_eq!(old.dfa().distinguish(new.dfa()), None);
+}
+
+#[test]
+fn shortest_distinguishing_input_is_found() {
+    let original = OAT_SPEC;
+    let old = CompiledLexer::from_spec(original).unwrap();
+
+    let unsigned = original.replace("-?(0x", "(0x");
+    let new = CompiledLexer::from_spec(&unsigned).unwrap();
+    assert_eq!(
+        old.dfa().distinguish(new.dfa()),
+        Some(Distinction {
+            input: "-0".to_string(),
+            left: Some("INTLITERAL".to_string()),
+            right: None,
+            at_line_start: true,
+            mode: "INITIAL".to_string(),
+        })
+    );
+
+    let renamed = original.replace("\"LE\"", "\"LESS_EQUAL\"");
+    let new = CompiledLexer::from_spec(&renamed).unwrap();
+    assert_eq!(
+        old.dfa().distinguish(new.dfa()),
+        Some(Distinction {
+            input: "<=".to_string(),
+            left: Some("LE".to_string()),
+            right: Some("LESS_EQUAL".to_string()),
+            at_line_start: true,
+            mode: "INITIAL".to_string(),
+        })
+    );
+}
@@ -0,0 +1,250 @@
+//! Tests for aligning two token streams and reporting where they diverge.
+//!
+//! The alignment must be a shortest edit script, so that each differing region holds only the
+//! tokens that really differ, and the report must underline them even on lines holding
+//! non-ASCII text. A lexer stopping is recorded and reported along with the differences.
+
+use lex4oat::diff::{Hunk, MismatchKind, Side, Stop, TokenDiff};
+use lex4oat::token::{LineIndex, Span, Token};
+use proptest::prelude::*;
+
+/// Builds the tokens of an input from `(kind, start, end)` triples.
+fn tokens(input: &str, spans: &[(&str, usize, usize)]) -> Vec<Token> {
+    let lines = LineIndex::new(input);
+    spans
+        .iter()
+        .map(|&(kind, start, end)| {
+            Token::new(
+                kind.to_string(),
+                input[start..end].to_string(),
+                Span::new(start, end),
+                &lines,
+            )
+        })
+        .collect()
+}
+
+/// Builds one token per character of an input, named after the character.
+fn letters(input: &str) -> Vec<Token> {
+    let spans: Vec<(String, usize, usize)> = input
+        .char_indices()
+        .map(|(start, c)| (c.to_string(), start, start + c.len_utf8()))
+        .collect();
+    let spans: Vec<(&str, usize, usize)> = spans
+        .iter()
+        .map(|(kind, start, end)| (kind.as_str(), *start, *end))
+        .collect();
+    tokens(input, &spans)
+}
+
+/// Builds one token per character, all at the start of the input, so that tokens match if
+/// and only if their characters are equal.
+fn at_start(input: &str) -> Vec<Token> {
+    input
+        .chars()
+        .map(|c| {
+            Token::new(
+                c.to_string(),
+                c.to_string(),
+                Span::new(0, 0),
+                &LineIndex::new(""),
+            )
+        })
+        .collect()
+}
+
+/// Returns the number of tokens removed or added by the hunks of a diff.
+fn edit_count(diff: &TokenDiff) -> usize {
+    diff.hunks()
+        .iter()
+        .map(|hunk| hunk.lib.len() + hunk.hand.len())
+        .sum()
+}
+
+/// Returns the length of the longest common subsequence of two strings.
+fn lcs_len(a: &[char], b: &[char]) -> usize {
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    lcs[0][0]
+}
+
+/// Returns the point at which the library lexer stopped.
+fn lib_stop(offset: usize) -> Option<Stop> {
+    Some(Stop {
+        offset,
+        side: Side::Reference,
+    })
+}
+
+/// Returns the token ranges of the hunks of a diff.
+fn hunks(diff: &TokenDiff) -> Vec<(std::ops::Range<usize>, std::ops::Range<usize>)> {
+    diff.hunks()
+        .iter()
+        .map(|Hunk { lib, hand }| (lib.clone(), hand.clone()))
+        .collect()
+}
+
+#[test]
+fn identical_streams_have_no_hunks() {
+    let input = "abc";
+    let diff = TokenDiff::new(&letters(input), &letters(input));
+    assert!(diff.is_empty());
+    assert_eq!(diff.first_kind(), None);
+    assert_eq!(diff.render(input, &letters(input), &letters(input)), "");
+}
+
+#[test]
+fn alignment_keeps_only_the_differing_tokens() {
+    let input = "abxcdey";
+    let all = letters(input);
+    // a b c d e y against a x c d e, with the tokens at their own positions.
+    let lib: Vec<Token> = [0, 1, 3, 4, 5, 6].iter().map(|&i| all[i].clone()).collect();
+    let hand: Vec<Token> = [0, 2, 3, 4, 5].iter().map(|&i| all[i].clone()).collect();
+    let diff = TokenDiff::new(&lib, &hand);
+    assert_eq!(hunks(&diff), [(1..2, 1..2), (5..6, 5..5)]);
+    let kinds: Vec<MismatchKind> = diff.hunks().iter().map(Hunk::kind).collect();
+    assert_eq!(kinds, [MismatchKind::Changed, MismatchKind::Missing]);
+
+    let diff = TokenDiff::new(&hand, &lib);
+    assert_eq!(hunks(&diff), [(1..2, 1..2), (5..5, 5..6)]);
+    assert_eq!(diff.hunks()[1].kind(), MismatchKind::Extra);
+}
+
+#[test]
+fn alignment_is_a_shortest_edit_script() {
+    // The example of Myers' paper: ABCABBA against CBABAC takes five edits. Tokens only match
+    // at equal positions, so every token is put at the start of the input.
+    let diff = TokenDiff::new(&at_start("ABCABBA"), &at_start("CBABAC"));
+    assert_eq!(edit_count(&diff), 5);
+}
+
+#[test]
+fn fully_divergent_and_empty_streams_are_aligned() {
+    let long = "ab".repeat(5000);
+    let other = "cd".repeat(5000);
+    let diff = TokenDiff::new(&at_start(&long), &at_start(&other));
+    assert_eq!(hunks(&diff), [(0..10000, 0..10000)]);
+    let diff = TokenDiff::new(&at_start(&long), &[]);
+    assert_eq!(hunks(&diff), [(0..10000, 0..0)]);
+    assert_eq!(diff.first_kind(), Some(MismatchKind::Missing));
+    let diff = TokenDiff::new(&[], &at_start(&long));
+    assert_eq!(hunks(&diff), [(0..0, 0..10000)]);
+}
+
+proptest! {
+    #[test]
+    fn alignment_takes_as_few_edits_as_the_longest_common_subsequence_allows(
+        a in "[abc]{0,40}",
+        b in "[abc]{0,40}",
+    ) {
+        let diff = TokenDiff::new(&at_start(&a), &at_start(&b));
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        prop_assert_eq!(edit_count(&diff), a.len() + b.len() - 2 * lcs_len(&a, &b));
+    }
+}
+
+#[test]
+fn split_tokens_form_one_changed_hunk() {
+    let input = "x abc y";
+    let lib = tokens(input, &[("ID", 0, 1), ("ID", 2, 5), ("ID", 6, 7)]);
+    let hand = tokens(
+        input,
+        &[("ID", 0, 1), ("ID", 2, 3), ("ID", 3, 5), ("ID", 6, 7)],
+    );
+    let diff = TokenDiff::new(&lib, &hand);
+    assert_eq!(hunks(&diff), [(1..2, 1..3)]);
+    assert_eq!(diff.first_kind(), Some(MismatchKind::Changed));
+}
+
+#[test]
+fn render_underlines_tokens_after_non_ascii_text() {
+    let input = "x = 1;\né ab\nc";
+    let lib = tokens(input, &[("ID", 0, 1), ("ID", 10, 12), ("ID", 13, 14)]);
+    let hand = tokens(input, &[("ID", 0, 1), ("STRING", 10, 14)]);
+    let report = TokenDiff::new(&lib, &hand)
+        .with_labels("old", "new")
+        .render(input, &lib, &hand);
+    assert_eq!(
+        report,
+        concat!(
+            "first divergence at line 2, column 3: the lexers produced different tokens\n",
+            "  1 | x = 1;\n",
+            "  2 | é ab\n",
+            "    |   ^^\n",
+            "  old: ID \"ab\" at 2:3, ID \"c\" at 3:1\n",
+            "  new: STRING \"ab\\nc\" at 2:3\n",
+        )
+    );
+}
+
+#[test]
+fn render_counts_further_regions() {
+    let input = "ab😀cd";
+    let lib = letters(input);
+    let hand: Vec<Token> = [0, 2, 3].iter().map(|&i| lib[i].clone()).collect();
+    let report = TokenDiff::new(&lib, &hand).render(input, &lib, &hand);
+    assert!(report.starts_with(
+        "first divergence at line 1, column 2: the hand-made lexer is missing tokens\n"
+    ));
+    assert!(report.contains("  1 | ab😀cd\n    |  ^\n"));
+    assert!(report.ends_with("1 more differing regions\n"));
+}
+
+#[test]
+fn tokens_from_the_stop_onwards_are_not_compared() {
+    let input = "x # y z";
+    let lib = tokens(input, &[("ID", 0, 1)]);
+    let hand = tokens(input, &[("ID", 0, 1), ("ID", 4, 5), ("ID", 6, 7)]);
+    assert_eq!(
+        TokenDiff::new(&lib, &hand).first_kind(),
+        Some(MismatchKind::Extra)
+    );
+    let diff = TokenDiff::until(&lib, &hand, lib_stop(2));
+    assert!(diff.is_empty());
+    assert_eq!(diff.stop(), lib_stop(2));
+    assert!(diff.is_truncated());
+
+    let lib = tokens(input, &[("ID", 0, 1), ("ID", 4, 5)]);
+    let diff = TokenDiff::until(&lib, &hand, lib_stop(6));
+    assert!(diff.is_empty());
+    let diff = TokenDiff::until(&lib, &hand, None);
+    assert_eq!(hunks(&diff), [(2..2, 2..3)]);
+    assert_eq!(diff.stop(), None);
+}
+
+#[test]
+fn render_reports_where_a_lexer_stopped() {
+    let input = "x # y";
+    let lib = tokens(input, &[("ID", 0, 1)]);
+    let hand = tokens(input, &[("ID", 0, 1), ("ID", 4, 5)]);
+    let report = TokenDiff::until(&lib, &hand, lib_stop(2)).render(input, &lib, &hand);
+    assert_eq!(
+        report,
+        concat!(
+            "first divergence at line 1, column 3: the library lexer stopped where no rule ",
+            "matches, so the tokens from there on were not compared\n",
+            "  1 | x # y\n",
+            "    |   ^\n",
+        )
+    );
+
+    let lib = tokens(input, &[("ID", 0, 1), ("ID", 4, 5)]);
+    let hand = tokens(input, &[("ID", 0, 1), ("NUMBER", 4, 5)]);
+    let report = TokenDiff::new(&lib, &hand)
+        .with_stop(lib_stop(2))
+        .render(input, &lib, &hand);
+    assert!(report.starts_with("first divergence at line 1, column 5:"));
+    assert!(report.ends_with(concat!(
+        "the library lexer stopped at line 1, column 3 where no rule matches and was ",
+        "restarted after it\n"
+    )));
+}
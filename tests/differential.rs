@@ -0,0 +1,242 @@
+//! Deterministic differential tests between the library lexer and the hand-made lexer.
+//!
+//! Inputs are generated from the Oat token alphabet, from random ASCII bytes, and from random
+//! walks over the hand-made lexer's DFA. A failing byte input is shrunk by proptest and saved
+//! into `test/` as a regression file, which the `regression_files_agree` test then keeps
+//! checking. Synthetic token streams must also produce exactly the tokens they were built from.
+
+mod common;
+
+use common::{compiled, lazy, run_lex4oat};
+use lex4oat::diff::TokenDiff;
+use lex4oat::fuzz::{alphabet_input, ascii_input, check_backends, save_regression};
+use lex4oat::generate::{Generator, SyntheticToken};
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::liblex4oat::{LibLex4Oat, lexer_def_from_spec};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestError, TestRng, TestRunner};
+use std::path::Path;
+
+/// Checks that the library lexer and the hand-made DFA lexer agree on an input.
+fn check_lib_and_dfa(input: &str) -> Result<(), String> {
+    check_backends(input, compiled())
+}
+
+/// Checks that simulating the hand-made NFA gives the same tokens as its DFA.
+fn check_nfa_and_dfa(input: &str) -> Result<(), String> {
+    let dfa_tokens = compiled().lex(input);
+    let nfa_tokens = compiled().nfa().lex(input);
+    let diff = TokenDiff::new(&dfa_tokens, &nfa_tokens).with_labels("DFA", "NFA");
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(diff.render(input, &dfa_tokens, &nfa_tokens))
+    }
+}
+
+/// Checks that the lazily built DFA gives the same tokens as the eagerly built one.
+fn check_lazy_and_dfa(input: &str) -> Result<(), String> {
+    let dfa_tokens = compiled().lex(input);
+    let lazy_tokens = lazy().lex(input);
+    let diff = TokenDiff::new(&dfa_tokens, &lazy_tokens).with_labels("DFA", "lazy DFA");
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(diff.render(input, &dfa_tokens, &lazy_tokens))
+    }
+}
+
+/// Runs a deterministic property test over byte strings turned into source code.
+///
+/// On failure the shrunk input is saved into `test/` and the test panics with the report.
+fn run_differential(generate: fn(&[u8]) -> String, check: fn(&str) -> Result<(), String>) {
+    let config = Config {
+        cases: 512,
+        failure_persistence: None,
+        ..Config::default()
+    };
+    let rng = TestRng::deterministic_rng(config.rng_algorithm);
+    let mut runner = TestRunner::new_with_rng(config, rng);
+
+    let result = runner.run(&vec(any::<u8>(), 0..64), |data| {
+        let input = generate(&data);
+        check(&input).map_err(TestCaseError::fail)
+    });
+
+    match result {
+        Ok(()) => {}
+        Err(TestError::Fail(reason, data)) => {
+            let input = generate(&data);
+            let test_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+            let path = save_regression(&test_dir, &input).expect("Failed to save regression");
+            panic!(
+                "Lexers disagree on {:?}, saved as {}\n{}",
+                input,
+                path.display(),
+                reason
+            );
+        }
+        Err(err) => panic!("{}", err),
+    }
+}
+
+#[test]
+fn alphabet_inputs_agree() {
+    run_differential(alphabet_input, check_lib_and_dfa);
+}
+
+#[test]
+fn ascii_inputs_agree() {
+    run_differential(ascii_input, check_lib_and_dfa);
+}
+
+#[test]
+fn nfa_simulation_matches_dfa() {
+    run_differential(alphabet_input, check_nfa_and_dfa);
+    run_differential(ascii_input, check_nfa_and_dfa);
+}
+
+#[test]
+fn lazy_dfa_matches_dfa() {
+    run_differential(alphabet_input, check_lazy_and_dfa);
+    run_differential(ascii_input, check_lazy_and_dfa);
+    assert!(lazy().flushes() > 0, "the state cache was never flushed");
+}
+
+#[test]
+fn regression_files_agree() {
+    let test_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+    for entry in std::fs::read_dir(test_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "oat") {
+            let input = std::fs::read_to_string(&path).unwrap();
+            if let Err(report) = check_backends(&input, compiled()) {
+                panic!("Lexers disagree on {}\n{}", path.display(), report);
+            }
+        }
+    }
+}
+
+#[test]
+fn synthetic_token_streams_agree() {
+    for seed in 0..64 {
+        let mut generator = Generator::new(compiled().dfa(), seed, 12);
+        let (input, expected) = generator.token_stream(32);
+        if let Err(report) = check_backends(&input, compiled()) {
+            panic!("Lexers disagree on {:?} (seed {})\n{}", input, seed, report);
+        }
+        let actual: Vec<SyntheticToken> = compiled()
+            .lex(&input)
+            .into_iter()
+            .map(|token| SyntheticToken {
+                kind: token.kind,
+                lexeme: token.lexeme,
+            })
+            .collect();
+        assert_eq!(
+            actual, expected,
+            "unexpected tokens for {:?} (seed {})",
+            input, seed
+        );
+    }
+}
+
+#[test]
+fn near_misses_agree() {
+    let mut generator = Generator::new(compiled().dfa(), 0, 12);
+    for rule in generator.rules() {
+        for _ in 0..16 {
+            let Some(input) = generator.near_miss(&rule) else {
+                break;
+            };
+            if let Err(report) = check_backends(&input, compiled()) {
+                panic!("Lexers disagree on {:?} near {}\n{}", input, rule, report);
+            }
+        }
+    }
+}
+
+#[test]
+fn library_lexer_follows_the_given_spec() {
+    let spec = "%%\n[a-z]+ \"WORD\"\n[0-9]+ \"NUMBER\"\n[ \\n]+ ;\n";
+    let input = "abc 12 de\n3";
+    let mut lib = LibLex4Oat::new(input.to_string());
+    lib.lex_with(&lexer_def_from_spec(spec).unwrap());
+    assert_eq!(
+        lib.tokens(),
+        &CompiledLexer::from_spec(spec).unwrap().lex(input)
+    );
+    assert_eq!(lib.tokens()[1].kind, "NUMBER");
+
+    let runtime = lexer_def_from_spec(include_str!("../src/oat.l")).unwrap();
+    let input =
+        std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/test/1.oat")).unwrap();
+    let mut compiled_in = LibLex4Oat::new(input.clone());
+    compiled_in.lex();
+    let mut built = LibLex4Oat::new(input);
+    built.lex_with(&runtime);
+    assert_eq!(built.tokens(), compiled_in.tokens());
+}
+
+#[test]
+fn compare_uses_the_given_spec_for_both_lexers() {
+    let spec = "%%\n[a-z]+ \"WORD\"\n[ \\n]+ ;\n";
+    assert_eq!(
+        run_lex4oat(Some(spec), &["compare"], "abc def\n").0,
+        Some(0)
+    );
+}
+
+#[test]
+fn compare_and_the_differential_tests_agree_on_unmatched_input() {
+    // Once the library lexer is restarted the tokens agree, but `compare` still reports the
+    // stop.
+    for input in ["x # y", "a $ b\n1", "\"open"] {
+        assert_eq!(check_backends(input, compiled()), Ok(()), "{:?}", input);
+        let (status, stderr) = run_lex4oat(None, &["compare"], input);
+        assert_eq!(status, Some(8), "{:?}", input);
+        assert!(
+            stderr.contains("The library lexer stopped where no rule matches"),
+            "{}",
+            stderr
+        );
+    }
+
+    // Tokens after the stop are compared too: the hand-made lexer only skips ASCII characters
+    // in negated sets, so it finds no string here.
+    let input = "x # \"\u{e9}\"";
+    assert!(check_backends(input, compiled()).is_err());
+    assert_eq!(run_lex4oat(None, &["compare"], input).0, Some(5));
+}
+
+#[test]
+fn the_library_lexer_can_restart_after_unmatched_input() {
+    let input = "x # y $ 1";
+    let mut lib = LibLex4Oat::new(input.to_string());
+    lib.lex_resuming();
+    assert_eq!(lib.stops(), [2, 6]);
+    assert_eq!(lib.error_offset(), Some(2));
+    let spans: Vec<(usize, usize)> = lib
+        .tokens()
+        .iter()
+        .map(|token| (token.span.start, token.span.end))
+        .collect();
+    assert_eq!(spans, [(0, 1), (4, 5), (8, 9)]);
+    assert_eq!(lib.tokens(), &compiled().lex(input));
+
+    lib.lex();
+    assert_eq!(lib.stops(), [2]);
+    assert_eq!(lib.tokens().len(), 1);
+}
+
+#[test]
+fn the_library_lexer_stopping_makes_the_tokens_subcommand_fail() {
+    let (status, stderr) = run_lex4oat(None, &["tokens", "-b", "lib"], "int x # y;");
+    assert_eq!(status, Some(7));
+    assert!(
+        stderr
+            .contains("line 1, column 7: No rule matches the input, so the library lexer stopped")
+    );
+    assert!(stderr.contains("1 files, 2 tokens, 1 errors"), "{}", stderr);
+}
@@ -0,0 +1,135 @@
+//! Checks every feature of the specification language beyond `oat.l` must pass: set
+//! operators, trailing context and anchors, start conditions and nested comments.
+//!
+//! Every backend must cut the same tokens from a feature's inputs, the derivative builder must
+//! agree with the subset construction, `spec-diff` must tell an edited specification from the
+//! original, and `check-spec` must accept the specification, skipping the library lexer's
+//! checks where it does not support the feature. The test file of each feature checks what is
+//! particular to it.
+
+mod common;
+
+use common::specs::{
+    INCLUSIVE_START_CONDITION, NESTED_COMMENTS, SET_OPERATIONS, SET_OPERATIONS_INPUT,
+    START_CONDITIONS, START_CONDITIONS_INPUT, TRAILING_CONTEXT, TRAILING_CONTEXT_INPUT,
+};
+use common::{OAT_SPEC, assert_backends_agree, assert_builders_agree};
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::spec::{check_spec, hand_made_only_feature};
+
+/// A specification exercising a feature, and what the shared checks expect of it.
+struct Feature {
+    /// The feature, named in failure messages.
+    name: &'static str,
+    /// The specification using the feature.
+    spec: &'static str,
+    /// The inputs every backend lexes.
+    inputs: &'static [&'static str],
+    /// The feature as `hand_made_only_feature` describes it, or `None` if `lrlex` supports it.
+    hand_made_only: Option<&'static str>,
+    /// A part of the specification and its replacement, which changes how some input lexes.
+    edit: (&'static str, &'static str),
+    /// The shortest input `spec-diff` tells the two specifications apart by, with the rules
+    /// the original and the edited specification accept it with, the mode it is lexed in, and
+    /// whether it is lexed at the start of a line.
+    distinction: (
+        &'static str,
+        Option<&'static str>,
+        Option<&'static str>,
+        &'static str,
+        bool,
+    ),
+}
+
+/// The features, each with a specification using it.
+const FEATURES: &[Feature] = &[
+    Feature {
+        name: "set operators",
+        spec: SET_OPERATIONS,
+        inputs: &[SET_OPERATIONS_INPUT, "ifif 0 00 10 /*/ */", "~if & /**/ */"],
+        hand_made_only: Some("set operators '%set-operators'"),
+        edit: ("[0-9]+&~(0[0-9]*)", "[0-9]+"),
+        distinction: ("0", Some("ZERO"), Some("NUM"), "INITIAL", true),
+    },
+    Feature {
+        name: "trailing context",
+        spec: TRAILING_CONTEXT,
+        inputs: &[TRAILING_CONTEXT_INPUT, "\n#a\n#b c\nd(", "xxxx(\n-x-1", "#"],
+        hand_made_only: Some("trailing context '/'"),
+        edit: ("^#", "#"),
+        // The anchored rule only differs away from the start of a line.
+        distinction: ("#a", None, Some("DIRECTIVE"), "INITIAL", false),
+    },
+    Feature {
+        name: "exclusive start conditions",
+        spec: START_CONDITIONS,
+        inputs: &[START_CONDITIONS_INPUT, "(*(*(**)*)x*)y", "\"\\\\\"z\""],
+        hand_made_only: None,
+        edit: (r#"[\"\\t]"#, r#"[\"\\]"#),
+        distinction: ("\\t", Some("ESCAPE"), None, "STRING", true),
+    },
+    Feature {
+        name: "inclusive start conditions",
+        spec: INCLUSIVE_START_CONDITION,
+        inputs: &["0x0x0x", "9 0xa 0xz", "0xab12 c"],
+        hand_made_only: None,
+        edit: ("[0-9a-f]+", "[0-9a-z]+"),
+        distinction: ("g", Some("ID"), Some("DIGITS"), "HEX", true),
+    },
+    Feature {
+        name: "nested comments",
+        spec: NESTED_COMMENTS,
+        inputs: &["a/**/b", "/* /* */ */ / * /", "x /* /* */", "*/ /*/ a"],
+        hand_made_only: Some("nested comments '%nested'"),
+        edit: ("\\/\\* \\*\\/", "\\/\\*\\* \\*\\/"),
+        distinction: ("/*", Some("COMMENT"), None, "INITIAL", true),
+    },
+];
+
+#[test]
+fn backends_agree_on_every_feature() {
+    for feature in FEATURES {
+        // The smallest state cache, so that the lazy DFA is flushed while lexing.
+        let lazy = assert_backends_agree(feature.spec, feature.inputs, 0);
+        assert!(lazy.flushes() > 0, "{}: cache never flushed", feature.name);
+    }
+}
+
+#[test]
+fn builders_agree_on_every_feature() {
+    for feature in FEATURES {
+        assert_builders_agree(feature.spec);
+    }
+}
+
+#[test]
+fn every_feature_is_compared_by_spec_diff() {
+    for feature in FEATURES {
+        let (part, replacement) = feature.edit;
+        assert!(feature.spec.contains(part), "{}", feature.name);
+        let old = CompiledLexer::from_spec(feature.spec).unwrap();
+        let new = CompiledLexer::from_spec(&feature.spec.replace(part, replacement)).unwrap();
+        let distinction = old.dfa().distinguish(new.dfa()).unwrap();
+
+        let (input, left, right, mode, at_line_start) = feature.distinction;
+        assert_eq!(distinction.input, input, "{}", feature.name);
+        assert_eq!(distinction.left.as_deref(), left, "{}", feature.name);
+        assert_eq!(distinction.right.as_deref(), right, "{}", feature.name);
+        assert_eq!(distinction.mode, mode, "{}", feature.name);
+        assert_eq!(distinction.at_line_start, at_line_start, "{}", feature.name);
+    }
+}
+
+#[test]
+fn library_lexer_checks_are_skipped_for_hand_made_features() {
+    for feature in FEATURES {
+        assert_eq!(
+            hand_made_only_feature(feature.spec),
+            feature.hand_made_only,
+            "{}",
+            feature.name
+        );
+        assert_eq!(check_spec(feature.spec), Vec::new(), "{}", feature.name);
+    }
+    assert_eq!(hand_made_only_feature(OAT_SPEC), None);
+}
@@ -0,0 +1,88 @@
+//! Tests for the machine-readable token formats.
+//!
+//! Lexemes, values and file names holding quotes, backslashes, separators, line breaks,
+//! control characters and non-ASCII text must be quoted so that every token stays on one line
+//! and reads back as the original text.
+
+use lex4oat::format::{Format, write_tokens};
+use lex4oat::token::{Span, Token, Value};
+use serde_json::Value as Json;
+use std::path::Path;
+
+/// A text exercising every character the formats must quote.
+const AWKWARD: &str = "say \"hi\", C:\\dir\nnext\r\tend \u{1}\u{7f} héllo 😀";
+
+/// Returns a string token holding `AWKWARD` as both its lexeme and its value.
+fn awkward_token() -> Token {
+    Token {
+        kind: "STRINGLITERAL".to_string(),
+        lexeme: AWKWARD.to_string(),
+        span: Span::new(0, AWKWARD.len()),
+        line: 1,
+        column: 1,
+        value: Some(Value::Str(AWKWARD.to_string())),
+    }
+}
+
+/// Writes the awkward token of a file named `a,"b".oat` in a format.
+fn render(format: Format) -> String {
+    let tokens = [awkward_token()];
+    let mut out = Vec::new();
+    write_tokens(&mut out, format, &[(Path::new("a,\"b\".oat"), &tokens)]).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn json_escapes_quotes_backslashes_and_control_characters() {
+    let out = render(Format::Jsonl);
+    assert_eq!(out.lines().count(), 1);
+    assert!(out.contains(r#""lexeme":"say \"hi\", C:\\dir\nnext\r\tend \u0001\u007f héllo 😀""#));
+    assert!(out.contains(r#""file":"a,\"b\".oat""#));
+
+    let object: Json = serde_json::from_str(&out).unwrap();
+    assert_eq!(object["lexeme"], AWKWARD);
+    assert_eq!(object["value"], AWKWARD);
+    assert_eq!(object["file"], "a,\"b\".oat");
+
+    let array: Json = serde_json::from_str(&render(Format::Json)).unwrap();
+    assert_eq!(array[0], object);
+}
+
+#[test]
+fn csv_quotes_fields_with_separators_quotes_and_line_breaks() {
+    let out = render(Format::Csv);
+    let expected = format!(
+        "file,kind,lexeme,start,end,line,column,value\n\
+         \"a,\"\"b\"\".oat\",STRINGLITERAL,{quoted},0,{end},1,1,{quoted}\n",
+        quoted = "\"say \"\"hi\"\", C:\\dir\nnext\r\tend \u{1}\u{7f} héllo 😀\"",
+        end = AWKWARD.len(),
+    );
+    assert_eq!(out, expected);
+
+    let mut plain = awkward_token();
+    plain.lexeme = "héllo\\x".to_string();
+    plain.span = Span::new(0, plain.lexeme.len());
+    plain.value = None;
+    let mut out = Vec::new();
+    write_tokens(&mut out, Format::Csv, &[(Path::new("a.oat"), &[plain])]).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(
+        out.lines().nth(1),
+        Some("a.oat,STRINGLITERAL,héllo\\x,0,8,1,1,")
+    );
+}
+
+#[test]
+fn sexp_escapes_quotes_backslashes_and_line_breaks() {
+    let out = render(Format::Sexp);
+    assert_eq!(out.lines().count(), 1);
+    let quoted = "\"say \\\"hi\\\", C:\\\\dir\\nnext\\r\\tend \u{1}\u{7f} héllo 😀\"";
+    assert_eq!(
+        out,
+        format!(
+            "(token :file \"a,\\\"b\\\".oat\" :kind \"STRINGLITERAL\" :lexeme {quoted} \
+             :span (0 . {end}) :line 1 :column 1 :value {quoted})\n",
+            end = AWKWARD.len(),
+        )
+    );
+}
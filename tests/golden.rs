@@ -0,0 +1,177 @@
+//! Golden-file tests over the Oat sources in `test/`.
+//!
+//! Every `test/*.oat` file is lexed with both the library lexer and the hand-made lexer, and
+//! both token streams must match the committed `test/*.tokens` expectation file. Run with
+//! `LEX4OAT_BLESS=1` to rewrite the expectation files after an intentional change; blessing
+//! records the library lexer's tokens, which the hand-made lexer must still match.
+
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::liblex4oat::LibLex4Oat;
+use lex4oat::token::Token;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+/// The environment variable that enables bless mode.
+const BLESS_VAR: &str = "LEX4OAT_BLESS";
+
+/// The maximum number of differing lines shown per file.
+const MAX_DIFF_LINES: usize = 20;
+
+/// The number of threads sharing one lexer.
+const THREADS: usize = 4;
+
+/// Renders tokens in the expectation file format: one `line:column KIND "lexeme"` per line.
+fn render_tokens(tokens: &[Token]) -> String {
+    let mut rendered = String::new();
+    for token in tokens {
+        let position = format!("{}:{}", token.line, token.column);
+        let _ = writeln!(
+            rendered,
+            "{:<7} {:<15} {:?}",
+            position, token.kind, token.lexeme
+        );
+    }
+    rendered
+}
+
+/// Renders a line diff between the expected and actual expectation file contents.
+///
+/// Lines are aligned with a longest common subsequence, and only the differing lines are
+/// shown, prefixed with `-` for expected and `+` for actual along with their line number.
+fn render_diff(expected: &str, actual: &str) -> String {
+    let old: Vec<&str> = expected.lines().collect();
+    let new: Vec<&str> = actual.lines().collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("-{:>4} {}", i + 1, old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+{:>4} {}", j + 1, new[j]));
+            j += 1;
+        }
+    }
+
+    let hidden = lines.len().saturating_sub(MAX_DIFF_LINES);
+    lines.truncate(MAX_DIFF_LINES);
+    if hidden > 0 {
+        lines.push(format!("... {} more differing lines", hidden));
+    }
+    lines.join("\n")
+}
+
+/// Returns every Oat source file in `test/`, relative to the package root and sorted by name.
+fn golden_sources() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut sources: Vec<PathBuf> = std::fs::read_dir(root.join("test"))
+        .unwrap()
+        .map(|entry| Path::new("test").join(entry.unwrap().file_name()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "oat"))
+        .collect();
+    sources.sort();
+    sources
+}
+
+#[test]
+fn tokens_match_golden_files() {
+    let bless = std::env::var_os(BLESS_VAR).is_some_and(|value| value != "0");
+    let compiled = CompiledLexer::from_spec(include_str!("../src/oat.l")).unwrap();
+    let mut failures = Vec::new();
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    for source in golden_sources() {
+        let input = std::fs::read_to_string(root.join(&source)).unwrap();
+        let golden = source.with_extension("tokens");
+
+        let mut lib_lexer = LibLex4Oat::new(input.clone());
+        lib_lexer.lex();
+        let lib_rendered = render_tokens(lib_lexer.tokens());
+        let hand_rendered = render_tokens(&compiled.lex(&input));
+
+        if bless {
+            std::fs::write(root.join(&golden), &lib_rendered).unwrap();
+        }
+        let Ok(expected) = std::fs::read_to_string(root.join(&golden)) else {
+            failures.push(format!(
+                "{}: missing expectation file, run with {}=1 to create it",
+                golden.display(),
+                BLESS_VAR
+            ));
+            continue;
+        };
+
+        for (backend, actual) in [("library", &lib_rendered), ("hand-made", &hand_rendered)] {
+            if *actual != expected {
+                failures.push(format!(
+                    "{}: {} lexer output differs from {}\n{}",
+                    source.display(),
+                    backend,
+                    golden.display(),
+                    render_diff(&expected, actual)
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{}\n\nRun with {}=1 to accept intentional changes.",
+        failures.join("\n\n"),
+        BLESS_VAR
+    );
+}
+
+#[test]
+fn one_lexer_shared_by_several_threads_lexes_like_a_single_thread() {
+    let compiled = Arc::new(CompiledLexer::from_spec(include_str!("../src/oat.l")).unwrap());
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let inputs: Arc<Vec<String>> = Arc::new(
+        golden_sources()
+            .iter()
+            .map(|source| std::fs::read_to_string(root.join(source)).unwrap())
+            .collect(),
+    );
+    let expected: Vec<_> = inputs
+        .iter()
+        .map(|input| compiled.lex_with_errors(input))
+        .collect();
+
+    // Every thread lexes every file, starting at a different one, so the threads lex
+    // different inputs with the same lexer at the same time.
+    let workers: Vec<_> = (0..THREADS)
+        .map(|first| {
+            let compiled = Arc::clone(&compiled);
+            let inputs = Arc::clone(&inputs);
+            thread::spawn(move || {
+                (0..inputs.len())
+                    .map(|i| (i + first) % inputs.len())
+                    .map(|i| (i, compiled.lex_with_errors(&inputs[i])))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for worker in workers {
+        for (i, lexed) in worker.join().unwrap() {
+            assert_eq!(lexed, expected[i], "{}", golden_sources()[i].display());
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Tests for highlighting Oat source files.
+//!
+//! Both lexers must color the same files alike, and stripping the markup of either output
+//! must give back the input byte for byte.
+
+mod common;
+
+use common::{compiled, lib_lex};
+use lex4oat::highlight::{Style, highlight};
+use std::process::Command;
+
+/// Returns the text of the code block of an HTML page, with tags removed and entities
+/// decoded.
+fn html_text(page: &str) -> String {
+    let start = page.find("<code>").unwrap() + "<code>".len();
+    let end = page.rfind("</code>").unwrap();
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in page[start..end].chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// Removes the ANSI escape sequences of a text.
+fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Returns the Oat test files, with their names.
+fn test_files() -> Vec<(String, String)> {
+    let mut files: Vec<(String, String)> =
+        std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/test"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "oat"))
+            .map(|path| {
+                let input = std::fs::read_to_string(&path).unwrap();
+                (path.display().to_string(), input)
+            })
+            .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn html_classes_follow_token_kinds() {
+    let input = "int x = 0x1f; /* <&> */\nif \"a&b\" # y";
+    let page = highlight(Style::Html, "a <b>", input, compiled().lex(input));
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.contains("<title>a &lt;b&gt;</title>"));
+    assert!(page.contains("<style>"));
+    assert!(page.contains(concat!(
+        "<code><span class=\"type\">int</span> ",
+        "<span class=\"identifier\">x</span> ",
+        "<span class=\"operator\">=</span> ",
+        "<span class=\"number\">0x1f</span>",
+        "<span class=\"operator\">;</span> ",
+        "<span class=\"comment\">/* &lt;&amp;&gt; */</span>\n",
+        "<span class=\"keyword\">if</span> ",
+        "<span class=\"string\">&quot;a&amp;b&quot;</span> ",
+        "<span class=\"error\">#</span> ",
+        "<span class=\"identifier\">y</span></code>",
+    )));
+    assert_eq!(html_text(&page), input);
+}
+
+#[test]
+fn ansi_colors_each_line_and_keeps_the_text() {
+    colored::control::set_override(true);
+    let input = "while x /* a\nb */ $";
+    let text = highlight(Style::Ansi, "", input, compiled().lex(input));
+    assert_eq!(strip_ansi(&text), input);
+    assert!(text.starts_with("\x1b[1;95mwhile\x1b[0m \x1b[37mx\x1b[0m "));
+    assert!(text.contains("\x1b[3;90m/* a\x1b[0m\n\x1b[3;90mb */\x1b[0m"));
+    assert!(text.ends_with("\x1b[4;31m$\x1b[0m"));
+}
+
+#[test]
+fn both_lexers_highlight_the_test_files_alike() {
+    for (name, input) in test_files() {
+        let hand = highlight(Style::Html, &name, &input, compiled().lex(&input));
+        let lib = highlight(Style::Html, &name, &input, lib_lex(&input));
+        assert_eq!(hand, lib, "{}", name);
+        assert_eq!(html_text(&hand), input, "{}", name);
+    }
+}
+
+#[test]
+fn the_subcommand_writes_html_or_plain_text() {
+    let input = concat!(env!("CARGO_MANIFEST_DIR"), "/test/1.oat");
+    let output = std::env::temp_dir().join(format!("highlight-{}.html", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_lex4oat"))
+        .args(["--spec", concat!(env!("CARGO_MANIFEST_DIR"), "/src/oat.l")])
+        .args(["highlight", "--backend", "lib", "--format", "html", input])
+        .arg("--output")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let page = std::fs::read_to_string(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert_eq!(html_text(&page), std::fs::read_to_string(input).unwrap());
+
+    let result = Command::new(env!("CARGO_BIN_EXE_lex4oat"))
+        .args(["--spec", concat!(env!("CARGO_MANIFEST_DIR"), "/src/oat.l")])
+        .args(["highlight", input])
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert!(result.status.success());
+    assert_eq!(
+        String::from_utf8(result.stdout).unwrap(),
+        std::fs::read_to_string(input).unwrap()
+    );
+}
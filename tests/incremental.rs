@@ -0,0 +1,211 @@
+//! Tests for re-lexing edited inputs incrementally.
+//!
+//! After any sequence of edits, the tokens and errors kept up to date by an
+//! `IncrementalLexer` must be those of lexing the edited input in full, and the reported
+//! change must cover every token that differs.
+
+use lex4oat::fuzz::alphabet_input;
+use lex4oat::incremental::{Change, Edit, IncrementalLexer};
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::token::Token;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::sync::OnceLock;
+
+/// A specification lexing strings and nested comments in exclusive modes.
+const MODES_SPEC: &str = r#"%x STRING COMMENT
+%%
+\" <STRING>"QUOTE"
+<STRING>\\[\"\\] "ESCAPE"
+<STRING>[^\"\\]+ "CHARS"
+<STRING>\" <INITIAL>"END_QUOTE"
+\(\* <+COMMENT>;
+<COMMENT>\(\* <+COMMENT>;
+<COMMENT>\*\) <-COMMENT>;
+<COMMENT>[^\(\*\)]+ ;
+<COMMENT>[\(\*\)] ;
+[a-z]+ "ID"
+[ \n]+ ;
+"#;
+
+/// A specification with nested comments, a line anchor, trailing context and tokens ending
+/// a line.
+const CONTEXT_SPEC: &str = "%nested \\/\\* \\*\\/ \"COMMENT\"
+%%
+^#[a-z]+ \"DIRECTIVE\"
+[a-z]+/\\( \"CALL\"
+[a-z]+ \"ID\"
+\\( \"LPAREN\"
+\\/ \"SLASH\"
+\\* \"TIMES\"
+# \"HASH\"
+;[a-z]*\\n \"REMARK\"
+[ \\n]+ ;
+";
+
+/// Returns the hand-made lexers under test, built once for all tests.
+fn lexers() -> &'static [CompiledLexer; 3] {
+    static LEXERS: OnceLock<[CompiledLexer; 3]> = OnceLock::new();
+    LEXERS.get_or_init(|| {
+        [
+            CompiledLexer::from_spec(include_str!("../src/oat.l")).unwrap(),
+            CompiledLexer::from_spec(MODES_SPEC).unwrap(),
+            CompiledLexer::from_spec(CONTEXT_SPEC).unwrap(),
+        ]
+    })
+}
+
+/// Moves an offset back to the nearest character boundary of an input.
+fn boundary(input: &str, offset: usize) -> usize {
+    let mut offset = offset % (input.len() + 1);
+    while !input.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// Applies edits one by one and compares every result against a full re-lex.
+fn check_edits(
+    compiled: &CompiledLexer,
+    input: String,
+    edits: &[(usize, usize, String)],
+) -> Result<(), TestCaseError> {
+    let mut lexer = IncrementalLexer::new(compiled.dfa(), input);
+    for (start, end, replacement) in edits {
+        let (start, end) = {
+            let a = boundary(lexer.input(), *start);
+            let b = boundary(lexer.input(), *end);
+            (a.min(b), a.max(b))
+        };
+        let old: Vec<Token> = lexer.tokens().to_vec();
+        let change = lexer.apply(&Edit::new(start, end, replacement));
+        let (tokens, errors) = compiled.lex_with_errors(lexer.input());
+        prop_assert_eq!(lexer.tokens(), &tokens[..], "{:?}", lexer.input());
+        prop_assert_eq!(lexer.errors(), &errors[..], "{:?}", lexer.input());
+
+        let Change {
+            old: was, new: now, ..
+        } = change;
+        prop_assert_eq!(&tokens[..now.start], &old[..was.start]);
+        prop_assert_eq!(tokens.len() - now.end, old.len() - was.end);
+        for (new, old) in tokens[now.end..].iter().zip(&old[was.end..]) {
+            prop_assert_eq!((&new.kind, &new.lexeme), (&old.kind, &old.lexeme));
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 128,
+        failure_persistence: None,
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn oat_edits_match_a_full_relex(
+        data in vec(any::<u8>(), 0..48),
+        edits in vec((any::<usize>(), any::<usize>(), vec(any::<u8>(), 0..6)), 1..6),
+    ) {
+        let edits: Vec<(usize, usize, String)> = edits
+            .into_iter()
+            .map(|(start, end, data)| (start, end, alphabet_input(&data)))
+            .collect();
+        check_edits(&lexers()[0], alphabet_input(&data), &edits)?;
+    }
+
+    #[test]
+    fn mode_edits_match_a_full_relex(
+        input in "[a-z \\n\"\\\\()*]{0,40}",
+        edits in vec((any::<usize>(), any::<usize>(), "[a-z \\n\"\\\\()*]{0,4}"), 1..6),
+    ) {
+        check_edits(&lexers()[1], input, &edits)?;
+    }
+
+    #[test]
+    fn context_edits_match_a_full_relex(
+        input in "[a-z \\n#/*(;]{0,40}",
+        edits in vec((any::<usize>(), any::<usize>(), "[a-z \\n#/*(;]{0,4}"), 1..6),
+    ) {
+        check_edits(&lexers()[2], input, &edits)?;
+    }
+}
+
+/// A program of many short statements.
+fn program() -> String {
+    (0..100).map(|i| format!("x{} = {};\n", i, i)).collect()
+}
+
+#[test]
+fn small_edits_relex_few_tokens() {
+    let compiled = &lexers()[0];
+    let input = program();
+    let offset = input.find("x50 = 50").unwrap() + "x50 = ".len();
+    let mut lexer = IncrementalLexer::new(compiled.dfa(), input);
+    let change = lexer.apply(&Edit::new(offset, offset + 2, "7"));
+    assert_eq!(
+        change,
+        Change {
+            old: 202..203,
+            new: 202..203,
+            relexed: 1,
+        }
+    );
+    assert_eq!(lexer.tokens()[202].lexeme, "7");
+    assert_eq!(lexer.tokens()[202].column, 7);
+    assert_eq!(lexer.tokens()[203].span.start, offset + 1);
+    assert_eq!(lexer.tokens(), compiled.lex(lexer.input()));
+}
+
+#[test]
+fn opening_a_string_relexes_up_to_its_new_end() {
+    let compiled = &lexers()[0];
+    let input = format!("s = ;\n{}t = \"done\";\n", program());
+    let mut lexer = IncrementalLexer::new(compiled.dfa(), input);
+    let count = lexer.tokens().len();
+
+    let change = lexer.apply(&Edit::new(4, 4, "\""));
+    assert_eq!(lexer.tokens(), compiled.lex(lexer.input()));
+    assert_eq!(lexer.tokens()[2].kind, "STRINGLITERAL");
+    assert!(lexer.tokens()[2].lexeme.ends_with("t = \""));
+    assert_eq!(lexer.tokens()[3].lexeme, "done");
+    assert_eq!(change.old, 2..count - 1);
+    assert_eq!(change.new, 2..4);
+
+    let change = lexer.apply(&Edit::new(4, 5, ""));
+    assert_eq!(lexer.tokens(), compiled.lex(lexer.input()));
+    assert_eq!(change.old, 2..4);
+    assert_eq!(change.new, 2..count - 1);
+}
+
+#[test]
+fn closing_a_comment_far_after_the_edit_is_found() {
+    let compiled = &lexers()[2];
+    let input = format!("a /* b */ {} c", "d ".repeat(50));
+    let mut lexer = IncrementalLexer::new(compiled.dfa(), input);
+    assert_eq!(lexer.tokens().len(), 53);
+
+    lexer.apply(&Edit::new(7, 9, ""));
+    let (tokens, errors) = compiled.lex_with_errors(lexer.input());
+    assert_eq!((lexer.tokens(), lexer.errors()), (&tokens[..], &errors[..]));
+    assert_eq!(errors[0].message, "Unterminated COMMENT");
+
+    let end = lexer.input().len();
+    let change = lexer.apply(&Edit::new(end, end, " */"));
+    assert_eq!(lexer.tokens(), compiled.lex(lexer.input()));
+    assert!(lexer.errors().is_empty());
+    assert_eq!(change.old, 1..1);
+    assert_eq!(change.new, 1..2);
+}
+
+#[test]
+fn tokens_after_the_edit_see_where_lines_start() {
+    let compiled = &lexers()[2];
+    let mut lexer = IncrementalLexer::new(compiled.dfa(), "a;\n#b".to_string());
+    let kinds: Vec<&str> = lexer.tokens().iter().map(|token| &token.kind[..]).collect();
+    assert_eq!(kinds, ["ID", "REMARK", "DIRECTIVE"]);
+
+    lexer.apply(&Edit::new(1, 3, "c"));
+    let kinds: Vec<&str> = lexer.tokens().iter().map(|token| &token.kind[..]).collect();
+    assert_eq!(kinds, ["ID", "HASH", "ID"]);
+}
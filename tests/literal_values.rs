@@ -0,0 +1,155 @@
+//! Tests for the values of literal tokens and hexadecimal integer literals.
+//!
+//! Both lexers must cut the same integer literals from `oat.l`, and parsing their values must
+//! report every integer that does not fit in an `i64` at the position of the literal.
+
+mod common;
+
+use common::{hand_lex, lib_lex, run_lex4oat};
+use lex4oat::literal::{parse_int, parse_literals};
+use lex4oat::token::{LexError, Span, Token, Value};
+
+/// Returns the kinds, lexemes and values of tokens.
+fn values(tokens: Vec<Token>) -> Vec<(String, String, Option<Value>)> {
+    tokens
+        .into_iter()
+        .map(|token| (token.kind, token.lexeme, token.value))
+        .collect()
+}
+
+#[test]
+fn integers_are_parsed() {
+    assert_eq!(parse_int("0"), Ok(0));
+    assert_eq!(parse_int("-42"), Ok(-42));
+    assert_eq!(parse_int("007"), Ok(7));
+    assert_eq!(parse_int("0x1fA"), Ok(0x1fa));
+    assert_eq!(parse_int("-0xff"), Ok(-255));
+    assert_eq!(parse_int("9223372036854775807"), Ok(i64::MAX));
+    assert_eq!(parse_int("-9223372036854775808"), Ok(i64::MIN));
+    assert_eq!(parse_int("-0x8000000000000000"), Ok(i64::MIN));
+    assert_eq!(
+        parse_int("9223372036854775808"),
+        Err("Integer literal 9223372036854775808 overflows a 64-bit integer".to_string())
+    );
+    assert_eq!(
+        parse_int("-0x8000000000000001"),
+        Err("Integer literal -0x8000000000000001 overflows a 64-bit integer".to_string())
+    );
+    assert_eq!(
+        parse_int("0x10000000000000000"),
+        Err("Integer literal 0x10000000000000000 overflows a 64-bit integer".to_string())
+    );
+    for lexeme in ["", "-", "0x", "+1", "1a"] {
+        assert_eq!(
+            parse_int(lexeme),
+            Err(format!("Integer literal {} is not a number", lexeme))
+        );
+    }
+}
+
+#[test]
+fn lexers_agree_on_hexadecimal_literals() {
+    let input = "x = 0x1fA+-0xff-0x 0xg 0X1 10x2;";
+    let tokens = values(hand_lex(input));
+    let expected = [
+        ("ID", "x"),
+        ("ASSIGN", "="),
+        ("INTLITERAL", "0x1fA"),
+        ("PLUS", "+"),
+        ("INTLITERAL", "-0xff"),
+        ("INTLITERAL", "-0"),
+        ("ID", "x"),
+        ("INTLITERAL", "0"),
+        ("ID", "xg"),
+        ("INTLITERAL", "0"),
+        ("ID", "X1"),
+        ("INTLITERAL", "10"),
+        ("ID", "x2"),
+        ("SEMICOLON", ";"),
+    ];
+    let expected: Vec<(String, String, Option<Value>)> = expected
+        .iter()
+        .map(|(kind, lexeme)| (kind.to_string(), lexeme.to_string(), None))
+        .collect();
+    assert_eq!(tokens, expected);
+    assert_eq!(values(lib_lex(input)), expected);
+}
+
+#[test]
+fn literal_tokens_get_their_values() {
+    let input = "b = true & !false; p = null; n = -0x10 + 3; s = \"a\\x21\";";
+    let mut tokens = hand_lex(input);
+    assert_eq!(parse_literals(input, &mut tokens), Vec::new());
+    let literals: Vec<(String, Value)> = tokens
+        .into_iter()
+        .filter_map(|token| Some((token.lexeme, token.value?)))
+        .collect();
+    assert_eq!(
+        literals,
+        [
+            ("true".to_string(), Value::Bool(true)),
+            ("false".to_string(), Value::Bool(false)),
+            ("null".to_string(), Value::Null),
+            ("-0x10".to_string(), Value::Int(-16)),
+            ("3".to_string(), Value::Int(3)),
+            ("\"a\\x21\"".to_string(), Value::Str("a!".to_string())),
+        ]
+    );
+}
+
+#[test]
+fn overflows_are_reported_in_input_order() {
+    let input = "f(\"\\q\", 1,\n  99999999999999999999, 0x7fffffffffffffff);";
+    for mut tokens in [hand_lex(input), lib_lex(input)] {
+        let errors = parse_literals(input, &mut tokens);
+        assert_eq!(
+            errors,
+            [
+                LexError {
+                    message: "Escape sequence '\\q' is not supported".to_string(),
+                    span: Span::new(3, 5),
+                    line: 1,
+                    column: 4,
+                },
+                LexError {
+                    message: "Integer literal 99999999999999999999 overflows a 64-bit integer"
+                        .to_string(),
+                    span: Span::new(13, 33),
+                    line: 2,
+                    column: 3,
+                },
+            ]
+        );
+        let ints: Vec<Option<Value>> = tokens
+            .into_iter()
+            .filter(|token| token.kind == "INTLITERAL")
+            .map(|token| token.value)
+            .collect();
+        assert_eq!(
+            ints,
+            [Some(Value::Int(1)), None, Some(Value::Int(i64::MAX))]
+        );
+    }
+}
+
+#[test]
+fn values_are_displayed_like_oat_literals() {
+    assert_eq!(Value::Int(-3).to_string(), "-3");
+    assert_eq!(Value::Bool(false).to_string(), "false");
+    assert_eq!(Value::Null.to_string(), "null");
+    assert_eq!(Value::Str("a\"\n".to_string()).to_string(), "\"a\\\"\\n\"");
+}
+
+#[test]
+fn overflows_make_the_tokens_subcommand_fail() {
+    let input = "x = 99999999999999999999;\n";
+    for backend in ["hand", "lib"] {
+        let (status, stderr) = run_lex4oat(None, &["tokens", "--values", "-b", backend], input);
+        assert_eq!(status, Some(7), "{}", backend);
+        assert!(stderr.contains(
+            "line 1, column 5: Integer literal 99999999999999999999 overflows a 64-bit integer"
+        ));
+        assert!(stderr.contains("1 files, 4 tokens, 1 errors"), "{}", stderr);
+    }
+    assert_eq!(run_lex4oat(None, &["tokens"], input).0, Some(0));
+}
@@ -0,0 +1,391 @@
+//! Tests for the `lsp` subcommand, driven by a scripted JSON-RPC client.
+//!
+//! Each test starts the language server as a child process, talks to it over its standard
+//! input and output, and checks the semantic tokens and diagnostics it answers with. The
+//! diagnostics kept up to date as a document is edited must be those of checking it in full.
+
+mod common;
+
+use common::compiled;
+use lex4oat::category::Category;
+use lex4oat::channel::{Channel, UNKNOWN_KIND, WHITESPACE_KIND};
+use lex4oat::incremental::Edit;
+use lex4oat::lsp::{Document, lexical_errors, read_message, semantic_tokens, write_message};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::io::BufReader;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// The URI of the document the tests open.
+const URI: &str = "file:///test.oat";
+
+/// A client talking to a language server child process.
+struct Client {
+    /// The server process.
+    child: Child,
+    /// The server's standard input.
+    stdin: ChildStdin,
+    /// The server's standard output.
+    stdout: BufReader<ChildStdout>,
+    /// The notifications received while waiting for responses.
+    notifications: VecDeque<Value>,
+    /// The ID of the next request.
+    next_id: u64,
+}
+
+impl Client {
+    /// Starts a language server and initializes it.
+    fn start() -> (Client, Value) {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_lex4oat"))
+            .args([
+                "--spec",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/src/oat.l"),
+                "lsp",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut client = Client {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            notifications: VecDeque::new(),
+            next_id: 1,
+        };
+        let capabilities = client.request("initialize", json!({ "capabilities": {} }));
+        client.notify("initialized", json!({}));
+        (client, capabilities["result"]["capabilities"].clone())
+    }
+
+    /// Sends a raw message.
+    fn send(&mut self, message: Value) {
+        write_message(&mut self.stdin, &message).unwrap();
+    }
+
+    /// Reads the next message.
+    fn receive(&mut self) -> Value {
+        let body = read_message(&mut self.stdout)
+            .unwrap()
+            .expect("server hung up");
+        serde_json::from_str(&body).unwrap()
+    }
+
+    /// Sends a request and waits for its response, keeping the notifications received.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                return message;
+            }
+            self.notifications.push_back(message);
+        }
+    }
+
+    /// Sends a notification.
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Returns the next diagnostics published, as `(line, character, message)` triples of
+    /// the start of each.
+    fn diagnostics(&mut self) -> Vec<(u64, u64, String)> {
+        let message = match self.notifications.pop_front() {
+            Some(message) => message,
+            None => self.receive(),
+        };
+        assert_eq!(message["method"], "textDocument/publishDiagnostics");
+        assert_eq!(message["params"]["uri"], URI);
+        message["params"]["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|diagnostic| {
+                let start = &diagnostic["range"]["start"];
+                (
+                    start["line"].as_u64().unwrap(),
+                    start["character"].as_u64().unwrap(),
+                    diagnostic["message"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    /// Opens the test document.
+    fn open(&mut self, text: &str) {
+        let document = json!({ "uri": URI, "languageId": "oat", "version": 1, "text": text });
+        self.notify("textDocument/didOpen", json!({ "textDocument": document }));
+    }
+
+    /// Replaces a range of the test document.
+    fn change(&mut self, start: (u64, u64), end: (u64, u64), text: &str) {
+        let range = json!({
+            "start": { "line": start.0, "character": start.1 },
+            "end": { "line": end.0, "character": end.1 },
+        });
+        self.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "range": range, "text": text }],
+            }),
+        );
+    }
+
+    /// Requests the semantic tokens of the test document, decoded into absolute
+    /// `(line, character, length, type)` tuples.
+    fn semantic_tokens(&mut self, legend: &[String]) -> Vec<(u64, u64, u64, String)> {
+        let params = json!({ "textDocument": { "uri": URI } });
+        let response = self.request("textDocument/semanticTokens/full", params);
+        let data: Vec<u64> = response["result"]["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_u64().unwrap())
+            .collect();
+        let (mut line, mut character) = (0, 0);
+        data.chunks(5)
+            .map(|chunk| {
+                if chunk[0] > 0 {
+                    character = 0;
+                }
+                line += chunk[0];
+                character += chunk[1];
+                (line, character, chunk[2], legend[chunk[3] as usize].clone())
+            })
+            .collect()
+    }
+
+    /// Shuts the server down, makes it exit, and returns its exit status.
+    fn shut_down(mut self) -> Option<i32> {
+        let response = self.request("shutdown", Value::Null);
+        assert_eq!(response["result"], Value::Null);
+        self.exit()
+    }
+
+    /// Makes the server exit and returns its exit status.
+    fn exit(mut self) -> Option<i32> {
+        self.notify("exit", Value::Null);
+        self.child.wait().unwrap().code()
+    }
+}
+
+/// Returns the semantic token types of the legend.
+fn legend(capabilities: &Value) -> Vec<String> {
+    capabilities["semanticTokensProvider"]["legend"]["tokenTypes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|name| name.as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn semantic_tokens_follow_token_kinds() {
+    let (mut client, capabilities) = Client::start();
+    assert_eq!(capabilities["textDocumentSync"]["change"], 2);
+    let legend = legend(&capabilities);
+
+    client.open("int x = 0x1f; /// doc\nstring s = \"hi\";");
+    assert_eq!(client.diagnostics(), []);
+    let expected = [
+        (0, 0, 3, "type"),
+        (0, 4, 1, "variable"),
+        (0, 6, 1, "operator"),
+        (0, 8, 4, "number"),
+        (0, 12, 1, "operator"),
+        (0, 14, 7, "comment"),
+        (1, 0, 6, "type"),
+        (1, 7, 1, "variable"),
+        (1, 9, 1, "operator"),
+        (1, 11, 4, "string"),
+        (1, 15, 1, "operator"),
+    ];
+    let expected: Vec<(u64, u64, u64, String)> = expected
+        .iter()
+        .map(|&(line, character, length, kind)| (line, character, length, kind.to_string()))
+        .collect();
+    assert_eq!(client.semantic_tokens(&legend), expected);
+    assert_eq!(client.shut_down(), Some(0));
+}
+
+#[test]
+fn multiline_tokens_are_split_and_counted_in_utf16() {
+    let (mut client, capabilities) = Client::start();
+    let legend = legend(&capabilities);
+
+    client.open("/* a\n  b */ \u{1f600} if");
+    assert_eq!(
+        client.diagnostics(),
+        [(1, 7, "Unexpected character \"\u{1f600}\"".to_string())]
+    );
+    let tokens = client.semantic_tokens(&legend);
+    let expected = [
+        (0, 0, 4, "comment"),
+        (1, 0, 6, "comment"),
+        (1, 10, 2, "keyword"),
+    ];
+    let expected: Vec<(u64, u64, u64, String)> = expected
+        .iter()
+        .map(|&(line, character, length, kind)| (line, character, length, kind.to_string()))
+        .collect();
+    assert_eq!(tokens, expected);
+    assert_eq!(client.shut_down(), Some(0));
+}
+
+#[test]
+fn lexical_errors_are_published_as_diagnostics() {
+    let (mut client, _) = Client::start();
+
+    client.open("x = 1 # $;\ny = \"abc;\n/* open");
+    assert_eq!(
+        client.diagnostics(),
+        [
+            (0, 6, "Unexpected character \"#\"".to_string()),
+            (0, 8, "Unexpected character \"$\"".to_string()),
+            (1, 4, "Unterminated STRINGLITERAL".to_string()),
+            (2, 0, "Unterminated COMMENT".to_string()),
+        ]
+    );
+
+    client.change((2, 7), (2, 7), " */ \"");
+    assert_eq!(
+        client.diagnostics(),
+        [
+            (0, 6, "Unexpected character \"#\"".to_string()),
+            (0, 8, "Unexpected character \"$\"".to_string()),
+        ]
+    );
+
+    client.change((0, 5), (0, 9), "");
+    assert_eq!(client.diagnostics(), []);
+
+    client.notify(
+        "textDocument/didClose",
+        json!({ "textDocument": { "uri": URI } }),
+    );
+    assert_eq!(client.diagnostics(), []);
+    assert_eq!(client.shut_down(), Some(0));
+}
+
+#[test]
+fn edits_update_the_semantic_tokens() {
+    let (mut client, capabilities) = Client::start();
+    let legend = legend(&capabilities);
+
+    client.open("x = 1;\ny = 2;\n");
+    client.diagnostics();
+    client.change((0, 4), (0, 5), "\"one\"");
+    client.diagnostics();
+    client.change((1, 0), (1, 1), "while");
+    client.diagnostics();
+
+    let tokens = client.semantic_tokens(&legend);
+    let kinds: Vec<(u64, u64, &str)> = tokens
+        .iter()
+        .map(|(line, character, _, kind)| (*line, *character, kind.as_str()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (0, 0, "variable"),
+            (0, 2, "operator"),
+            (0, 4, "string"),
+            (0, 9, "operator"),
+            (1, 0, "keyword"),
+            (1, 6, "operator"),
+            (1, 8, "number"),
+            (1, 9, "operator"),
+        ]
+    );
+    assert_eq!(client.shut_down(), Some(0));
+}
+
+#[test]
+fn protocol_errors_are_answered() {
+    let (mut client, _) = Client::start();
+
+    let response = client.request("textDocument/hover", json!({}));
+    assert_eq!(response["error"]["code"], -32601);
+
+    let params = json!({ "textDocument": { "uri": "file:///closed.oat" } });
+    let response = client.request("textDocument/semanticTokens/full", params);
+    assert_eq!(response["error"]["code"], -32602);
+
+    write_message(&mut client.stdin, &json!("ignored")).unwrap();
+    assert_eq!(client.receive()["error"]["code"], -32600);
+
+    let body = "{\"jsonrpc\": ";
+    let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    std::io::Write::write_all(&mut client.stdin, framed.as_bytes()).unwrap();
+    let response = client.receive();
+    assert_eq!(response["error"]["code"], -32700);
+    assert_eq!(response["id"], Value::Null);
+
+    assert_eq!(client.exit(), Some(1));
+}
+
+#[test]
+fn whitespace_and_unmatched_text_have_no_category() {
+    assert_eq!(Category::of_kind("COMMENT"), Some(Category::Comment));
+    assert_eq!(Category::of_kind("DOC_COMMENT"), Some(Category::Comment));
+    assert_eq!(Category::of_kind(WHITESPACE_KIND), None);
+    assert_eq!(Category::of_kind(UNKNOWN_KIND), None);
+
+    let input = "x # y /* c */\n  $";
+    let tokens = compiled().lex(input);
+    let lossless = Channel::Lossless.select(input, tokens.clone());
+    assert_eq!(
+        semantic_tokens(input, &lossless),
+        semantic_tokens(input, &tokens)
+    );
+}
+
+/// Moves an offset back to the nearest character boundary of an input.
+fn boundary(input: &str, offset: usize) -> usize {
+    let mut offset = offset % (input.len() + 1);
+    while !input.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 256,
+        failure_persistence: None,
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn edited_documents_have_the_diagnostics_of_a_full_check(
+        input in "[a-z0-9 \\n\"\\\\/*#$é;]{0,40}",
+        edits in vec((any::<usize>(), any::<usize>(), "[a-z \\n\"\\\\/*#$é]{0,4}"), 1..6),
+    ) {
+        let dfa = compiled().dfa();
+        let mut document = Document::new(dfa, input);
+        for (start, end, replacement) in &edits {
+            let input = document.lexer().input();
+            let (start, end) = {
+                let a = boundary(input, *start);
+                let b = boundary(input, *end);
+                (a.min(b), a.max(b))
+            };
+            document.apply(&Edit::new(start, end, replacement));
+            let input = document.lexer().input();
+            let (tokens, errors) = compiled().lex_with_errors(input);
+            prop_assert_eq!(
+                document.diagnostics(),
+                lexical_errors(dfa, input, &tokens, &errors),
+                "{:?}",
+                input
+            );
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Tests for nested comments declared with `%nested OPEN CLOSE "NAME"`.
+//!
+//! The opener is matched like a rule, and lexing then counts openers and closers until they
+//! balance, so a comment holding comments must be one token, and a comment that is never
+//! closed must be reported at its opener. `tests/features.rs` checks that every backend and
+//! both DFA builders agree on them.
+
+mod common;
+
+use common::specs::NESTED_COMMENTS as SPEC;
+use common::{OAT_SPEC, kinds, owned, run_lex4oat};
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::nfa::{NestedComment, parse_spec};
+use lex4oat::spec::check_spec;
+use lex4oat::token::{LexError, Span};
+
+#[test]
+fn nested_comments_are_one_token() {
+    let lexer = CompiledLexer::from_spec(SPEC).unwrap();
+    let tokens = kinds(lexer.lex("a /* x /* y */ z */ b / c * d /*/ */ /*/**/*/"));
+    let expected = [
+        ("ID", "a"),
+        ("COMMENT", "/* x /* y */ z */"),
+        ("ID", "b"),
+        ("SLASH", "/"),
+        ("ID", "c"),
+        ("TIMES", "*"),
+        ("ID", "d"),
+        ("COMMENT", "/*/ */"),
+        ("COMMENT", "/*/**/*/"),
+    ];
+    assert_eq!(tokens, owned(&expected));
+}
+
+#[test]
+fn oat_comments_can_be_made_nested() {
+    let oat = OAT_SPEC;
+    let comment_rule = oat
+        .lines()
+        .find(|line| line.ends_with("\"COMMENT\""))
+        .unwrap();
+    let nested = format!(
+        "%nested \\/\\* \\*\\/ \"COMMENT\"\n{}",
+        oat.replace(comment_rule, "")
+    );
+    let tokens = kinds(
+        CompiledLexer::from_spec(&nested)
+            .unwrap()
+            .lex("x = 1; /* if (a /* b */) */ y"),
+    );
+    let expected = [
+        ("ID", "x"),
+        ("ASSIGN", "="),
+        ("INTLITERAL", "1"),
+        ("SEMICOLON", ";"),
+        ("COMMENT", "/* if (a /* b */) */"),
+        ("ID", "y"),
+    ];
+    assert_eq!(tokens, owned(&expected));
+}
+
+#[test]
+fn unterminated_comments_report_their_opening_line() {
+    let lexer = CompiledLexer::from_spec(SPEC).unwrap();
+    let (tokens, errors) = lexer.lex_with_errors("a\n  /* x /* y */\nz");
+    assert_eq!(kinds(tokens), owned(&[("ID", "a")]));
+    assert_eq!(
+        errors,
+        [LexError {
+            message: "Unterminated COMMENT".to_string(),
+            span: Span::new(4, 6),
+            line: 2,
+            column: 3,
+        }]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "line 2, column 3: Unterminated COMMENT"
+    );
+}
+
+#[test]
+fn skipped_nested_comments_produce_no_tokens() {
+    let spec = "%nested \\(\\* \\*\\) ;\n%%\n[a-z]+ \"ID\"\n\\( \"LPAREN\"\n";
+    let lexer = CompiledLexer::from_spec(spec).unwrap();
+    let tokens = kinds(lexer.lex("a (* (* b *) c *) (d"));
+    assert_eq!(tokens, owned(&[("ID", "a"), ("LPAREN", "("), ("ID", "d")]));
+
+    let (_, errors) = lexer.lex_with_errors("a (* b");
+    assert_eq!(errors[0].message, "Unterminated comment");
+}
+
+#[test]
+fn nested_comment_declarations_are_checked() {
+    assert_eq!(
+        parse_spec(SPEC).nested_comments,
+        [NestedComment {
+            open: "\\/\\*".to_string(),
+            close: "\\*\\/".to_string(),
+            name: "COMMENT".to_string(),
+        }]
+    );
+
+    let errors = check_spec("%nested \\/\\* \"C\"\n%nested a/b c \"D\"\n%%\na \"A\"\n");
+    let lines: Vec<(usize, &str)> = errors
+        .iter()
+        .map(|err| (err.line, err.message.as_str()))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (
+                1,
+                "Nested comment must be declared as %nested OPEN CLOSE \"NAME\" (hand-made lexer)"
+            ),
+            (
+                2,
+                "Nested comment delimiter a/b must be a plain regex (hand-made lexer)"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn unterminated_comments_make_the_tokens_subcommand_fail() {
+    for backend in ["hand", "nfa", "lazy"] {
+        let args = ["tokens", "-b", backend];
+        let (status, stderr) = run_lex4oat(Some(SPEC), &args, "a\n  /* x /* y */\nz");
+        assert_eq!(status, Some(7), "{}", backend);
+        assert!(stderr.contains("line 2, column 3: Unterminated COMMENT"));
+        assert!(stderr.contains("1 files, 1 tokens, 1 errors"), "{}", stderr);
+    }
+    assert_eq!(run_lex4oat(Some(SPEC), &["tokens"], "a /* b */").0, Some(0));
+}
+
+#[test]
+fn unterminated_comments_are_counted_by_compare() {
+    let args = ["compare", "--reference", "nfa", "--candidate", "hand"];
+    let (status, stderr) = run_lex4oat(Some(SPEC), &args, "a\n  /* x /* y */\nz");
+    assert_eq!(status, Some(7));
+    assert!(stderr.contains("line 2, column 3: Unterminated COMMENT (NFA lexer)"));
+    assert!(stderr.contains("line 2, column 3: Unterminated COMMENT (hand-made lexer)"));
+    assert!(stderr.contains("1 files, 1 tokens, 2 errors"), "{}", stderr);
+}
@@ -0,0 +1,109 @@
+//! Tests for shrinking inputs with delta debugging.
+//!
+//! The reduced input must still be interesting and 1-minimal: deleting any single character
+//! loses the behaviour. The passes must delete whole lines first, then whole tokens, and only
+//! then single characters.
+
+use lex4oat::reduce::reduce;
+use proptest::prelude::*;
+
+/// Checks whether deleting any single character of an input makes it uninteresting.
+fn is_one_minimal(input: &str, interesting: impl Fn(&str) -> bool) -> bool {
+    input.char_indices().all(|(offset, c)| {
+        let candidate = format!("{}{}", &input[..offset], &input[offset + c.len_utf8()..]);
+        !interesting(&candidate)
+    })
+}
+
+/// Checks whether a candidate is the concatenation of some of the units, in order.
+fn is_made_of(candidate: &str, units: &[&str]) -> bool {
+    if candidate.is_empty() {
+        return true;
+    }
+    units.iter().enumerate().any(|(index, unit)| {
+        candidate
+            .strip_prefix(unit)
+            .is_some_and(|rest| !unit.is_empty() && is_made_of(rest, &units[index + 1..]))
+    })
+}
+
+#[test]
+fn reduction_keeps_the_behaviour_and_is_one_minimal() {
+    let input = "int x = 1;\nstring s = \"a # b\";\nvar y = x # 2;\n";
+    let interesting = |candidate: &str| candidate.matches('#').count() == 2;
+    let reduced = reduce(input, interesting);
+    assert_eq!(reduced, "##");
+    assert!(is_one_minimal(&reduced, interesting));
+
+    let interesting = |candidate: &str| candidate.contains("x #");
+    let reduced = reduce(input, interesting);
+    assert_eq!(reduced, "x #");
+}
+
+#[test]
+fn passes_go_from_lines_to_tokens_to_chars() {
+    let input = "int a;\nkeep me = 1;\nint b;\n";
+    let mut candidates: Vec<String> = Vec::new();
+    let reduced = reduce(input, |candidate| {
+        candidates.push(candidate.to_string());
+        candidate.contains("me")
+    });
+    assert_eq!(reduced, "me");
+
+    // Lines are deleted until only the interesting line is left.
+    let lines: Vec<&str> = input.split_inclusive('\n').collect();
+    let tokens_start = candidates
+        .iter()
+        .position(|candidate| !is_made_of(candidate, &lines))
+        .unwrap();
+    assert!(tokens_start > 0);
+
+    // Then whole tokens of that line, words and whitespace runs and punctuation.
+    let tokens = ["keep", " ", "me", " ", "=", " ", "1", ";", "\n"];
+    let chars_start = tokens_start
+        + candidates[tokens_start..]
+            .iter()
+            .position(|candidate| !is_made_of(candidate, &tokens))
+            .unwrap();
+    assert!(chars_start > tokens_start);
+
+    // Only then single characters, of what the token pass left.
+    for candidate in &candidates[chars_start..] {
+        assert!(is_made_of(candidate, &["m", "e"]), "{:?}", candidate);
+    }
+}
+
+#[test]
+fn tokens_are_words_whitespace_runs_and_single_punctuation() {
+    // A single line leaves nothing to the line pass, so the first candidate is the first
+    // half of the tokens: `ab_1`, `  `, `-`, `>` and `x`.
+    let input = "ab_1  ->x";
+    let mut candidates: Vec<String> = Vec::new();
+    let reduced = reduce(input, |candidate| {
+        candidates.push(candidate.to_string());
+        candidate.contains('x')
+    });
+    assert_eq!(reduced, "x");
+    assert_eq!(candidates[0], "ab_1  -");
+    assert_eq!(candidates[1], ">x");
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 64,
+        failure_persistence: None,
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn random_reductions_are_one_minimal(input in "[ab \\n;]{0,40}") {
+        let interesting = |candidate: &str| {
+            candidate.matches('a').count() >= 2 && candidate.contains('b')
+        };
+        prop_assume!(interesting(&input));
+        let reduced = reduce(&input, interesting);
+        prop_assert!(interesting(&reduced));
+        prop_assert_eq!(reduced.len(), 3);
+        prop_assert!(is_one_minimal(&reduced, interesting));
+    }
+}
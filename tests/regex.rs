@@ -0,0 +1,189 @@
+//! Tests for the regex construction of the hand-made lexers.
+//!
+//! Each regex is made the only rule of a specification, and the hand-made DFA and NFA lexers
+//! must cut every short input over its alphabet into the same tokens as `lrlex`. Regexes the
+//! hand-made lexers cannot build are reported by `check_spec` and by the lexers themselves.
+
+mod common;
+
+use common::{run_lex4oat, run_lex4oat_on_spec};
+use lex4oat::diff::{Side, Stop, TokenDiff};
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::liblex4oat::{LibLex4Oat, lexer_def_from_spec};
+use lex4oat::spec::check_spec;
+
+/// Returns every string over an alphabet, up to a length.
+fn strings(alphabet: &[char], max_len: usize) -> Vec<String> {
+    let mut all = vec![String::new()];
+    let mut last = vec![String::new()];
+    for _ in 0..max_len {
+        last = last
+            .iter()
+            .flat_map(|prefix| alphabet.iter().map(move |&c| format!("{}{}", prefix, c)))
+            .collect();
+        all.extend(last.iter().cloned());
+    }
+    all
+}
+
+/// Checks that the hand-made lexers and `lrlex` agree on a rule over all short inputs.
+///
+/// A rule matching any single character follows the rule, so that `lrlex` rarely stops, and
+/// it is restarted where it does, so every input is compared to its end.
+///
+/// # Arguments
+///
+/// * `regex` - The regex of the only rule, named `R`.
+/// * `alphabet` - The characters the inputs are made of.
+fn check_rule(regex: &str, alphabet: &[char]) {
+    let spec = format!("%%\n{} \"R\"\n. \"OTHER\"\n", regex);
+    let lexerdef = lexer_def_from_spec(&spec).unwrap();
+    let compiled = CompiledLexer::from_spec(&spec).unwrap();
+    for input in strings(alphabet, 6) {
+        let mut lib = LibLex4Oat::new(input.clone());
+        lib.lex_resuming_with(&lexerdef);
+        for (label, tokens) in [
+            ("DFA", compiled.lex(&input)),
+            ("NFA", compiled.nfa().lex(&input)),
+        ] {
+            let stop = lib.error_offset().map(|offset| Stop {
+                offset,
+                side: Side::Reference,
+            });
+            let diff = TokenDiff::new(lib.tokens(), &tokens)
+                .with_stop(stop)
+                .with_labels("lrlex", label);
+            assert!(
+                diff.is_empty(),
+                "{} on {:?}:\n{}",
+                regex,
+                input,
+                diff.render(&input, lib.tokens(), &tokens)
+            );
+        }
+    }
+}
+
+/// Returns the lexemes the hand-made lexer cuts from an input with a single rule.
+fn lexemes(regex: &str, input: &str) -> Vec<String> {
+    CompiledLexer::from_spec(&format!("%%\n{} \"R\"\n", regex))
+        .unwrap()
+        .lex(input)
+        .into_iter()
+        .map(|token| token.lexeme)
+        .collect()
+}
+
+#[test]
+fn repetition_only_loops_over_its_atom() {
+    assert_eq!(lexemes("ab*", "abbab"), ["abb", "ab"]);
+    assert_eq!(lexemes("ab+", "abbaab"), ["abb", "ab"]);
+    check_rule("ab*", &['a', 'b']);
+    check_rule("ab+c", &['a', 'b', 'c']);
+    check_rule("a?b", &['a', 'b']);
+}
+
+#[test]
+fn skipped_groups_never_enter_a_loop_halfway() {
+    assert_eq!(
+        lexemes("(a*b)?c", "c abc bc aac ac"),
+        ["c", "abc", "bc", "c", "c"]
+    );
+    assert_eq!(lexemes("(ab*)?c", "bc abbc bbc"), ["c", "abbc", "c"]);
+    check_rule("(a*b)?c", &['a', 'b', 'c']);
+    check_rule("(ab*)?c", &['a', 'b', 'c']);
+    check_rule("(a+b)?c", &['a', 'b', 'c']);
+}
+
+#[test]
+fn escapes_in_sets_stand_for_control_characters() {
+    assert_eq!(lexemes("[\\n\\t]+", "n\n\t\ntn\t"), ["\n\t\n", "\t"]);
+    check_rule("[\\n\\t]+", &['\n', '\t', 'n', 't']);
+    check_rule("[^\\r]", &['\r', 'r', 'a']);
+}
+
+#[test]
+fn tokens_keep_the_whitespace_they_match() {
+    assert_eq!(lexemes("a\\s+", "a  \nb a\t"), ["a  \n", "a\t"]);
+    check_rule("\\s*a\\s*", &['a', ' ', '\n']);
+}
+
+#[test]
+fn alternatives_and_groups_are_combined() {
+    check_rule("a|b*", &['a', 'b']);
+    check_rule("(ab)+c", &['a', 'b', 'c']);
+    check_rule("x(y|z)*", &['x', 'y', 'z']);
+    check_rule("(a|bc)?d", &['a', 'b', 'c', 'd']);
+}
+
+#[test]
+fn negated_sets_cover_control_characters() {
+    assert_eq!(
+        lexemes("[^a]+", "\n\t\u{1}\u{7f}a b"),
+        ["\n\t\u{1}\u{7f}", " b"]
+    );
+    check_rule("[^a]", &['a', '\n', '\t', '\u{1}', '\u{7f}']);
+    check_rule("[^\\n]+", &['a', '\n', '\r']);
+}
+
+#[test]
+fn dots_match_any_character() {
+    assert_eq!(lexemes("a.", "aba\na."), ["ab", "a\n", "a."]);
+    check_rule("a.b", &['a', 'b', '.', '\n']);
+    check_rule("\\\"([^\\\"\\\\]|\\\\.)*\\\"", &['"', '\\', 'a', '\n']);
+}
+
+#[test]
+fn sets_ending_in_a_dash_match_the_dash() {
+    assert_eq!(lexemes("[a-]+", "a-a b-"), ["a-a", "-"]);
+    check_rule("[a-]+", &['a', '-', 'b']);
+    check_rule("[a-c-]", &['a', 'b', '-', 'd']);
+}
+
+#[test]
+fn unfinished_escapes_are_errors() {
+    for (regex, message) in [
+        ("a\\", "Regex ends with an unfinished escape"),
+        ("[a\\]", "Character class ends with an unfinished escape"),
+        ("(a\\)", "Group ends with an unfinished escape"),
+    ] {
+        let spec = format!("%%\n{} \"X\"\n", regex);
+        let errors = check_spec(&spec);
+        assert!(
+            errors
+                .iter()
+                .any(|err| err.line == 2 && err.message == format!("{} (hand-made lexer)", message)),
+            "{}: {:?}",
+            regex,
+            errors
+        );
+        assert!(CompiledLexer::from_spec(&spec).is_err(), "{}", regex);
+    }
+    // An escaped backslash finishes its escape.
+    assert_eq!(lexemes("[a\\\\]+", "a\\a"), ["a\\a"]);
+    assert_eq!(check_spec("%%\n[a\\\\] \"X\"\n"), Vec::new());
+}
+
+#[test]
+fn subcommands_refuse_specifications_check_spec_rejects() {
+    for spec in ["%%\na\\ \"X\"\n", "%%\n[a\\] \"X\"\n", "%%\n[a\\\\ \"X\"\n"] {
+        let runs = [
+            run_lex4oat(Some(spec), &["tokens"], "a"),
+            run_lex4oat(Some(spec), &["compare"], "a"),
+            run_lex4oat(Some(spec), &["highlight"], "a"),
+            run_lex4oat_on_spec(spec, &["nfa"]),
+            run_lex4oat_on_spec(spec, &["dfa"]),
+            run_lex4oat_on_spec(spec, &["samples"]),
+            run_lex4oat_on_spec(spec, &["lsp"]),
+        ];
+        for (status, stderr) in runs {
+            assert_eq!(status, Some(4), "{:?}: {}", spec, stderr);
+            assert!(
+                stderr.contains("(hand-made lexer)"),
+                "{:?}: {}",
+                spec,
+                stderr
+            );
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Property tests for the lossless token stream.
+//!
+//! Whatever the input and whichever lexer produced the tokens, the main tokens and the trivia
+//! between them must be contiguous, and their texts must concatenate to the input byte for
+//! byte.
+
+mod common;
+
+use common::{compiled, lazy, lib_lex};
+use lex4oat::channel::{Channel, Channels, UNKNOWN_KIND, WHITESPACE_KIND, is_trivia};
+use lex4oat::fuzz::{alphabet_input, ascii_input};
+use lex4oat::token::Token;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+/// Lexes an input with every backend.
+fn lex_all(input: &str) -> Vec<(&'static str, Vec<Token>)> {
+    vec![
+        ("lib", lib_lex(input)),
+        ("hand", compiled().lex(input)),
+        ("nfa", compiled().nfa().lex(input)),
+        ("lazy", lazy().lex(input)),
+    ]
+}
+
+/// Checks that the lossless stream of every backend rebuilds an input.
+fn check_round_trip(input: &str) -> Result<(), TestCaseError> {
+    for (backend, tokens) in lex_all(input) {
+        let main: Vec<Token> = tokens
+            .iter()
+            .filter(|token| !is_trivia(&token.kind))
+            .cloned()
+            .collect();
+        let channels = Channels::split(input, tokens.clone());
+        prop_assert_eq!(channels.source(), input, "{}", backend);
+        prop_assert_eq!(channels.main().cloned().collect::<Vec<Token>>(), main);
+
+        let lossless = Channel::Lossless.select(input, tokens);
+        let mut end = 0;
+        for token in &lossless {
+            prop_assert_eq!(token.span.start, end, "{} at {:?}", backend, token);
+            prop_assert_eq!(&input[token.span.start..token.span.end], &token.lexeme);
+            prop_assert!(!token.lexeme.is_empty(), "{} at {:?}", backend, token);
+            end = token.span.end;
+        }
+        prop_assert_eq!(end, input.len(), "{}", backend);
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 128,
+        failure_persistence: None,
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn alphabet_inputs_round_trip(data in vec(any::<u8>(), 0..64)) {
+        check_round_trip(&alphabet_input(&data))?;
+    }
+
+    #[test]
+    fn ascii_inputs_round_trip(data in vec(any::<u8>(), 0..64)) {
+        check_round_trip(&ascii_input(&data))?;
+    }
+
+    #[test]
+    fn unicode_inputs_round_trip(input in "\\PC{0,32}|[ \\t\\r\\n\\u{a0}\\u{2028}a-z/*\"]{0,32}") {
+        check_round_trip(&input)?;
+    }
+}
+
+#[test]
+fn unmatched_text_is_kept_as_unknown_trivia() {
+    let input = "x # y\u{a0}\u{e9}\n";
+    let kinds: Vec<(String, String)> = Channel::Lossless
+        .select(input, compiled().lex(input))
+        .into_iter()
+        .map(|token| (token.kind, token.lexeme))
+        .collect();
+    let expected = [
+        ("ID", "x"),
+        (WHITESPACE_KIND, " "),
+        (UNKNOWN_KIND, "#"),
+        (WHITESPACE_KIND, " "),
+        ("ID", "y"),
+        (WHITESPACE_KIND, "\u{a0}"),
+        (UNKNOWN_KIND, "\u{e9}"),
+        (WHITESPACE_KIND, "\n"),
+    ];
+    let expected: Vec<(String, String)> = expected
+        .iter()
+        .map(|(kind, lexeme)| (kind.to_string(), lexeme.to_string()))
+        .collect();
+    assert_eq!(kinds, expected);
+}
+
+#[test]
+fn test_files_round_trip() {
+    let test_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test");
+    for entry in std::fs::read_dir(test_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "oat") {
+            let input = std::fs::read_to_string(&path).unwrap();
+            check_round_trip(&input).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Tests for the set operators `&` (intersection), `~` (difference) and leading `~`
+//! (complement) in specification rules declaring `%set-operators`, and for `&` and `~` as
+//! plain characters in specifications that do not.
+//!
+//! Rules using them are determinized on their own and combined with the product construction,
+//! whose automata must follow set semantics. `tests/features.rs` checks that every backend
+//! and both DFA builders agree on them.
+
+mod common;
+
+use common::specs::{SET_OPERATIONS as SPEC, SET_OPERATIONS_INPUT as INPUT};
+use common::{OAT_SPEC, assert_builders_agree, kinds, owned, run_lex4oat};
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::nfa::SetExpr;
+use lex4oat::spec::{check_spec, hand_made_only_feature, uses_set_operations};
+
+#[test]
+fn set_operators_are_lexed() {
+    let tokens = kinds(CompiledLexer::from_spec(SPEC).unwrap().lex(INPUT));
+    let expected = [
+        ("IF", "if"),
+        ("ID", "ifx"),
+        ("ID", "iff"),
+        ("ID", "abc"),
+        ("NUM", "120"),
+        ("ZERO", "0"),
+        ("ZERO", "0"),
+        ("NUM", "7"),
+        ("ZERO", "0"),
+        ("COMMENT", "/* a */"),
+        ("ID", "b"),
+        ("COMMENT", "/**/"),
+    ];
+    assert_eq!(tokens, owned(&expected));
+}
+
+#[test]
+fn product_dfas_follow_set_semantics() {
+    let letters = SetExpr::Regex("[a-c]+".to_string()).to_dfa("L").unwrap();
+    let short = SetExpr::Regex("a|b|ab".to_string()).to_dfa("S").unwrap();
+
+    let both = letters.intersection(&short, "X");
+    let only_letters = letters.difference(&short, "X");
+    let not_letters = letters.complement("X");
+    for input in ["a", "ab", "ba", "abc", "", "x", "ax"] {
+        let in_letters = letters.classify(input).is_some();
+        let in_short = short.classify(input).is_some();
+        let expect = |accepted: bool| accepted.then_some("X");
+        assert_eq!(both.classify(input), expect(in_letters && in_short));
+        assert_eq!(
+            only_letters.classify(input),
+            expect(in_letters && !in_short)
+        );
+        assert_eq!(
+            not_letters.classify(input),
+            expect(!in_letters),
+            "{:?}",
+            input
+        );
+    }
+}
+
+#[test]
+fn set_expressions_are_parsed_left_to_right() {
+    let regex = |regex: &str| Box::new(SetExpr::Regex(regex.to_string()));
+    assert_eq!(SetExpr::parse("ab(c|d)"), None);
+    assert_eq!(SetExpr::parse("\\&[~]"), None);
+    assert_eq!(
+        SetExpr::parse("~a&b~c"),
+        Some(SetExpr::Difference(
+            Box::new(SetExpr::Intersection(
+                Box::new(SetExpr::Complement(regex("a"))),
+                regex("b")
+            )),
+            regex("c")
+        ))
+    );
+}
+
+#[test]
+fn set_operator_operands_are_checked() {
+    assert!(uses_set_operations(SPEC));
+    assert!(!uses_set_operations(OAT_SPEC));
+
+    let errors = check_spec("%set-operators\n%%\na&&b \"X\"\na~ \"Y\"\n");
+    let lines: Vec<(usize, &str)> = errors
+        .iter()
+        .map(|err| (err.line, err.message.as_str()))
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (3, "Set operator '&' has no left operand (hand-made lexer)"),
+            (4, "Set operator '~' has no right operand (hand-made lexer)"),
+        ]
+    );
+}
+
+#[test]
+fn undeclared_set_operators_match_themselves_as_in_lrlex() {
+    let spec = "%%\na&&b \"AND\"\n~ \"TILDE\"\n[a-z]+~ \"ID\"\n[ ]+ ;\n";
+    assert!(!uses_set_operations(spec));
+    assert_eq!(hand_made_only_feature(spec), None);
+    assert_eq!(check_spec(spec), Vec::new());
+
+    let input = "a&&b ~ ab~ ~";
+    let tokens = kinds(CompiledLexer::from_spec(spec).unwrap().lex(input));
+    let expected = [
+        ("AND", "a&&b"),
+        ("TILDE", "~"),
+        ("ID", "ab~"),
+        ("TILDE", "~"),
+    ];
+    assert_eq!(tokens, owned(&expected));
+    assert_builders_agree(spec);
+    assert_eq!(run_lex4oat(Some(spec), &["compare"], input).0, Some(0));
+}
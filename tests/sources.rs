@@ -0,0 +1,147 @@
+//! Tests for resolving the inputs of the `tokens` and `compare` subcommands.
+//!
+//! Directories are searched recursively without following symbolic links to directories,
+//! duplicate files keep their first position, and files lexed in parallel are reported in the
+//! order they were collected, followed by a summary of all of them.
+
+mod common;
+
+use common::compiled;
+use lex4oat::sources::collect_sources;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A temporary directory removed when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates an empty temporary directory with a name no other test uses.
+    fn new() -> TempDir {
+        static DIRS: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "lex4oat-sources-{}-{}",
+            std::process::id(),
+            DIRS.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// Writes a file below the directory, creating its parent directories.
+    fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Returns the paths of files relative to a directory.
+fn relative(dir: &Path, files: Vec<PathBuf>) -> Vec<String> {
+    files
+        .iter()
+        .map(|file| file.strip_prefix(dir).unwrap().display().to_string())
+        .collect()
+}
+
+#[test]
+fn directories_are_searched_recursively_in_sorted_order() {
+    let dir = TempDir::new();
+    dir.write("b.oat", "");
+    dir.write("a.oat", "");
+    dir.write("sub/c.oat", "");
+    dir.write("notes.txt", "");
+
+    let files = collect_sources(std::slice::from_ref(&dir.0)).unwrap();
+    assert_eq!(relative(&dir.0, files), ["a.oat", "b.oat", "sub/c.oat"]);
+}
+
+#[test]
+fn duplicate_files_keep_their_first_position() {
+    let dir = TempDir::new();
+    let a = dir.write("a.oat", "");
+    let b = dir.write("b.oat", "");
+    dir.write("sub/c.oat", "");
+    let missing = dir.0.join("missing.oat");
+
+    let pattern = dir.0.join("*.oat");
+    let inputs = [b.clone(), dir.0.clone(), pattern, missing, a];
+    let files = collect_sources(&inputs).unwrap();
+    assert_eq!(
+        relative(&dir.0, files),
+        ["b.oat", "a.oat", "sub/c.oat", "missing.oat"]
+    );
+}
+
+#[test]
+fn invalid_patterns_are_errors() {
+    let dir = TempDir::new();
+    assert!(collect_sources(&[dir.0.join("[a.oat")]).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn symbolic_links_to_directories_are_not_followed() {
+    let dir = TempDir::new();
+    let a = dir.write("a.oat", "");
+    dir.write("sub/b.oat", "");
+    std::os::unix::fs::symlink(&dir.0, dir.0.join("sub/parent")).unwrap();
+    std::os::unix::fs::symlink(&a, dir.0.join("sub/link.oat")).unwrap();
+
+    let files = collect_sources(std::slice::from_ref(&dir.0)).unwrap();
+    assert_eq!(
+        relative(&dir.0, files),
+        ["a.oat", "sub/b.oat", "sub/link.oat"]
+    );
+}
+
+#[test]
+fn files_lexed_in_parallel_are_reported_in_order_with_a_summary() {
+    let dir = TempDir::new();
+    let mut names = Vec::new();
+    let mut token_count = 0;
+    for i in 0..12 {
+        // One file holds an integer that overflows, so that one error is counted.
+        let input = if i == 5 {
+            "x 99999999999999999999 y".to_string()
+        } else {
+            "x ".repeat(i + 1)
+        };
+        token_count += compiled().lex(&input).len();
+        names.push(dir.write(&format!("f{:02}.oat", i), &input));
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_lex4oat"))
+        .args(["--spec", concat!(env!("CARGO_MANIFEST_DIR"), "/src/oat.l")])
+        .args(["tokens", "--values", "--jobs", "4"])
+        .arg(&dir.0)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(7));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let headers: Vec<&str> = stdout
+        .lines()
+        .filter_map(|line| line.strip_prefix("==> ")?.strip_suffix(" <=="))
+        .collect();
+    let expected: Vec<String> = names
+        .iter()
+        .map(|name| name.display().to_string())
+        .collect();
+    assert_eq!(headers, expected);
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!("12 files, {} tokens, 1 errors", token_count)),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,145 @@
+//! Tests for start conditions (`%s`, `%x`), the `<A,B>` rule prefixes that activate rules in
+//! them, and the `<S>`, `<+S>` and `<-S>` mode changes before token names.
+//!
+//! Rules must switch modes at the right tokens, and every DFA must have a start state per
+//! mode. `tests/features.rs` checks that every backend switches modes at the same tokens as
+//! `lrlex`, and that both DFA builders agree on the start states of every mode.
+
+mod common;
+
+use common::specs::{
+    INCLUSIVE_START_CONDITION as HEX_SPEC, START_CONDITIONS as SPEC,
+    START_CONDITIONS_INPUT as INPUT,
+};
+use common::{kinds, owned};
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::nfa::{ModeChange, Rule, StartCondition, parse_spec};
+use lex4oat::spec::{check_spec, hand_made_only_feature};
+
+#[test]
+fn rules_switch_modes() {
+    let tokens = kinds(CompiledLexer::from_spec(SPEC).unwrap().lex(INPUT));
+    let expected = [
+        ("ID", "say"),
+        ("QUOTE", "\""),
+        ("CHARS", "a"),
+        ("ESCAPE", "\\\""),
+        ("CHARS", "b"),
+        ("ESCAPE", "\\t"),
+        ("CHARS", "c"),
+        ("END_QUOTE", "\""),
+        ("ID", "done"),
+        ("QUOTE", "\""),
+        ("CHARS", "end"),
+        ("END_QUOTE", "\""),
+    ];
+    assert_eq!(tokens, owned(&expected));
+}
+
+#[test]
+fn inclusive_start_conditions_keep_unprefixed_rules() {
+    let tokens = kinds(
+        CompiledLexer::from_spec(HEX_SPEC)
+            .unwrap()
+            .lex("12 0xff ab 0x1 7"),
+    );
+    let expected = [
+        ("INT", "12"),
+        ("PREFIX", "0x"),
+        ("DIGITS", "ff"),
+        ("ID", "ab"),
+        ("PREFIX", "0x"),
+        ("DIGITS", "1"),
+        ("INT", "7"),
+    ];
+    assert_eq!(tokens, owned(&expected));
+}
+
+#[test]
+fn dfas_have_one_start_state_per_mode() {
+    for (spec, names) in [
+        (SPEC, &["INITIAL", "STRING", "COMMENT"][..]),
+        (HEX_SPEC, &["INITIAL", "HEX"][..]),
+    ] {
+        let subset = CompiledLexer::from_spec(spec).unwrap();
+        let modes: Vec<&str> = subset
+            .dfa()
+            .get_modes()
+            .iter()
+            .map(|mode| mode.name.as_str())
+            .collect();
+        assert_eq!(modes, names);
+    }
+}
+
+#[test]
+fn specifications_are_parsed_into_modes_and_rules() {
+    let spec = parse_spec("%s A\n%X B C\n%%\n<A,B>a+ <+C>\"X\"\nb <-C>;\nc \"Y\"\n");
+    let condition = |name: &str, exclusive| StartCondition {
+        name: name.to_string(),
+        exclusive,
+    };
+    assert_eq!(
+        spec.start_conditions,
+        [
+            condition("A", false),
+            condition("B", true),
+            condition("C", true)
+        ]
+    );
+    assert_eq!(
+        spec.rules[0],
+        Rule {
+            regex: "a+".to_string(),
+            name: "X".to_string(),
+            start_conditions: vec!["A".to_string(), "B".to_string()],
+            target: Some((ModeChange::Push, "C".to_string())),
+        }
+    );
+    assert!(spec.rules[1].is_lexed());
+    assert_eq!(
+        spec.rules[1].target,
+        Some((ModeChange::Pop, "C".to_string()))
+    );
+    assert_eq!(
+        Rule::parse("\\<a\\> \"LT\"").start_conditions,
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn mode_changes_follow_the_lrlex_stack() {
+    let mut stack = vec![(0, 1)];
+    ModeChange::Push.apply(&mut stack, 1);
+    ModeChange::Push.apply(&mut stack, 1);
+    ModeChange::Push.apply(&mut stack, 2);
+    assert_eq!(stack, [(0, 1), (1, 2), (2, 1)]);
+    ModeChange::Pop.apply(&mut stack, 2);
+    ModeChange::Pop.apply(&mut stack, 1);
+    assert_eq!(stack, [(0, 1), (1, 1)]);
+    ModeChange::Begin.apply(&mut stack, 2);
+    assert_eq!(stack, [(2, 1)]);
+    ModeChange::Pop.apply(&mut stack, 2);
+    assert_eq!(stack, [(0, 1)]);
+}
+
+#[test]
+fn unknown_start_conditions_are_reported() {
+    assert_eq!(hand_made_only_feature(SPEC), None);
+    assert_eq!(check_spec(SPEC), Vec::new());
+    assert_eq!(check_spec(HEX_SPEC), Vec::new());
+
+    let errors = check_spec("%x S\n%%\n<T>a \"A\"\nb <U>\"B\"\n<S>c <-S>;\n");
+    let hand_made: Vec<(usize, &str)> = errors
+        .iter()
+        .filter(|err| err.message.ends_with("(hand-made lexer)"))
+        .map(|err| (err.line, err.message.as_str()))
+        .collect();
+    assert_eq!(
+        hand_made,
+        [
+            (3, "Unknown start condition 'T' (hand-made lexer)"),
+            (4, "Unknown start condition 'U' (hand-made lexer)"),
+        ]
+    );
+}
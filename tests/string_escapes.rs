@@ -0,0 +1,187 @@
+//! Tests for decoding the escape sequences of string literals.
+//!
+//! Decoding runs after lexing, so it must give the same values whichever lexer produced the
+//! tokens, and must report every invalid escape at the line and column it starts at.
+
+mod common;
+
+use common::{hand_lex, lib_lex, run_lex4oat};
+use lex4oat::escape::{EscapeError, decode_string, decode_strings};
+use lex4oat::liblex4oat::LibLex4Oat;
+use lex4oat::token::{LexError, Span, Value};
+
+/// Returns the messages and lexeme-relative ranges of escape errors.
+fn problems(errors: Vec<EscapeError>) -> Vec<(String, usize, usize)> {
+    errors
+        .into_iter()
+        .map(|err| (err.message, err.span.start, err.span.end))
+        .collect()
+}
+
+#[test]
+fn valid_escapes_are_decoded() {
+    let decoded = decode_string(r#""a\nb\tc\\d\"e\x41\x7f\u{e9}\u{1F600}\u{0}""#);
+    assert_eq!(decoded, Ok("a\nb\tc\\d\"eA\x7fé😀\0".to_string()));
+    assert_eq!(decode_string("\"\""), Ok(String::new()));
+    assert_eq!(
+        decode_string("\"plain text\""),
+        Ok("plain text".to_string())
+    );
+    assert_eq!(decode_string("unquoted\\t"), Ok("unquoted\t".to_string()));
+}
+
+#[test]
+fn invalid_escapes_are_all_reported() {
+    let errors = decode_string(r#""\q\x4g\x80\u41\u{}\u{1234567}\u{d800}\u{41""#).unwrap_err();
+    let expected = [
+        ("Escape sequence '\\q' is not supported", 1, 3),
+        ("Escape sequence '\\x4' needs two hexadecimal digits", 3, 6),
+        ("Escape sequence '\\x80' is above '\\x7f'", 7, 11),
+        (
+            "Escape sequence '\\u' needs hexadecimal digits in braces",
+            11,
+            13,
+        ),
+        (
+            "Escape sequence '\\u{}' needs one to six hexadecimal digits in braces",
+            15,
+            19,
+        ),
+        (
+            "Escape sequence '\\u{1234567}' needs one to six hexadecimal digits in braces",
+            19,
+            30,
+        ),
+        (
+            "Escape sequence '\\u{d800}' is not a Unicode scalar value",
+            30,
+            38,
+        ),
+        (
+            "Escape sequence '\\u{41' needs one to six hexadecimal digits in braces",
+            38,
+            43,
+        ),
+    ];
+    let expected: Vec<(String, usize, usize)> = expected
+        .iter()
+        .map(|(message, start, end)| (message.to_string(), *start, *end))
+        .collect();
+    assert_eq!(problems(errors), expected);
+
+    let errors = decode_string("\"ab\\\"").unwrap_err();
+    assert_eq!(
+        problems(errors),
+        [("Escape sequence '\\' is unfinished".to_string(), 3, 4)]
+    );
+}
+
+#[test]
+fn string_tokens_get_their_values() {
+    let input = "string s = \"\\x48i\\n\";\nint x = 1;";
+    let mut tokens = hand_lex(input);
+    let errors = decode_strings(input, &mut tokens);
+    assert_eq!(errors, Vec::new());
+    let values: Vec<(&str, Option<&Value>)> = tokens
+        .iter()
+        .map(|token| (token.kind.as_str(), token.value.as_ref()))
+        .filter(|(_, value)| value.is_some())
+        .collect();
+    let expected = Value::Str("Hi\n".to_string());
+    assert_eq!(values, [("STRINGLITERAL", Some(&expected))]);
+    assert!(
+        tokens
+            .iter()
+            .filter(|token| token.kind != "STRINGLITERAL")
+            .all(|token| token.value.is_none())
+    );
+}
+
+#[test]
+fn escaped_quotes_stay_inside_string_literals() {
+    let input = "f(\"say \\\"hi\\\"\", \"\\\\\", \"a\\\"b\");";
+    let expected = [
+        ("\"say \\\"hi\\\"\"", "say \"hi\""),
+        ("\"\\\\\"", "\\"),
+        ("\"a\\\"b\"", "a\"b"),
+    ];
+    for mut tokens in [hand_lex(input), lib_lex(input)] {
+        assert_eq!(decode_strings(input, &mut tokens), Vec::new());
+        let strings: Vec<(String, Option<Value>)> = tokens
+            .into_iter()
+            .filter(|token| token.kind == "STRINGLITERAL")
+            .map(|token| (token.lexeme, token.value))
+            .collect();
+        let expected: Vec<(String, Option<Value>)> = expected
+            .iter()
+            .map(|(lexeme, value)| (lexeme.to_string(), Some(Value::Str(value.to_string()))))
+            .collect();
+        assert_eq!(strings, expected);
+    }
+
+    // An escaped quote does not close a string, so this one is never closed.
+    let kinds: Vec<String> = hand_lex("\"a\\\"")
+        .into_iter()
+        .map(|token| token.kind)
+        .collect();
+    assert!(!kinds.contains(&"STRINGLITERAL".to_string()));
+}
+
+#[test]
+fn invalid_escapes_are_reported_at_their_column() {
+    let input = "f(\"ok\");\n  g(\"e\\z\n\\x\");";
+    let mut tokens = hand_lex(input);
+    let errors = decode_strings(input, &mut tokens);
+    assert_eq!(
+        errors,
+        [
+            LexError {
+                message: "Escape sequence '\\z' is not supported".to_string(),
+                span: Span::new(15, 17),
+                line: 2,
+                column: 7,
+            },
+            LexError {
+                message: "Escape sequence '\\x' needs two hexadecimal digits".to_string(),
+                span: Span::new(18, 20),
+                line: 3,
+                column: 1,
+            },
+        ]
+    );
+    let strings: Vec<Option<Value>> = tokens
+        .into_iter()
+        .filter(|token| token.kind == "STRINGLITERAL")
+        .map(|token| token.value)
+        .collect();
+    assert_eq!(strings, [Some(Value::Str("ok".to_string())), None]);
+}
+
+#[test]
+fn lexers_agree_on_decoded_strings() {
+    let input = include_str!("../test/0.oat").to_string() + "\nstring s = \"\\u{3bb} \\x3d \\\\\";";
+    let mut hand = hand_lex(&input);
+    let mut lib = LibLex4Oat::new(input.clone());
+    lib.lex();
+    let mut lib = lib.tokens().clone();
+    assert_eq!(decode_strings(&input, &mut hand), Vec::new());
+    assert_eq!(decode_strings(&input, &mut lib), Vec::new());
+    assert_eq!(hand, lib);
+    assert!(
+        hand.iter()
+            .any(|token| token.value == Some(Value::Str("λ = \\".to_string())))
+    );
+}
+
+#[test]
+fn invalid_escapes_make_the_tokens_subcommand_fail() {
+    let input = "string s = \"a\\q\";\nstring t = \"\\u{}\";\n";
+    let (status, stderr) = run_lex4oat(None, &["tokens", "--values"], input);
+    assert_eq!(status, Some(7));
+    assert!(stderr.contains("line 1, column 14: Escape sequence '\\q' is not supported"));
+    assert!(
+        stderr.contains("1 files, 10 tokens, 2 errors"),
+        "{}",
+        stderr
+    );
+}
@@ -0,0 +1,66 @@
+//! Tests for trailing context (`r/s`) and line anchors (`^`, `$`) in specification rules.
+//!
+//! Tokens must be cut before their trailing context, and rules anchored with `^` or `$` must
+//! only match at the start or end of a line. `tests/features.rs` checks that every backend
+//! and both DFA builders agree on them.
+
+mod common;
+
+use common::specs::{TRAILING_CONTEXT as SPEC, TRAILING_CONTEXT_INPUT as INPUT};
+use common::{kinds, owned};
+use lex4oat::lex4oat::CompiledLexer;
+use lex4oat::nfa::RuleRegex;
+use lex4oat::spec::{check_spec, hand_made_only_feature};
+
+#[test]
+fn tokens_are_cut_before_trailing_context() {
+    let tokens = kinds(CompiledLexer::from_spec(SPEC).unwrap().lex(INPUT));
+    let expected = [
+        ("DIRECTIVE", "#define"),
+        ("CALL", "f"),
+        ("LPAREN", "("),
+        ("ID", "y"),
+        ("NEG", "-"),
+        ("INT", "1"),
+        ("DASH", "-"),
+        ("INT", "2"),
+        ("HASH", "#"),
+        ("ID", "if"),
+        ("LAST", "foo"),
+        ("XS", "xx"),
+        ("ID", "x"),
+        ("ID", "end"),
+    ];
+    assert_eq!(tokens, owned(&expected));
+}
+
+#[test]
+fn rule_regexes_are_split() {
+    assert_eq!(
+        RuleRegex::parse("^a/b(c/d)$"),
+        RuleRegex {
+            line_start: true,
+            token: "a".to_string(),
+            context: Some("b(c/d)".to_string()),
+            line_end: true,
+        }
+    );
+    let plain = RuleRegex::parse("a\\/[/$]\\$");
+    assert!(!plain.line_start && !plain.has_trailing_context());
+    assert_eq!(plain.token, "a\\/[/$]\\$");
+}
+
+#[test]
+fn trailing_contexts_and_anchors_are_checked() {
+    assert_eq!(
+        hand_made_only_feature("%%\n^a \"A\"\n"),
+        Some("the line anchors '^' and '$'")
+    );
+
+    let errors = check_spec("%%\na/b/c \"A\"\n");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].message,
+        "Rule has more than one trailing context '/' (hand-made lexer)"
+    );
+}
@@ -0,0 +1,144 @@
+//! Tests for line comments, documentation comments and the trivia channel.
+//!
+//! Both lexers must cut the same `//` and `///` comments from `oat.l`, and splitting a token
+//! stream into channels must leave the parser only the main tokens while keeping every comment
+//! and all whitespace as trivia.
+
+mod common;
+
+use common::{hand_lex, kinds, lib_lex, lrlex_kinds, owned};
+use lex4oat::channel::{Channel, Channels, UNKNOWN_KIND, WHITESPACE_KIND, is_trivia};
+use lex4oat::lex4oat::CompiledLexer;
+
+/// An input with every kind of comment.
+const INPUT: &str =
+    "/// Doubles x.\nint f(int x) { // twice  \r\n  return x * 2; /* done */ }\n//// rule\n";
+
+#[test]
+fn line_comments_run_to_the_end_of_the_line() {
+    let tokens = hand_lex(INPUT);
+    let comments: Vec<(String, String)> = kinds(&tokens)
+        .into_iter()
+        .filter(|(kind, _)| is_trivia(kind))
+        .collect();
+    assert_eq!(
+        comments,
+        owned(&[
+            ("DOC_COMMENT", "/// Doubles x."),
+            ("COMMENT", "// twice  "),
+            ("COMMENT", "/* done */"),
+            ("COMMENT", "//// rule"),
+        ])
+    );
+    assert_eq!(lib_lex(INPUT), tokens);
+}
+
+#[test]
+fn parsers_see_only_main_tokens() {
+    let channels = Channels::split(INPUT, hand_lex(INPUT));
+    assert_eq!(
+        kinds(channels.main()),
+        owned(&[
+            ("INT", "int"),
+            ("ID", "f"),
+            ("LPAREN", "("),
+            ("INT", "int"),
+            ("ID", "x"),
+            ("RPAREN", ")"),
+            ("LBRACE", "{"),
+            ("RETURN", "return"),
+            ("ID", "x"),
+            ("TIMES", "*"),
+            ("INTLITERAL", "2"),
+            ("SEMICOLON", ";"),
+            ("RBRACE", "}"),
+        ])
+    );
+    assert!(channels.main().all(|token| !is_trivia(&token.kind)));
+    assert!(channels.trivia().all(|token| is_trivia(&token.kind)));
+}
+
+#[test]
+fn trivia_is_attached_to_the_next_main_token() {
+    let channels = Channels::split(INPUT, hand_lex(INPUT));
+    assert_eq!(
+        kinds(&channels.tokens[0].leading),
+        owned(&[("DOC_COMMENT", "/// Doubles x."), (WHITESPACE_KIND, "\n")])
+    );
+    assert_eq!(channels.tokens[0].token.lexeme, "int");
+    let ret = &channels.tokens[7];
+    assert_eq!(ret.token.lexeme, "return");
+    assert_eq!(
+        kinds(&ret.leading),
+        owned(&[
+            (WHITESPACE_KIND, " "),
+            ("COMMENT", "// twice  "),
+            (WHITESPACE_KIND, "\r\n  "),
+        ])
+    );
+    assert_eq!((ret.leading[2].line, ret.leading[2].column), (2, 26));
+    assert_eq!(
+        kinds(&channels.trailing),
+        owned(&[
+            (WHITESPACE_KIND, "\n"),
+            ("COMMENT", "//// rule"),
+            (WHITESPACE_KIND, "\n"),
+        ])
+    );
+}
+
+#[test]
+fn channels_cover_the_whole_input() {
+    for input in [INPUT, "", "  ", "x", "// only\n", "  a  /**/  b  "] {
+        let channels = Channels::split(input, hand_lex(input));
+        let mut text = String::new();
+        for token in &channels.tokens {
+            for trivia in &token.leading {
+                text.push_str(&trivia.lexeme);
+            }
+            text.push_str(&token.token.lexeme);
+        }
+        for trivia in &channels.trailing {
+            text.push_str(&trivia.lexeme);
+        }
+        assert_eq!(text, input);
+    }
+}
+
+#[test]
+fn unmatched_text_is_not_whitespace() {
+    let input = "a #$ b\t@";
+    let channels = Channels::split(input, hand_lex(input));
+    assert_eq!(
+        kinds(channels.trivia()),
+        owned(&[
+            (WHITESPACE_KIND, " "),
+            (UNKNOWN_KIND, "#$"),
+            (WHITESPACE_KIND, " "),
+            (WHITESPACE_KIND, "\t"),
+            (UNKNOWN_KIND, "@"),
+        ])
+    );
+}
+
+#[test]
+fn channels_are_selected() {
+    let tokens = hand_lex(INPUT);
+    assert_eq!(Channel::All.select(INPUT, tokens.clone()), tokens);
+    let main = Channel::Main.select(INPUT, tokens.clone());
+    assert_eq!(main.len(), 13);
+    let trivia = Channel::Trivia.select(INPUT, tokens);
+    assert_eq!(trivia.first().unwrap().kind, "DOC_COMMENT");
+    assert_eq!(trivia.last().unwrap().kind, WHITESPACE_KIND);
+    assert_eq!(trivia.len(), 17);
+}
+
+#[test]
+fn hand_made_escapes_and_optional_groups_match_lrlex() {
+    let spec = "%%\na(b[^c]*)? \"A\"\nx(yz+)? \"X\"\n[^\\n]\\t \"TAB\"\n[a-z]+ \"ID\"\n[ \\n] ;\n";
+    let lexer = CompiledLexer::from_spec(spec).unwrap();
+    for input in ["a abd ab a", "xz xyzz x", "n\t", "a\nx"] {
+        let tokens = kinds(lexer.lex(input));
+        assert_eq!(tokens, lrlex_kinds(spec, input), "{:?}", input);
+    }
+}